@@ -0,0 +1,494 @@
+//! Construction of proofs step by step.
+//!
+//! A `ProofConstructor` builds proofs from programs, such as scripts
+//! generating instances of schemes.  Each step either introduces an essential
+//! hypothesis, or applies an assertion of the database to earlier steps, with
+//! optional substitutions for its variables:
+//!
+//! ```text
+//! let mut proof = ProofConstructor::new(&db);
+//! let min = proof.hyp("a1i.1")?;
+//! let maj = proof.apply("ax-1", &[], &[("ph", "ph"), ("ps", "ps")])?;
+//! let qed = proof.apply("ax-mp", &[min, maj], &[])?;
+//! println!("{}", proof.render("a1i", qed, ProofStyle::Compressed)?);
+//! ```
+//!
+//! The conclusion of a step is computed by unifying the earlier steps with
+//! the essential hypotheses of the assertion, and substituting the result in
+//! its assertion.  The syntax proofs of the mandatory floating hypotheses are
+//! generated from the substitutions.
+//!
+//! The disjoint variable conditions of the assertions are checked in two
+//! stages: substituting the same variable for two variables which must be
+//! disjoint is rejected when the step is added, and the other pairs of
+//! variables which must be disjoint are checked against the `$d` statements
+//! of the theorem when the proof is rendered.
+
+use crate::diag::Diagnostic;
+use crate::formula::Substitutions;
+use crate::nameck::Atom;
+use crate::nameck::Nameset;
+use crate::parser::as_str;
+use crate::parser::StatementAddress;
+use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::parser::TokenPtr;
+use crate::proof::ProofStyle;
+use crate::proof::ProofTreeArray;
+use crate::proof::ProofTreePrinter;
+use crate::scopeck::Hyp;
+use crate::verify::ProofBuilder;
+use crate::Database;
+use crate::Formula;
+use std::fmt;
+
+/// A problem found while constructing a proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstructError {
+    /// The label does not exist.
+    UnknownLabel(String),
+    /// The label of an applied step is not a `$a` or `$p` statement.
+    NotAssertion(String),
+    /// The label of a hypothesis step is not a `$e` statement, or when
+    /// rendering, it is not a hypothesis of the theorem.
+    NotHypothesis(String),
+    /// The statement cannot be parsed with the grammar of the database.
+    NotParsed(String),
+    /// The step does not belong to this constructor.
+    UnknownStep(StepId),
+    /// The number of steps given differs from the number of essential
+    /// hypotheses of the assertion.
+    HypCount {
+        /// The number of essential hypotheses of the assertion
+        expected: usize,
+        /// The number of steps given
+        found: usize,
+    },
+    /// The step given for the hypothesis, counted from 1, does not match it
+    /// under the substitutions of the step.
+    HypothesisMismatch(usize),
+    /// A substitution is given for a token which is not a mandatory variable
+    /// of the assertion.
+    UnknownVariable(String),
+    /// A substitution uses a token which is not a math symbol.
+    UnknownSymbol(String),
+    /// A substitution cannot be parsed with the grammar of the database.
+    Parse(Diagnostic),
+    /// A mandatory variable of the assertion is not determined by the steps
+    /// given, and has no substitution.
+    UnboundVariable(String),
+    /// The given variables must be disjoint, and are not disjoint in the
+    /// theorem.
+    DvViolation(String, String),
+    /// The given variables of the assertion must be disjoint, but their
+    /// substitutions share the third variable.
+    DvSameVariable(String, String, String),
+    /// The rendered step does not prove the assertion of the theorem.
+    ConclusionMismatch,
+}
+
+impl fmt::Display for ConstructError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConstructError::UnknownLabel(ref label) => write!(f, "Unknown label {label}"),
+            ConstructError::NotAssertion(ref label) => {
+                write!(f, "{label} is not a $a or $p statement")
+            }
+            ConstructError::NotHypothesis(ref label) => {
+                write!(f, "{label} is not a hypothesis of the theorem")
+            }
+            ConstructError::NotParsed(ref label) => write!(f, "{label} was not parsed"),
+            ConstructError::UnknownStep(step) => write!(f, "Unknown step {}", step.0),
+            ConstructError::HypCount { expected, found } => {
+                write!(f, "Expected {expected} hypotheses, found {found}")
+            }
+            ConstructError::HypothesisMismatch(index) => {
+                write!(f, "Hypothesis {index} does not match the step given")
+            }
+            ConstructError::UnknownVariable(ref token) => {
+                write!(f, "{token} is not a mandatory variable of the assertion")
+            }
+            ConstructError::UnknownSymbol(ref token) => {
+                write!(f, "Unknown math symbol {token}")
+            }
+            ConstructError::Parse(ref diag) => {
+                write!(f, "Substitution does not parse: {diag:?}")
+            }
+            ConstructError::UnboundVariable(ref token) => {
+                write!(f, "No substitution for variable {token}")
+            }
+            ConstructError::DvViolation(ref var1, ref var2) => {
+                write!(f, "Variables {var1} and {var2} must be disjoint")
+            }
+            ConstructError::DvSameVariable(ref var1, ref var2, ref variable) => write!(
+                f,
+                "Variables {var1} and {var2} must be disjoint, but both contain {variable}"
+            ),
+            ConstructError::ConclusionMismatch => {
+                write!(f, "The step does not prove the assertion of the theorem")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConstructError {}
+
+/// The identifier of a step added to a [`ProofConstructor`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StepId(usize);
+
+/// A hypothesis of the assertion applied by a step.
+#[derive(Debug)]
+enum StepHyp {
+    /// A floating hypothesis, proved by the syntax proof of its substitution
+    Syntax(Formula),
+    /// An essential hypothesis, proved by an earlier step
+    Step(StepId),
+}
+
+/// A step of a constructed proof.
+#[derive(Debug)]
+struct Step {
+    address: StatementAddress,
+    formula: Formula,
+    /// The hypotheses of the assertion, in the order of its frame
+    hyps: Vec<StepHyp>,
+    /// The substitution of the variables of the assertion, empty for
+    /// hypotheses
+    substitutions: Substitutions,
+}
+
+/// Builds a proof step by step.  See the [module documentation](crate::construct).
+///
+/// Requires: [`Database::name_pass`], [`Database::scope_pass`],
+/// [`Database::grammar_pass`], [`Database::stmt_parse_pass`]
+#[derive(Debug)]
+pub struct ProofConstructor<'a> {
+    db: &'a Database,
+    steps: Vec<Step>,
+}
+
+impl<'a> ProofConstructor<'a> {
+    /// Starts a proof with no steps.
+    #[must_use]
+    pub const fn new(db: &'a Database) -> Self {
+        ProofConstructor { db, steps: vec![] }
+    }
+
+    /// Returns the conclusion of a step.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the step does not belong to this constructor.
+    #[must_use]
+    pub fn formula(&self, step: StepId) -> &Formula {
+        &self.steps[step.0].formula
+    }
+
+    /// Returns the statement applied by a step.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the step does not belong to this constructor.
+    #[must_use]
+    pub fn statement(&self, step: StepId) -> StatementRef<'a> {
+        self.db.parse_result().statement(self.steps[step.0].address)
+    }
+
+    /// Adds a step for an essential hypothesis.
+    pub fn hyp(&mut self, label: &str) -> Result<StepId, ConstructError> {
+        let sref = self
+            .db
+            .statement(label)
+            .ok_or_else(|| ConstructError::UnknownLabel(label.to_owned()))?;
+        if sref.statement_type() != StatementType::Essential {
+            return Err(ConstructError::NotHypothesis(label.to_owned()));
+        }
+        let formula = self
+            .db
+            .stmt_parse_result()
+            .get_formula(&sref)
+            .ok_or_else(|| ConstructError::NotParsed(label.to_owned()))?;
+        Ok(self.push(Step {
+            address: sref.address(),
+            formula: formula.clone(),
+            hyps: vec![],
+            substitutions: Substitutions::default(),
+        }))
+    }
+
+    /// Adds a step applying an assertion to earlier steps, given for its
+    /// essential hypotheses in order.  The variables which are not
+    /// determined by these steps must be given a substitution, as a pair of
+    /// the variable and the math string substituted for it, without
+    /// typecode; other substitutions are optional.
+    pub fn apply(
+        &mut self,
+        label: &str,
+        hyps: &[StepId],
+        substitutions: &[(&str, &str)],
+    ) -> Result<StepId, ConstructError> {
+        let db = self.db;
+        let nset = db.name_result();
+        let sset = db.parse_result();
+        let stmt_parse = db.stmt_parse_result();
+        let sref = db
+            .statement(label)
+            .ok_or_else(|| ConstructError::UnknownLabel(label.to_owned()))?;
+        if !matches!(
+            sref.statement_type(),
+            StatementType::Axiom | StatementType::Provable
+        ) {
+            return Err(ConstructError::NotAssertion(label.to_owned()));
+        }
+        let assertion = stmt_parse
+            .get_formula(&sref)
+            .ok_or_else(|| ConstructError::NotParsed(label.to_owned()))?;
+        let frame = db
+            .scope_result()
+            .get(sref.label())
+            .ok_or_else(|| ConstructError::NotAssertion(label.to_owned()))?;
+        for &hyp in hyps {
+            if hyp.0 >= self.steps.len() {
+                return Err(ConstructError::UnknownStep(hyp));
+            }
+        }
+        let essential_count = frame
+            .hypotheses
+            .iter()
+            .filter(|hyp| matches!(hyp, Hyp::Essential(..)))
+            .count();
+        if essential_count != hyps.len() {
+            return Err(ConstructError::HypCount {
+                expected: essential_count,
+                found: hyps.len(),
+            });
+        }
+
+        // the variables of the assertion, as their floating hypotheses
+        let floating: Vec<StatementRef<'_>> = frame
+            .floating()
+            .map(|address| sset.statement(address))
+            .collect();
+        let mut subst = Substitutions::default();
+        for &(variable, math) in substitutions {
+            let float = floating
+                .iter()
+                .find(|float| float.math_at(1).slice == variable.as_bytes())
+                .ok_or_else(|| ConstructError::UnknownVariable(variable.to_owned()))?;
+            let mut symbols = Vec::new();
+            for token in math.split_whitespace() {
+                let symbol = nset
+                    .lookup_symbol(token.as_bytes())
+                    .ok_or_else(|| ConstructError::UnknownSymbol(token.to_owned()))?;
+                symbols.push(symbol.atom);
+            }
+            let typecode = nset.get_atom(float.math_at(0).slice);
+            let formula = db
+                .grammar_result()
+                .parse_math_string(typecode, &mut symbols.into_iter(), nset)
+                .map_err(ConstructError::Parse)?;
+            subst.insert(label_atom(nset, float.label()), formula);
+        }
+
+        // unify the steps with the essential hypotheses
+        let mut steps = hyps.iter();
+        let mut index = 0;
+        for hyp in &*frame.hypotheses {
+            if let Hyp::Essential(address, _) = *hyp {
+                let step = *steps.next().unwrap();
+                index += 1;
+                let essential = stmt_parse
+                    .get_formula(&sset.statement(address))
+                    .ok_or_else(|| ConstructError::NotParsed(label.to_owned()))?;
+                if !self.steps[step.0].formula.unify_with(essential, &mut subst) {
+                    return Err(ConstructError::HypothesisMismatch(index));
+                }
+            }
+        }
+
+        // collect the hypotheses in frame order
+        let mut steps = hyps.iter();
+        let mut step_hyps = Vec::with_capacity(frame.hypotheses.len());
+        for hyp in &*frame.hypotheses {
+            step_hyps.push(match *hyp {
+                Hyp::Floating(address, ..) => {
+                    let float = sset.statement(address);
+                    let formula = subst.get(label_atom(nset, float.label())).ok_or_else(|| {
+                        ConstructError::UnboundVariable(as_str(float.math_at(1).slice).to_owned())
+                    })?;
+                    StepHyp::Syntax(formula.clone())
+                }
+                Hyp::Essential(..) => StepHyp::Step(*steps.next().unwrap()),
+            });
+        }
+
+        // the variables substituted for variables which must be disjoint
+        // must be distinct; whether they are disjoint depends on the theorem
+        // being proved, and is checked by `render`
+        let report = db.check_dv(frame, &subst, frame);
+        if let Some(violation) = report.violations.first() {
+            let (var1, var2) = violation.mandatory;
+            return Err(ConstructError::DvSameVariable(
+                as_str(nset.atom_name(var1)).to_owned(),
+                as_str(nset.atom_name(var2)).to_owned(),
+                as_str(nset.atom_name(violation.variable)).to_owned(),
+            ));
+        }
+
+        let formula = assertion.substitute(&subst);
+        Ok(self.push(Step {
+            address: sref.address(),
+            formula,
+            hyps: step_hyps,
+            substitutions: subst,
+        }))
+    }
+
+    /// Returns the proof of a step, as a proof tree array whose `qed` step is
+    /// the given step.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the step does not belong to this constructor.
+    #[must_use]
+    pub fn proof_tree(&self, step: StepId) -> ProofTreeArray {
+        let mut arr = ProofTreeArray::default();
+        let mut built = vec![None; self.steps.len()];
+        let mut stack_buffer = Vec::new();
+        arr.qed = self.build(step, &mut built, &mut stack_buffer, &mut arr);
+        arr.calc_indent();
+        arr
+    }
+
+    /// Checks that a step proves the assertion of a `$p` statement from its
+    /// hypotheses, and respects its disjoint variable conditions, and renders
+    /// its proof in the given style, laid out as by [`Database::show_proof`].
+    pub fn render(
+        &self,
+        theorem: &str,
+        step: StepId,
+        style: ProofStyle,
+    ) -> Result<String, ConstructError> {
+        if step.0 >= self.steps.len() {
+            return Err(ConstructError::UnknownStep(step));
+        }
+        let db = self.db;
+        let sref = db
+            .statement(theorem)
+            .filter(|sref| sref.statement_type() == StatementType::Provable)
+            .ok_or_else(|| ConstructError::UnknownLabel(theorem.to_owned()))?;
+        let frame = db
+            .scope_result()
+            .get(sref.label())
+            .ok_or_else(|| ConstructError::UnknownLabel(theorem.to_owned()))?;
+        let assertion = db
+            .stmt_parse_result()
+            .get_formula(&sref)
+            .ok_or_else(|| ConstructError::NotParsed(theorem.to_owned()))?;
+        if *assertion != self.steps[step.0].formula {
+            return Err(ConstructError::ConclusionMismatch);
+        }
+
+        let sset = db.parse_result();
+        let nset = db.name_result();
+        let mut used = vec![false; self.steps.len()];
+        self.mark_used(step, &mut used);
+        for (index, _) in used.iter().enumerate().filter(|(_, &used)| used) {
+            let step = &self.steps[index];
+            let stmt = sset.statement(step.address);
+            if stmt.statement_type() == StatementType::Essential
+                && !frame
+                    .hypotheses
+                    .iter()
+                    .any(|hyp| hyp.address() == step.address)
+            {
+                return Err(ConstructError::NotHypothesis(
+                    as_str(stmt.label()).to_owned(),
+                ));
+            }
+            if let Some(step_frame) = db.scope_result().get(stmt.label()) {
+                let report = db.check_dv(step_frame, &step.substitutions, frame);
+                if let Some(&(var1, var2)) = report.missing.first() {
+                    return Err(ConstructError::DvViolation(
+                        as_str(nset.atom_name(var1)).to_owned(),
+                        as_str(nset.atom_name(var2)).to_owned(),
+                    ));
+                }
+            }
+        }
+
+        let arr = self.proof_tree(step);
+        let printer = ProofTreePrinter {
+            sset,
+            nset,
+            scope: db.scope_result(),
+            thm_label: sref.label(),
+            style,
+            arr: &arr,
+            initial_chr: 0,
+            indent: 2,
+            line_width: 79,
+        };
+        Ok(printer.to_string())
+    }
+
+    /// Adds a step, and returns its identifier.
+    fn push(&mut self, step: Step) -> StepId {
+        self.steps.push(step);
+        StepId(self.steps.len() - 1)
+    }
+
+    /// Marks the steps used in the proof of a step.
+    fn mark_used(&self, step: StepId, used: &mut [bool]) {
+        if used[step.0] {
+            return;
+        }
+        used[step.0] = true;
+        for hyp in &self.steps[step.0].hyps {
+            if let StepHyp::Step(hyp) = *hyp {
+                self.mark_used(hyp, used);
+            }
+        }
+    }
+
+    /// Adds the proof of a step to a proof tree array, and returns its index.
+    fn build(
+        &self,
+        step: StepId,
+        built: &mut [Option<usize>],
+        stack_buffer: &mut Vec<u8>,
+        arr: &mut ProofTreeArray,
+    ) -> usize {
+        if let Some(ix) = built[step.0] {
+            return ix;
+        }
+        let hyps = self.steps[step.0]
+            .hyps
+            .iter()
+            .map(|hyp| match *hyp {
+                // substitutions are parsed from math symbols of the
+                // database, which are never work variables
+                StepHyp::Syntax(ref formula) => formula
+                    .as_ref(self.db)
+                    .build_syntax_proof(stack_buffer, arr)
+                    .expect("substitutions have no work variables"),
+                StepHyp::Step(hyp) => self.build(hyp, built, stack_buffer, arr),
+            })
+            .collect();
+        let step_data = &self.steps[step.0];
+        let range = step_data
+            .formula
+            .as_ref(self.db)
+            .append_to_stack_buffer(stack_buffer);
+        let ix = arr.build(step_data.address, hyps, stack_buffer, range);
+        built[step.0] = Some(ix);
+        ix
+    }
+}
+
+/// Returns the atom of an existing label.
+fn label_atom(nset: &Nameset, label: TokenPtr<'_>) -> Atom {
+    nset.lookup_label(label)
+        .map_or_else(Atom::default, |lookup| lookup.atom)
+}
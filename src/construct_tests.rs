@@ -0,0 +1,148 @@
+use crate::construct::ConstructError;
+use crate::construct::ProofConstructor;
+use crate::grammar_tests::mkdb;
+use crate::proof::ProofStyle;
+use crate::show::ProofLayout;
+use crate::worksheet_tests::worksheet_db;
+use crate::Database;
+
+#[test]
+fn test_construct_proof() {
+    let db = worksheet_db();
+    let mut proof = ProofConstructor::new(&db);
+    let min = proof.hyp("a1i.1").unwrap();
+    let maj = proof
+        .apply("ax-1", &[], &[("ph", "ph"), ("ps", "ps")])
+        .unwrap();
+    assert_eq!(
+        proof.formula(maj).as_ref(&db).to_string(),
+        "wff ( ph -> ( ps -> ph ) )"
+    );
+    let qed = proof.apply("ax-mp", &[min, maj], &[]).unwrap();
+    assert_eq!(
+        proof.formula(qed).as_ref(&db).to_string(),
+        "wff ( ps -> ph )"
+    );
+    assert_eq!(proof.statement(qed).label(), b"ax-mp");
+
+    // the proof is the one of the database, in every style
+    for style in [
+        ProofStyle::Compressed,
+        ProofStyle::Normal,
+        ProofStyle::Explicit,
+    ] {
+        let mut out = Vec::new();
+        db.show_proof(
+            db.statement("a1i").unwrap(),
+            ProofLayout::Style(style),
+            false,
+            &mut out,
+        )
+        .unwrap();
+        let expected = String::from_utf8(out).unwrap();
+        assert_eq!(
+            proof.render("a1i", qed, style).unwrap(),
+            expected.trim_end()
+        );
+    }
+    assert!(matches!(
+        proof.render("a1i", maj, ProofStyle::Compressed),
+        Err(ConstructError::ConclusionMismatch)
+    ));
+}
+
+#[test]
+fn test_construct_errors() {
+    let db = worksheet_db();
+    let mut proof = ProofConstructor::new(&db);
+    let min = proof.hyp("a1i.1").unwrap();
+    let error = |result: Result<_, ConstructError>| result.unwrap_err().to_string();
+    assert_eq!(
+        error(proof.hyp("ax-1")),
+        "ax-1 is not a hypothesis of the theorem"
+    );
+    assert_eq!(
+        error(proof.apply("ax-mp", &[min], &[])),
+        "Expected 2 hypotheses, found 1"
+    );
+    assert_eq!(
+        error(proof.apply("ax-1", &[], &[("ph", "ph")])),
+        "No substitution for variable ps"
+    );
+    assert_eq!(
+        error(proof.apply("ax-1", &[], &[("ch", "ph")])),
+        "ch is not a mandatory variable of the assertion"
+    );
+    assert_eq!(
+        error(proof.apply("ax-1", &[], &[("ph", "( ph -> x )")])),
+        "Unknown math symbol x"
+    );
+    assert!(error(proof.apply("ax-1", &[], &[("ph", "( ph ->")])).starts_with("Substitution"));
+    assert_eq!(
+        error(proof.apply("ax-mp", &[min, min], &[])),
+        "Hypothesis 2 does not match the step given"
+    );
+    assert_eq!(error(proof.apply("nope", &[], &[])), "Unknown label nope");
+}
+
+pub(super) const DV_DB: &[u8] = b"
+    $c |- wff setvar A. ( ) -> $.
+    $( $j syntax 'wff'; syntax 'setvar'; syntax '|-' as 'wff'; $)
+    $v ph ps x y $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    vx $f setvar x $.
+    vy $f setvar y $.
+    wi $a wff ( ph -> ps ) $.
+    wal $a wff A. x ph $.
+    ${
+        $d x ph $.
+        ax-5 $a |- ( ph -> A. x ph ) $.
+    $}
+    ${
+        $d x y $.
+        th1 $p |- ( A. y ph -> A. x A. y ph ) $= ? $.
+    $}
+    ${
+        $d x y $. $d x ph $.
+        th2 $p |- ( A. y ph -> A. x A. y ph ) $= ? $.
+    $}
+";
+
+pub(super) fn dv_db() -> Database {
+    let mut db = mkdb(DV_DB);
+    db.scope_pass();
+    db.stmt_parse_pass();
+    db
+}
+
+#[test]
+fn test_construct_dv() {
+    let db = dv_db();
+    let mut proof = ProofConstructor::new(&db);
+    let step = proof
+        .apply("ax-5", &[], &[("ph", "A. y ph"), ("x", "x")])
+        .unwrap();
+    assert_eq!(
+        proof
+            .render("th1", step, ProofStyle::Normal)
+            .unwrap_err()
+            .to_string(),
+        "Variables ph and x must be disjoint"
+    );
+    assert_eq!(
+        proof.render("th2", step, ProofStyle::Normal).unwrap(),
+        "  wph vy wal vx ax-5 $."
+    );
+    let err = proof
+        .apply("ax-5", &[], &[("ph", "A. x ph"), ("x", "x")])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ConstructError::DvSameVariable("x".to_owned(), "ph".to_owned(), "x".to_owned())
+    );
+    assert_eq!(
+        err.to_string(),
+        "Variables x and ph must be disjoint, but both contain x"
+    );
+}
@@ -7,11 +7,13 @@
 //! data; the implementation expects there to be minor changes, and optimizes
 //! with incremental recomputation.
 //!
-//! It is also possible to modify a loaded database by opening it (details TBD);
-//! while the database is open most analyses cannot be used, but it is permitted
-//! to call `Clone::clone` on a `Database` and the type is designed to make that
-//! relatively efficient (currently requires the duplication of three large hash
-//! tables, this can be optimized).
+//! It is also possible to modify individual statements of a loaded database in
+//! memory, using the methods in the `edit` module; only the affected segment is
+//! reparsed.  It is permitted to call `Clone::clone` on a `Database`, for
+//! instance to try an edit and discard it, and the type is designed to make that
+//! efficient: the segment set and the pass results are shared, and the large
+//! indexes inside them are structurally shared persistent maps, so a clone
+//! costs O(1) and later incremental updates only copy what they change.
 //!
 //! ## On segmentation
 //!
@@ -87,15 +89,23 @@
 //!
 //! ## Parallelism and promises
 //!
-//! The current parallel processing implementation is fairly simplistic.  If you
-//! want to run a number of code fragments in parallel, get a reference to the
-//! `Executor` object for the current database, then use it to queue a closure
-//! for each task you want to run; the queueing step returns a `Promise` object
-//! which can be used to wait for the task to complete.  Generally you want to
-//! queue everything, then wait for everything.
+//! If you want to run a number of code fragments in parallel, get a reference
+//! to the `Executor` object for the current database, then use it to queue a
+//! closure for each task you want to run; the queueing step returns a `Promise`
+//! object which can be used to wait for the task to complete.  Generally you
+//! want to queue everything, then wait for everything.  Queued tasks may queue
+//! and wait for tasks of their own.
 //!
 //! To improve packing efficiency, jobs are dispatched in descending order of
 //! estimated runtime.  This requires an additional argument when queueing.
+//! Passes which work segment by segment use `Executor::exec_segment`, which
+//! remembers how long each segment took and uses that as the estimate on the
+//! next run.
+//!
+//! The executor is a work-stealing thread pool: each worker prefers the jobs
+//! it queued itself, and takes work from the others when it runs out.  An
+//! executor can be shared by several databases, in which case their passes
+//! compete for the same threads.
 
 use crate::diag;
 use crate::diag::DiagnosticClass;
@@ -105,31 +115,48 @@ use crate::formula::Label;
 use crate::grammar;
 use crate::grammar::Grammar;
 use crate::grammar::StmtParse;
+use crate::hygiene;
+use crate::hygiene::HygieneOptions;
+use crate::hygiene::HygieneResult;
 use crate::nameck::Nameset;
 use crate::outline::OutlineNode;
+use crate::parser::SegmentId;
+use crate::parser::StatementAddress;
 use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::profile::ProofProfile;
+use crate::progress::Progress;
+use crate::progress::ProgressObserver;
 use crate::scopeck;
 use crate::scopeck::ScopeResult;
 use crate::segment_set::SegmentSet;
+use crate::snapshot::Snapshot;
+use crate::util::HashMap;
 use crate::verify;
 use crate::verify::VerifyResult;
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::collections::VecDeque;
 use std::fmt;
 use std::fmt::Debug;
 use std::fs::File;
 use std::panic;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering as AtomicOrdering;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
 use std::thread;
+use std::time::Duration;
 use std::time::Instant;
 
 /// Structure for options that affect database processing, and must be constant
 /// for the lifetime of the database container.
 ///
 /// Some of these could theoretically support modification.
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct DbOptions {
     /// If true, the automatic splitting of large files described above is
     /// enabled, with the caveat about chapter comments inside grouping
@@ -141,6 +168,9 @@ pub struct DbOptions {
     /// True to print names (determined by a very simple heuristic, see
     /// `parser::guess_buffer_name`) of segments which are recalculated in each
     /// pass.
+    ///
+    /// For a machine-readable version of this and of `timing`, see
+    /// `Database::set_progress_observer`.
     pub trace_recalc: bool,
     /// True to record detailed usage data needed for incremental operation.
     ///
@@ -151,6 +181,13 @@ pub struct DbOptions {
     pub incremental: bool,
     /// Number of jobs to run in parallel at any given time.
     pub jobs: usize,
+    /// Checks made by the source hygiene pass, see `Database::hygiene_pass`.
+    pub hygiene: HygieneOptions,
+    /// The typecode of the logical steps of proofs, such as `|-`, used where
+    /// syntax steps are told apart from logical ones, as in mmj2 worksheets.
+    /// If `None`, the typecode declared by a `$j syntax ... as ...;` command
+    /// is used, and otherwise `|-`.
+    pub provable_typecode: Option<String>,
 }
 
 impl Default for DbOptions {
@@ -161,13 +198,26 @@ impl Default for DbOptions {
             trace_recalc: false,
             incremental: false,
             jobs: 1,
+            hygiene: HygieneOptions::default(),
+            provable_typecode: None,
         }
     }
 }
 
+/// Rough run time per byte of input, in nanoseconds, for jobs whose cost has
+/// not been measured yet.
+const DEFAULT_NANOS_PER_BYTE: usize = 50;
+
+/// Converts the size in bytes of the input of a job into an estimate of its
+/// run time in nanoseconds, the unit of the estimates of `Executor::exec`.
+#[must_use]
+pub(crate) const fn estimate_bytes(bytes: usize) -> usize {
+    bytes.saturating_mul(DEFAULT_NANOS_PER_BYTE)
+}
+
 /// Wraps a heap-allocated closure with a difficulty score which can be used for
 /// sorting; this might belong in the standard library as `CompareFirst` or such.
-struct Job(usize, Box<dyn FnMut() + Send>);
+struct Job(usize, Box<dyn FnOnce() + Send>);
 impl PartialEq for Job {
     fn eq(&self, other: &Job) -> bool {
         self.0 == other.0
@@ -176,7 +226,7 @@ impl PartialEq for Job {
 impl Eq for Job {}
 impl PartialOrd for Job {
     fn partial_cmp(&self, other: &Job) -> Option<Ordering> {
-        Some(self.0.cmp(&other.0))
+        Some(self.cmp(other))
     }
 }
 impl Ord for Job {
@@ -185,77 +235,215 @@ impl Ord for Job {
     }
 }
 
+/// Measured run times, used to refine the estimates of jobs which are
+/// submitted again in a later run of the same pass.
+#[derive(Default)]
+struct CostModel {
+    /// Nanoseconds taken by recent runs of a given pass on a given segment.
+    by_segment: HashMap<(&'static str, SegmentId), u64>,
+    /// Total nanoseconds and bytes for each pass, to estimate segments which
+    /// have not been seen yet.
+    by_pass: HashMap<&'static str, (u64, u64)>,
+}
+
+/// The state shared by all handles to a thread pool and by its workers.
+///
+/// Each worker has its own deque of jobs.  Jobs queued from a worker go on
+/// the back of its deque and are taken from there first, so nested jobs run
+/// depth-first on the thread which created them unless another worker runs
+/// out of work and steals them from the front.  Jobs queued from any other
+/// thread go in a shared heap and are dispatched biggest first.
+struct Pool {
+    /// Distinguishes the workers of this pool from those of other pools.
+    id: usize,
+    injector: Mutex<BinaryHeap<Job>>,
+    locals: Vec<Mutex<VecDeque<Job>>>,
+    /// Number of queued jobs; also the lock used by idle workers to sleep.
+    queued: Mutex<usize>,
+    work_cv: Condvar,
+    /// Signalled under the `queued` lock when a job is queued or finishes,
+    /// for the workers waiting on a promise.
+    change_cv: Condvar,
+    shutdown: AtomicBool,
+    costs: Mutex<CostModel>,
+}
+
+thread_local! {
+    /// Identifies the pool and worker index if the current thread is a worker.
+    static WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Source of `Pool::id` values.
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Pool {
+    /// Returns the worker index of the current thread, if it belongs to this
+    /// pool.
+    fn current_worker(&self) -> Option<usize> {
+        WORKER
+            .with(Cell::get)
+            .filter(|&(pool, _)| pool == self.id)
+            .map(|(_, index)| index)
+    }
+
+    fn push(&self, job: Job) {
+        // count the job before it can be taken, so that `find_job` never
+        // decrements the count below zero
+        let mut queued = self.queued.lock().unwrap();
+        *queued += 1;
+        match self.current_worker() {
+            Some(index) => self.locals[index].lock().unwrap().push_back(job),
+            None => self.injector.lock().unwrap().push(job),
+        }
+        drop(queued);
+        self.work_cv.notify_one();
+        self.change_cv.notify_all();
+    }
+
+    /// Runs a job taken from the queue, and wakes the workers waiting on a
+    /// promise, which may be the one of this job.
+    fn run(&self, job: Job) {
+        (job.1)();
+        let _queued = self.queued.lock().unwrap();
+        self.change_cv.notify_all();
+    }
+
+    /// Takes a job: from our own deque, then from the shared heap, then from
+    /// the other workers.
+    fn find_job(&self, me: Option<usize>) -> Option<Job> {
+        let own = me.and_then(|index| self.locals[index].lock().unwrap().pop_back());
+        let job = own
+            .or_else(|| self.injector.lock().unwrap().pop())
+            .or_else(|| {
+                let start = me.map_or(0, |index| index + 1);
+                (0..self.locals.len())
+                    .map(|offset| (start + offset) % self.locals.len())
+                    .filter(|&index| Some(index) != me)
+                    .find_map(|index| self.locals[index].lock().unwrap().pop_front())
+            })?;
+        *self.queued.lock().unwrap() -= 1;
+        Some(job)
+    }
+
+    fn work_loop(&self, index: usize) {
+        WORKER.with(|worker| worker.set(Some((self.id, index))));
+        loop {
+            if let Some(job) = self.find_job(Some(index)) {
+                self.run(job);
+                continue;
+            }
+            let mut queued = self.queued.lock().unwrap();
+            while *queued == 0 {
+                if self.shutdown.load(AtomicOrdering::SeqCst) {
+                    return;
+                }
+                queued = self.work_cv.wait(queued).unwrap();
+            }
+            drop(queued);
+        }
+    }
+
+    fn estimate(&self, pass: &'static str, id: SegmentId, bytes: usize) -> usize {
+        let costs = self.costs.lock().unwrap();
+        let known = costs.by_segment.get(&(pass, id)).copied();
+        let totals = costs.by_pass.get(pass).copied();
+        drop(costs);
+        match (known, totals) {
+            (Some(nanos), _) => nanos as usize,
+            (None, Some((nanos, total))) if total > 0 => (bytes as u64 * nanos / total) as usize,
+            _ => estimate_bytes(bytes),
+        }
+    }
+
+    fn record(&self, pass: &'static str, id: SegmentId, bytes: usize, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        let mut costs = self.costs.lock().unwrap();
+        // a segment whose previous result was reused finishes almost
+        // immediately; only let that pull the estimate down gradually, since
+        // the next change may well require a full recalculation again
+        let slot = costs.by_segment.entry((pass, id)).or_default();
+        *slot = nanos.max(*slot / 2);
+        let totals = costs.by_pass.entry(pass).or_default();
+        totals.0 += nanos;
+        totals.1 += bytes as u64;
+    }
+}
+
+/// Stops the workers when the last `Executor` handle to a pool is dropped.
+struct PoolHandle(Arc<Pool>);
+
+impl Drop for PoolHandle {
+    fn drop(&mut self) {
+        let _queued = self.0.queued.lock().unwrap();
+        self.0.shutdown.store(true, AtomicOrdering::SeqCst);
+        self.0.work_cv.notify_all();
+    }
+}
+
 /// Object which holds the state of the work queue and allows queueing tasks to
 /// run on the thread pool.
+///
+/// Cloning an `Executor` gives another handle to the same pool, which is how a
+/// pool can be shared between several databases (see
+/// `Database::new_with_executor`).
 #[derive(Clone)]
 pub struct Executor {
     concurrency: usize,
-    // Jobs are kept in a heap so that we can dispatch the biggest one first.
-    mutex: Arc<Mutex<BinaryHeap<Job>>>,
-    // Condvar used to notify work threads of new work.
-    work_cv: Arc<Condvar>,
+    handle: Arc<PoolHandle>,
 }
 
 /// Debug printing for `Executor` displays the current count of queued but not
 /// dispatched tasks.
 impl fmt::Debug for Executor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let g = self.mutex.lock().unwrap();
-        write!(f, "Executor(active={})", g.len())
-    }
-}
-
-fn queue_work(exec: &Executor, estimate: usize, mut f: Box<dyn FnMut() + Send>) {
-    if exec.concurrency <= 1 {
-        f();
-        return;
+        let queued = *self.handle.0.queued.lock().unwrap();
+        write!(f, "Executor(active={queued})")
     }
-    let mut wq = exec.mutex.lock().unwrap();
-    wq.push(Job(estimate, f));
-    exec.work_cv.notify_one();
 }
 
 impl Executor {
     /// Instantiates a new work queue and creates the threads to service it.
     ///
-    /// The threads will exit when the `Executor` goes out of scope (not yet
-    /// implemented).  In the future, we *may* have process-level coordination
-    /// to allow different `Executor`s to share a thread pool, and use per-job
-    /// concurrency limits.
+    /// The threads will exit when the last clone of the `Executor` goes out of
+    /// scope, after finishing any jobs which are still queued.
     #[must_use]
     pub fn new(concurrency: usize) -> Executor {
-        let mutex = Arc::new(Mutex::new(BinaryHeap::new()));
-        let cv = Arc::new(Condvar::new());
-
-        if concurrency > 1 {
-            for _ in 0..concurrency {
-                let mutex = mutex.clone();
-                let cv = cv.clone();
-                thread::spawn(move || loop {
-                    let mut task: Job = {
-                        let mut mutexg = mutex.lock().unwrap();
-                        while mutexg.is_empty() {
-                            mutexg = cv.wait(mutexg).unwrap();
-                        }
-                        mutexg.pop().unwrap()
-                    };
-                    (task.1)();
-                });
-            }
+        let workers = if concurrency > 1 { concurrency } else { 0 };
+        let pool = Arc::new(Pool {
+            id: NEXT_POOL_ID.fetch_add(1, AtomicOrdering::Relaxed),
+            injector: Mutex::new(BinaryHeap::new()),
+            locals: (0..workers).map(|_| Mutex::default()).collect(),
+            queued: Mutex::new(0),
+            work_cv: Condvar::new(),
+            change_cv: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            costs: Mutex::default(),
+        });
+
+        for index in 0..workers {
+            let pool = pool.clone();
+            thread::Builder::new()
+                .name(format!("worker {index}"))
+                .spawn(move || pool.work_loop(index))
+                .expect("failed to spawn worker thread");
         }
 
         Executor {
             concurrency,
-            mutex,
-            work_cv: cv,
+            handle: Arc::new(PoolHandle(pool)),
         }
     }
 
     /// Queue a job on this work queue.
     ///
-    /// The estimate is meaningless in isolation but jobs with a higher estimate
-    /// will be dispatched first, so it should be comparable among jobs that
-    /// could simultaneously be in the work queue.
+    /// The estimate is the expected run time of the job in nanoseconds.  Jobs
+    /// with a higher estimate will be dispatched first, so every job uses this
+    /// unit, including those which only know the size of their input and
+    /// convert it at a default rate.
+    ///
+    /// The job may itself queue further jobs and wait for them; while a worker
+    /// is waiting on a promise it runs other queued jobs, so this cannot
+    /// deadlock the pool.
     ///
     /// Returns a `Promise` that can be used to wait for completion of the
     /// queued work.  If the provided task panics, the error will be stored and
@@ -265,28 +453,76 @@ impl Executor {
         TASK: FnOnce() -> RV + Send + 'static,
         RV: Send + 'static,
     {
+        if self.concurrency <= 1 {
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(task));
+            return Promise::new_once(move || result.unwrap_or_else(|e| panic::resume_unwind(e)));
+        }
+
         let parts = Arc::new((Mutex::new(None), Condvar::new()));
 
         let partsc = parts.clone();
-        let mut task_o = Some(task);
-        queue_work(
-            self,
+        self.handle.0.push(Job(
             estimate,
             Box::new(move || {
-                let mut g = partsc.0.lock().unwrap();
-                let task_f =
-                    panic::AssertUnwindSafe(task_o.take().expect("should only be called once"));
-                *g = Some(panic::catch_unwind(task_f));
-                partsc.1.notify_one();
+                let result = panic::catch_unwind(panic::AssertUnwindSafe(task));
+                *partsc.0.lock().unwrap() = Some(result);
+                partsc.1.notify_all();
             }),
-        );
+        ));
 
-        Promise::new_once(move || {
-            let mut g = parts.0.lock().unwrap();
-            while g.is_none() {
-                g = parts.1.wait(g).unwrap();
+        let pool = self.handle.0.clone();
+        Promise::new_once(move || loop {
+            let done = parts.0.lock().unwrap().take();
+            if let Some(result) = done {
+                return result.unwrap_or_else(|e| panic::resume_unwind(e));
+            }
+            if let Some(index) = pool.current_worker() {
+                // help out instead of blocking a worker thread
+                if let Some(job) = pool.find_job(Some(index)) {
+                    pool.run(job);
+                } else {
+                    // checking the result under the `queued` lock means that
+                    // the end of the job cannot be missed
+                    let queued = pool.queued.lock().unwrap();
+                    if *queued == 0 && parts.0.lock().unwrap().is_none() {
+                        drop(pool.change_cv.wait(queued).unwrap());
+                    }
+                }
+            } else {
+                let mut g = parts.0.lock().unwrap();
+                while g.is_none() {
+                    g = parts.1.wait(g).unwrap();
+                }
             }
-            g.take().unwrap().unwrap()
+        })
+    }
+
+    /// Queue a job which processes one segment for an analysis pass.
+    ///
+    /// The first time a pass sees a segment, its estimate is based on the
+    /// segment's size in bytes and the speed of the pass so far; the time
+    /// taken is then measured, and used as the estimate the next time the same
+    /// pass runs on the same segment.  All
+    /// jobs queued through this method use nanoseconds as their estimate, so
+    /// they can be ranked against each other across passes.
+    pub fn exec_segment<TASK, RV>(
+        &self,
+        pass: &'static str,
+        id: SegmentId,
+        bytes: usize,
+        task: TASK,
+    ) -> Promise<RV>
+    where
+        TASK: FnOnce() -> RV + Send + 'static,
+        RV: Send + 'static,
+    {
+        let pool = self.handle.0.clone();
+        let estimate = pool.estimate(pass, id, bytes);
+        self.exec(estimate, move || {
+            let start = Instant::now();
+            let result = task();
+            pool.record(pass, id, bytes, start.elapsed());
+            result
         })
     }
 }
@@ -369,11 +605,11 @@ impl<T> Promise<T> {
 /// until it is necessary to run an analysis pass on one clone or the other;
 /// then if the analysis pass has a result index which is normally updated in
 /// place, such as the hash table of statement labels constructed by nameck,
-/// that table must be duplicated so that it can be updated for one database
-/// without affecting the other.
-#[derive(Debug)]
+/// that index must be detached so that it can be updated for one database
+/// without affecting the other.  These indexes are persistent maps, so
+/// detaching them is O(1), and the update only copies the entries it touches.
+#[derive(Clone, Debug)]
 pub struct Database {
-    options: Arc<DbOptions>,
     segments: Arc<SegmentSet>,
     /// We track the "current" and "previous" for all known passes, so that each
     /// pass can use its most recent results for optimized incremental
@@ -388,6 +624,8 @@ pub struct Database {
     outline: Option<Arc<OutlineNode>>,
     grammar: Option<Arc<Grammar>>,
     stmt_parse: Option<Arc<StmtParse>>,
+    hygiene: Option<Arc<HygieneResult>>,
+    progress: Progress,
 }
 
 impl Default for Database {
@@ -396,26 +634,27 @@ impl Default for Database {
     }
 }
 
-fn time<R, F: FnOnce() -> R>(opts: &DbOptions, name: &str, f: F) -> R {
+fn time<R, F: FnOnce() -> R>(progress: &Progress, name: &'static str, f: F) -> R {
+    progress.pass_started(name);
     let now = Instant::now();
     let ret = f();
-    if opts.timing {
-        // no as_msecs :(
-        println!("{} {}ms", name, (now.elapsed() * 1000).as_secs());
-    }
+    progress.pass_finished(name, now.elapsed());
     ret
 }
 
 impl Drop for Database {
     fn drop(&mut self) {
-        time(&self.options.clone(), "free", move || {
+        time(&self.progress.clone(), "free", move || {
             self.prev_verify = None;
             self.verify = None;
             self.prev_scopes = None;
             self.scopes = None;
             self.prev_nameset = None;
             self.nameset = None;
-            Arc::make_mut(&mut self.segments).clear();
+            // the segments may still be shared with a snapshot
+            if let Some(segments) = Arc::get_mut(&mut self.segments) {
+                segments.clear();
+            }
             self.outline = None;
         });
     }
@@ -428,17 +667,26 @@ impl Database {
     /// threadpool, but that may change.
     #[must_use]
     pub fn new(options: DbOptions) -> Database {
-        let options = Arc::new(options);
         let exec = Executor::new(options.jobs);
+        Self::new_with_executor(options, &exec)
+    }
+
+    /// Constructs a new empty database object which runs its passes on an
+    /// existing thread pool, such as the one of another database (see
+    /// `Database::executor`).  `options.jobs` is ignored.
+    #[must_use]
+    pub fn new_with_executor(options: DbOptions, exec: &Executor) -> Database {
+        let progress = Progress::new(options.timing);
         Database {
-            segments: Arc::new(SegmentSet::new(options.clone(), &exec)),
-            options,
+            segments: Arc::new(SegmentSet::new(Arc::new(options), exec)),
+            progress,
             nameset: None,
             scopes: None,
             verify: None,
             outline: None,
             grammar: None,
             stmt_parse: None,
+            hygiene: None,
             prev_nameset: None,
             prev_scopes: None,
             prev_verify: None,
@@ -475,16 +723,71 @@ impl Database {
     /// current database content and incremental processing will be used as
     /// appropriate.
     pub fn parse(&mut self, start: String, text: Vec<(String, Vec<u8>)>) {
-        time(&self.options.clone(), "parse", || {
+        time(&self.progress.clone(), "parse", || {
             Arc::make_mut(&mut self.segments).read(start, text);
-            self.nameset = None;
-            self.scopes = None;
-            self.verify = None;
-            self.outline = None;
-            self.grammar = None;
+            self.invalidate();
         });
     }
 
+    /// Replaces the segment set with an edited version; see the `edit`
+    /// module.
+    pub(crate) fn replace_segments(&mut self, segments: SegmentSet) {
+        self.segments = Arc::new(segments);
+        self.invalidate();
+    }
+
+    /// Discards the current results of all passes after a change to the
+    /// segments, keeping the previous ones for incremental processing.
+    fn invalidate(&mut self) {
+        self.nameset = None;
+        self.scopes = None;
+        self.verify = None;
+        self.outline = None;
+        self.grammar = None;
+        self.stmt_parse = None;
+        self.hygiene = None;
+    }
+
+    /// Registers an observer which will receive structured progress events
+    /// from all subsequently run passes, replacing any previous observer.
+    ///
+    /// Pass `None` to stop reporting.  See the `progress` module for the
+    /// events which are sent.
+    pub fn set_progress_observer(&mut self, observer: Option<Arc<dyn ProgressObserver>>) {
+        self.progress.set_observer(observer);
+        Arc::make_mut(&mut self.segments).progress = self.progress.clone();
+    }
+
+    /// Returns a read-only view of the current state of the database, which
+    /// can be sent to other threads and is unaffected by later changes.
+    ///
+    /// Only the results of passes which have already been run are included;
+    /// run the passes you need first.  See the `snapshot` module.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(Database {
+            segments: self.segments.clone(),
+            prev_nameset: None,
+            nameset: self.nameset.clone(),
+            prev_scopes: None,
+            scopes: self.scopes.clone(),
+            prev_verify: None,
+            verify: self.verify.clone(),
+            outline: self.outline.clone(),
+            grammar: self.grammar.clone(),
+            stmt_parse: self.stmt_parse.clone(),
+            hygiene: self.hygiene.clone(),
+            progress: Progress::default(),
+        })
+    }
+
+    /// Returns the thread pool used by this database, which can be passed to
+    /// `Database::new_with_executor` to share it.
+    #[must_use]
+    pub fn executor(&self) -> &Executor {
+        &self.segments.exec
+    }
+
     /// Obtains a reference to the current parsed data.
     pub(crate) const fn parse_result(&self) -> &Arc<SegmentSet> {
         &self.segments
@@ -493,7 +796,7 @@ impl Database {
     /// Calculates and returns the name to definition lookup table.
     pub fn name_pass(&mut self) -> &Arc<Nameset> {
         if self.nameset.is_none() {
-            time(&self.options.clone(), "nameck", || {
+            time(&self.progress.clone(), "nameck", || {
                 let mut ns = self.prev_nameset.take().unwrap_or_default();
                 let pr = self.parse_result();
                 Arc::make_mut(&mut ns).update(pr);
@@ -521,7 +824,7 @@ impl Database {
     pub fn scope_pass(&mut self) -> &Arc<ScopeResult> {
         if self.scopes.is_none() {
             self.name_pass();
-            time(&self.options.clone(), "scopeck", || {
+            time(&self.progress.clone(), "scopeck", || {
                 let mut sc = self.prev_scopes.take().unwrap_or_default();
                 let parse = self.parse_result();
                 let name = self.name_result();
@@ -552,7 +855,7 @@ impl Database {
         if self.verify.is_none() {
             self.name_pass();
             self.scope_pass();
-            time(&self.options.clone(), "verify", || {
+            time(&self.progress.clone(), "verify", || {
                 let mut ver = self.prev_verify.take().unwrap_or_default();
                 let parse = self.parse_result();
                 let scope = self.scope_result();
@@ -579,7 +882,7 @@ impl Database {
     /// Computes and returns the root node of the outline.
     pub fn outline_pass(&mut self) -> &Arc<OutlineNode> {
         if self.outline.is_none() {
-            time(&self.options.clone(), "outline", || {
+            time(&self.progress.clone(), "outline", || {
                 let parse = self.parse_result().clone();
                 let mut outline = OutlineNode::default();
                 parse.build_outline(&mut outline);
@@ -597,12 +900,32 @@ impl Database {
         self.outline.as_ref().unwrap()
     }
 
+    /// Runs the source hygiene checks selected in `DbOptions::hygiene`, and
+    /// returns their findings.  See the `hygiene` module.
+    pub fn hygiene_pass(&mut self) -> &Arc<HygieneResult> {
+        if self.hygiene.is_none() {
+            time(&self.progress.clone(), "hygiene", || {
+                let result = hygiene::check(&self.segments.options.hygiene, self.parse_result());
+                self.hygiene = Some(Arc::new(result));
+            })
+        }
+        self.hygiene_result()
+    }
+
+    /// Returns the findings of the source hygiene checks.
+    /// Panics if [`Database::hygiene_pass`] was not previously called.
+    #[inline]
+    #[must_use]
+    pub fn hygiene_result(&self) -> &Arc<HygieneResult> {
+        self.hygiene.as_ref().unwrap()
+    }
+
     /// Builds and returns the grammar.
     pub fn grammar_pass(&mut self) -> &Arc<Grammar> {
         if self.grammar.is_none() {
             self.name_pass();
             self.scope_pass();
-            time(&self.options.clone(), "grammar", || {
+            time(&self.progress.clone(), "grammar", || {
                 self.grammar = Some(Arc::new(Grammar::new(self)));
             })
         }
@@ -623,7 +946,7 @@ impl Database {
             self.name_pass();
             self.scope_pass();
             self.grammar_pass();
-            time(&self.options.clone(), "stmt_parse", || {
+            time(&self.progress.clone(), "stmt_parse", || {
                 let parse = self.parse_result();
                 let name = self.name_result();
                 let grammar = self.grammar_result();
@@ -665,23 +988,42 @@ impl Database {
         Some(self.parse_result().statement(lookup.address))
     }
 
+    /// Get a statement by address.
+    #[must_use]
+    pub fn statement_by_address(&self, address: StatementAddress) -> StatementRef<'_> {
+        self.parse_result().statement(address)
+    }
+
     /// Iterates over all the statements
     pub fn statements(&self) -> impl Iterator<Item = StatementRef<'_>> + '_ {
         self.segments.segments().into_iter().flatten()
     }
 
-    /// Export an mmp file for a given statement.
+    /// Export an mmp file for a given statement into the current directory.
+    /// See [`Database::export_proofs`] to export several proofs at once.
     /// Requires: [`Database::name_pass`], [`Database::scope_pass`]
-    pub fn export(&self, stmt: &str) {
-        time(&self.options, "export", || {
-            let sref = self.statement(stmt).unwrap_or_else(|| {
-                panic!("Label {} did not correspond to an existing statement", stmt)
-            });
+    pub fn export(&self, stmt: &str) -> Result<(), export::ExportError> {
+        time(&self.progress, "export", || {
+            let sref = self
+                .statement(stmt)
+                .ok_or(export::ExportError::UnknownLabel)?;
+            if sref.statement_type() != StatementType::Provable {
+                return Err(export::ExportError::NotProvable);
+            }
 
             File::create(format!("{}.mmp", stmt))
                 .map_err(export::ExportError::Io)
                 .and_then(|mut file| self.export_mmp(sref, &mut file))
-                .unwrap()
+        })
+    }
+
+    /// Verifies every proof again, recording time and size metrics for each
+    /// one.  See the `profile` module for ways to report them.
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`]
+    #[must_use]
+    pub fn profile_proofs(&self) -> Vec<ProofProfile> {
+        time(&self.progress, "profile_proofs", || {
+            verify::profile(self.parse_result(), self.name_result(), self.scope_result())
         })
     }
 
@@ -689,7 +1031,7 @@ impl Database {
     /// Requires: [`Database::name_pass`], [`Database::grammar_pass`]
     #[cfg(feature = "dot")]
     pub fn export_grammar_dot(&self) {
-        time(&self.options, "export_grammar_dot", || {
+        time(&self.progress, "export_grammar_dot", || {
             let name = self.name_result();
             let grammar = self.grammar_result();
 
@@ -703,7 +1045,7 @@ impl Database {
     /// Dump the grammar of this database.
     /// Requires: [`Database::name_pass`], [`Database::grammar_pass`]
     pub fn print_grammar(&self) {
-        time(&self.options, "print_grammar", || {
+        time(&self.progress, "print_grammar", || {
             self.grammar_result().dump(self);
         })
     }
@@ -711,7 +1053,7 @@ impl Database {
     /// Dump the formulas of this database.
     /// Requires: [`Database::name_pass`], [`Database::stmt_parse_pass`]
     pub fn print_formula(&self) {
-        time(&self.options, "print_formulas", || {
+        time(&self.progress, "print_formulas", || {
             self.stmt_parse_result().dump(self);
         })
     }
@@ -719,7 +1061,7 @@ impl Database {
     /// Verify that printing the formulas of this database gives back the original formulas.
     /// Requires: [`Database::name_pass`], [`Database::stmt_parse_pass`]
     pub fn verify_parse_stmt(&self) {
-        time(&self.options, "verify_parse_stmt", || {
+        time(&self.progress, "verify_parse_stmt", || {
             if let Err(diag) = self.stmt_parse_result().verify(self) {
                 drop(diag::to_annotations(self.parse_result(), vec![diag]));
             }
@@ -729,7 +1071,7 @@ impl Database {
     /// Dump the outline of this database.
     /// Requires: [`Database::outline_pass`]
     pub fn print_outline(&self) {
-        time(&self.options, "print_outline", || {
+        time(&self.progress, "print_outline", || {
             let root_node = self.outline_result();
             self.print_outline_node(root_node, 0);
         })
@@ -775,7 +1117,10 @@ impl Database {
         if types.contains(&DiagnosticClass::StmtParse) {
             diags.extend(self.stmt_parse_pass().diagnostics());
         }
-        time(&self.options.clone(), "diag", || {
+        if types.contains(&DiagnosticClass::Hygiene) {
+            diags.extend(self.hygiene_pass().diagnostics());
+        }
+        time(&self.progress.clone(), "diag", || {
             diag::to_annotations(self.parse_result(), diags)
         })
     }
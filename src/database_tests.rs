@@ -0,0 +1,317 @@
+use crate::database::Database;
+use crate::database::DbOptions;
+use crate::database::Executor;
+use crate::database::Promise;
+use crate::diag::DiagnosticClass;
+use crate::profile;
+use crate::progress::ChromeTrace;
+use crate::progress::ProgressObserver;
+use crate::progress::SegmentProgress;
+use crate::snapshot::Snapshot;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+pub(super) const PROGRESS_DB: &[u8] = b"
+    $c |- wff ( ) -> $.
+    $v ph ps $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps ) $.
+        ax-mp $a |- ps $.
+    $}
+    ${
+        mp2.1 $e |- ph $.
+        mp2.2 $e |- ( ph -> ps ) $.
+        mp2 $p |- ps $= wph wps mp2.1 mp2.2 ax-mp $.
+    $}
+";
+
+#[derive(Debug, Default)]
+struct Recorder(Mutex<Vec<String>>);
+
+impl ProgressObserver for Recorder {
+    fn pass_started(&self, pass: &'static str) {
+        self.0.lock().unwrap().push(["start ", pass].concat());
+    }
+
+    fn pass_finished(&self, pass: &'static str, _elapsed: Duration) {
+        self.0.lock().unwrap().push(["finish ", pass].concat());
+    }
+
+    fn segment(&self, progress: &SegmentProgress<'_>) {
+        assert!(progress.index < progress.count);
+        self.0.lock().unwrap().push(format!(
+            "{} {}/{} {}",
+            progress.pass,
+            progress.index + 1,
+            progress.count,
+            if progress.reused { "reused" } else { "recalc" }
+        ));
+    }
+}
+
+fn mkdb_observed(recorder: &Arc<Recorder>) -> Database {
+    let options = DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    };
+    let mut db = Database::new(options);
+    db.set_progress_observer(Some(recorder.clone()));
+    db
+}
+
+#[test]
+fn test_progress_events() {
+    let recorder = Arc::new(Recorder::default());
+    let mut db = mkdb_observed(&recorder);
+    let data = vec![("test.mm".to_owned(), PROGRESS_DB.to_owned())];
+    db.parse("test.mm".to_owned(), data.clone());
+    db.verify_pass();
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        vec![
+            "start parse",
+            "parse 1/1 recalc",
+            "finish parse",
+            "start nameck",
+            "nameck 1/1 recalc",
+            "finish nameck",
+            "start scopeck",
+            "scopeck 1/1 recalc",
+            "finish scopeck",
+            "start verify",
+            "verify 1/1 recalc",
+            "finish verify",
+        ]
+    );
+
+    // reparsing the same text hits the parse cache, and everything downstream
+    // can be reused
+    recorder.0.lock().unwrap().clear();
+    db.parse("test.mm".to_owned(), data);
+    db.verify_pass();
+    let events = recorder.0.lock().unwrap().clone();
+    assert!(events.contains(&"parse 1/1 reused".to_owned()));
+    assert!(events.contains(&"nameck 1/1 reused".to_owned()));
+    assert!(events.contains(&"scopeck 1/1 reused".to_owned()));
+    assert!(events.contains(&"verify 1/1 reused".to_owned()));
+}
+
+#[test]
+fn test_chrome_trace() {
+    let trace = Arc::new(ChromeTrace::new());
+    let mut db = Database::new(DbOptions::default());
+    db.set_progress_observer(Some(trace.clone()));
+    db.parse(
+        "test.mm".to_owned(),
+        vec![("test.mm".to_owned(), PROGRESS_DB.to_owned())],
+    );
+    db.verify_pass();
+    let mut out = Vec::new();
+    trace.write(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("{\"displayTimeUnit\":\"ms\",\"traceEvents\":["));
+    assert!(out.contains("\"name\":\"verify\",\"cat\":\"pass\""));
+    assert!(out.contains("\"cat\":\"verify\""));
+    assert!(out.contains("\"args\":{\"index\":0,\"count\":1,"));
+    assert!(out.trim_end().ends_with("]}"));
+}
+
+#[test]
+fn test_profile_proofs() {
+    let text = [
+        PROGRESS_DB,
+        b"
+    ${
+        mp2c.1 $e |- ph $.
+        mp2c.2 $e |- ( ph -> ps ) $.
+        mp2c $p |- ps $= ( ax-mp ) ABCDE $.
+    $}
+    bad $p |- ph $= wph $.
+",
+    ]
+    .concat();
+    let mut db = Database::new(DbOptions::default());
+    db.parse("test.mm".to_owned(), vec![("test.mm".to_owned(), text)]);
+    db.scope_pass();
+    let profiles = db.profile_proofs();
+    let summary: Vec<_> = profiles
+        .iter()
+        .map(|p| {
+            (
+                p.label.as_str(),
+                p.valid,
+                p.steps,
+                p.max_depth,
+                p.referenced,
+                p.compressed_length,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("mp2", true, 5, 4, 5, None),
+            ("mp2c", true, 5, 4, 1, Some(5)),
+            ("bad", false, 1, 1, 1, None),
+        ]
+    );
+
+    let mut csv = Vec::new();
+    profile::write_csv(&profiles[..1], &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.starts_with(
+        "label,valid,time_us,steps,max_depth,referenced,compressed_length\nmp2,true,"
+    ));
+    assert!(csv.ends_with(",5,4,5,\n"));
+}
+
+// all jobs must be queued before the first one is awaited
+#[allow(clippy::needless_collect)]
+#[test]
+fn test_nested_jobs() {
+    let exec = Executor::new(2);
+    let promises: Vec<_> = (0..8)
+        .map(|outer| {
+            let inner_exec = exec.clone();
+            exec.exec(outer, move || {
+                let inner: Vec<_> = (0..4)
+                    .map(|inner| inner_exec.exec(inner, move || outer * 10 + inner))
+                    .collect();
+                inner.into_iter().map(Promise::wait).sum::<usize>()
+            })
+        })
+        .collect();
+    let total: usize = promises.into_iter().map(Promise::wait).sum();
+    assert_eq!(total, (0..8).map(|outer| outer * 40 + 6).sum());
+}
+
+// jobs queued from several workers at once are stolen by the others while
+// they are being counted
+#[allow(clippy::needless_collect)]
+#[test]
+fn test_nested_jobs_stress() {
+    let exec = Executor::new(4);
+    for _ in 0..50 {
+        let promises: Vec<_> = (0..16)
+            .map(|outer| {
+                let inner_exec = exec.clone();
+                exec.exec(outer, move || {
+                    let inner: Vec<_> = (0..32)
+                        .map(|inner| {
+                            let leaf_exec = inner_exec.clone();
+                            inner_exec
+                                .exec(inner, move || leaf_exec.exec(0, move || inner + 1).wait())
+                        })
+                        .collect();
+                    inner.into_iter().map(Promise::wait).sum::<usize>()
+                })
+            })
+            .collect();
+        let total: usize = promises.into_iter().map(Promise::wait).sum();
+        assert_eq!(total, 16 * (1..=32).sum::<usize>());
+    }
+    assert_eq!(format!("{exec:?}"), "Executor(active=0)");
+}
+
+#[test]
+fn test_shared_executor() {
+    let exec = Executor::new(3);
+    let options = DbOptions {
+        jobs: 3,
+        ..DbOptions::default()
+    };
+    let mut dbs: Vec<_> = (0..2)
+        .map(|_| Database::new_with_executor(options.clone(), &exec))
+        .collect();
+    drop(exec);
+    for db in &mut dbs {
+        db.parse(
+            "test.mm".to_owned(),
+            vec![("test.mm".to_owned(), PROGRESS_DB.to_owned())],
+        );
+        db.verify_pass();
+        assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+    }
+}
+
+const fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_snapshot() {
+    assert_send_sync::<Snapshot>();
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    db.parse(
+        "test.mm".to_owned(),
+        vec![("test.mm".to_owned(), PROGRESS_DB.to_owned())],
+    );
+    db.verify_pass();
+    db.outline_pass();
+    let snapshot = db.snapshot();
+
+    let reader = thread::spawn(move || {
+        let label = snapshot.name_result().lookup_label(b"mp2").unwrap().atom;
+        let frame = snapshot.get_frame(label).unwrap();
+        (
+            snapshot.statement("mp2").is_some(),
+            frame.floating().count(),
+            snapshot.outline_result().children.len(),
+            snapshot.diag_notations(&[DiagnosticClass::Verify]).len(),
+        )
+    });
+
+    let broken = [PROGRESS_DB, b"    bad $p |- ph $= wph $.\n"].concat();
+    db.parse("test.mm".to_owned(), vec![("test.mm".to_owned(), broken)]);
+    db.verify_pass();
+    assert!(db.statement("mp2").is_some());
+    assert_eq!(db.diag_notations(&[DiagnosticClass::Verify]).len(), 1);
+    assert_eq!(reader.join().unwrap(), (true, 2, 0, 0));
+}
+
+#[test]
+fn test_clone_speculative_edit() {
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    db.parse(
+        "test.mm".to_owned(),
+        vec![("test.mm".to_owned(), PROGRESS_DB.to_owned())],
+    );
+    db.verify_pass();
+
+    let mut speculative = db.clone();
+    let edited = [PROGRESS_DB, b"    bad $p |- ph $= wph $.\n"].concat();
+    speculative.parse("test.mm".to_owned(), vec![("test.mm".to_owned(), edited)]);
+    assert!(speculative.name_pass().lookup_label(b"bad").is_some());
+    assert_eq!(
+        speculative.diag_notations(&[DiagnosticClass::Verify]).len(),
+        1
+    );
+    drop(speculative);
+
+    assert!(db.name_result().lookup_label(b"bad").is_none());
+    assert!(db.statement("mp2").is_some());
+    assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+}
+
+pub(super) fn mkdb_edit(text: &[u8]) -> Database {
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    db.parse(
+        "test.mm".to_owned(),
+        vec![("test.mm".to_owned(), text.to_owned())],
+    );
+    db.verify_pass();
+    db
+}
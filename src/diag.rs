@@ -37,6 +37,9 @@ pub enum DiagnosticClass {
     Grammar,
     /// Statement Parsing result
     StmtParse,
+    /// Source hygiene warnings, which are about the layout of the text and
+    /// not its meaning
+    Hygiene,
 }
 
 /// List of all diagnostic codes.  For a description of each, see the source of
@@ -75,6 +78,7 @@ pub enum Diagnostic {
     GrammarCantBuild,
     GrammarProvableFloat,
     IoError(String),
+    LineTooLong(Span, usize),
     LocalLabelAmbiguous(Span),
     LocalLabelDuplicate(Span),
     MalformedAdditionalInfo(Span),
@@ -82,6 +86,7 @@ pub enum Diagnostic {
     MissingLabel,
     MissingProof(Span),
     NestedComment(Span, Span),
+    NonAsciiText(Span),
     NotActiveSymbol(TokenIndex),
     NotAProvableStatement,
     ParsedStatementTooShort(Token),
@@ -109,6 +114,8 @@ pub enum Diagnostic {
     StepUsedBeforeDefinition(Token),
     SymbolDuplicatesLabel(TokenIndex, StatementAddress),
     SymbolRedeclared(TokenIndex, TokenAddress),
+    TabCharacter(Span),
+    TrailingWhitespace(Span),
     UnclosedBeforeEof,
     UnclosedBeforeInclude(StatementIndex),
     UnclosedComment(Span),
@@ -361,6 +368,12 @@ fn annotate_diagnostic(
             info.args.push(("error", err.clone()));
             ann(&mut info, Span::NULL);
         }
+        LineTooLong(span, limit) => {
+            info.s = "Line is longer than {limit} characters";
+            info.level = Warning;
+            info.args.push(("limit", d(limit)));
+            ann(&mut info, span);
+        }
         LocalLabelAmbiguous(span) => {
             info.s = "Local label conflicts with the name of an existing statement";
             ann(&mut info, span);
@@ -395,6 +408,11 @@ fn annotate_diagnostic(
             info.level = Note;
             ann(&mut info, opener);
         }
+        NonAsciiText(span) => {
+            info.s = "Non-ASCII character; source files should be limited to US-ASCII";
+            info.level = Warning;
+            ann(&mut info, span);
+        }
         NotActiveSymbol(index) => {
             info.s = "Token used here must be active in the current scope";
             ann(&mut info, stmt.math_span(index));
@@ -524,6 +542,16 @@ fn annotate_diagnostic(
             let sp = info.stmt.math_span(taddr.token_index);
             ann(&mut info, sp);
         }
+        TabCharacter(span) => {
+            info.s = "Tab character; use spaces for indentation and alignment";
+            info.level = Warning;
+            ann(&mut info, span);
+        }
+        TrailingWhitespace(span) => {
+            info.s = "White space at the end of a line";
+            info.level = Warning;
+            ann(&mut info, span);
+        }
         UnclosedBeforeEof => {
             info.s = "${ group must be closed with a $} before end of file";
             ann(&mut info, stmt.span());
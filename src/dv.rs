@@ -0,0 +1,162 @@
+//! Disjoint variable checking for substitutions.
+//!
+//! Applying a theorem in a proof substitutes formulas for its variables, and
+//! each of its mandatory `$d` conditions then requires that the variables
+//! occurring in the two substituted formulas be disjoint: they shall not have
+//! any variable in common, and each pair of variables from either formula
+//! shall be covered by a `$d` statement of the theorem being proved.
+//!
+//! `Database::check_dv` reports both kinds of problems for a substitution
+//! built by `Formula::unify`, before the theorem is applied, where the
+//! verifier would only report a `ProofDvViolation` afterwards.
+
+use crate::formula::Label;
+use crate::formula::Substitutions;
+use crate::formula::Symbol;
+use crate::formula::WorkVariable;
+use crate::parser::as_str;
+use crate::scopeck::Frame;
+use crate::scopeck::Hyp;
+use crate::scopeck::VarIndex;
+use crate::Database;
+
+/// A mandatory `$d` condition of an applied theorem which a substitution
+/// cannot satisfy, because a variable occurs in the formulas substituted for
+/// both of its variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DvViolation {
+    /// The pair of variables of the applied theorem which must be disjoint
+    pub mandatory: (Symbol, Symbol),
+    /// The variable occurring in both substituted formulas
+    pub variable: Symbol,
+}
+
+/// The result of checking a substitution against the `$d` conditions of the
+/// theorem it applies to, in the context of the theorem being proved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DvReport {
+    /// The conditions which no `$d` statement can satisfy.
+    pub violations: Vec<DvViolation>,
+    /// The pairs of distinct variables which must be disjoint, but are not
+    /// declared so for the theorem being proved, each once and in the order of
+    /// the variables of that theorem where known.
+    pub missing: Vec<(Symbol, Symbol)>,
+}
+
+impl DvReport {
+    /// Returns whether the substitution respects all `$d` conditions.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        self.violations.is_empty() && self.missing.is_empty()
+    }
+
+    /// Returns the `$d` statements to add to the theorem being proved for the
+    /// missing disjoint variable conditions, one per pair, like `$d x y $.`.
+    #[must_use]
+    pub fn missing_statements(&self, db: &Database) -> Vec<String> {
+        let nset = db.name_result();
+        self.missing
+            .iter()
+            .map(|&(var1, var2)| {
+                format!(
+                    "$d {} {} $.",
+                    as_str(nset.atom_name(var1)),
+                    as_str(nset.atom_name(var2))
+                )
+            })
+            .collect()
+    }
+}
+
+impl Database {
+    /// Checks a substitution for the variables of the theorem with the given
+    /// frame against its mandatory `$d` conditions, in the context of the
+    /// theorem being proved, with frame `target`, whose `optional_dv` gives the
+    /// pairs of variables which are disjoint.
+    ///
+    /// The substitution maps the labels of the floating hypotheses of the
+    /// theorem to formulas, as built by [`crate::formula::Formula::unify`].
+    /// Conditions on variables without substitution, and pairs involving
+    /// work variables, cannot be checked yet and are ignored.
+    #[must_use]
+    pub fn check_dv(
+        &self,
+        frame: &Frame,
+        substitutions: &Substitutions,
+        target: &Frame,
+    ) -> DvReport {
+        let mut report = DvReport::default();
+        for &(ix1, ix2) in &*frame.mandatory_dv {
+            let substituted = |ix: VarIndex| {
+                frame.hypotheses.iter().find_map(|hyp| match *hyp {
+                    Hyp::Floating(address, index, _) if index == ix => {
+                        let label = self.parse_result().statement(address).label();
+                        substitutions.get(self.name_result().lookup_label(label)?.atom)
+                    }
+                    _ => None,
+                })
+            };
+            if let (Some(formula1), Some(formula2)) = (substituted(ix1), substituted(ix2)) {
+                let mandatory = (frame.var_list[ix1], frame.var_list[ix2]);
+                for label1 in formula1.variables() {
+                    for label2 in formula2.variables() {
+                        if let Some((var1, var2)) =
+                            self.dv_variable(label1).zip(self.dv_variable(label2))
+                        {
+                            if var1 == var2 {
+                                let violation = DvViolation {
+                                    mandatory,
+                                    variable: var1,
+                                };
+                                if !report.violations.contains(&violation) {
+                                    report.violations.push(violation);
+                                }
+                            } else if !disjoint(target, var1, var2) {
+                                let pair = ordered(target, var1, var2);
+                                if !report.missing.contains(&pair) {
+                                    report.missing.push(pair);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    /// Returns the variable symbol of the floating hypothesis with the given
+    /// label, `None` for work variables.
+    fn dv_variable(&self, label: Label) -> Option<Symbol> {
+        if WorkVariable::from_atom(label).is_some() {
+            return None;
+        }
+        let nset = self.name_result();
+        let address = nset.lookup_label(nset.atom_name(label))?.address;
+        let sref = self.parse_result().statement(address);
+        if sref.math_len() < 2 {
+            return None;
+        }
+        Some(nset.lookup_symbol(sref.math_at(1).slice)?.atom)
+    }
+}
+
+/// Returns whether two variables are declared disjoint for a frame.
+fn disjoint(frame: &Frame, var1: Symbol, var2: Symbol) -> bool {
+    let index = |var: Symbol| frame.var_list.iter().position(|&v| v == var);
+    match (index(var1), index(var2)) {
+        (Some(ix1), Some(ix2)) => frame.optional_dv[ix1].has_bit(ix2),
+        _ => false,
+    }
+}
+
+/// Orders a pair of variables as they come in the variables of a frame,
+/// variables unknown to the frame coming last.
+fn ordered(frame: &Frame, var1: Symbol, var2: Symbol) -> (Symbol, Symbol) {
+    let index = |var: Symbol| frame.var_list.iter().position(|&v| v == var);
+    match (index(var1), index(var2)) {
+        (Some(ix1), Some(ix2)) if ix2 < ix1 => (var2, var1),
+        (None, Some(_)) => (var2, var1),
+        _ => (var1, var2),
+    }
+}
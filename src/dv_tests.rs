@@ -0,0 +1,67 @@
+use crate::construct_tests::dv_db;
+use crate::parser::as_str;
+use crate::Database;
+
+/// Checks the substitution unifying the given formula with `ax-5`, in the
+/// context of the given theorem.
+fn check(db: &Database, theorem: &str, goal: &str) -> (Vec<String>, Vec<String>) {
+    let nset = db.name_result();
+    let mut symbols = goal
+        .split_whitespace()
+        .map(|token| nset.lookup_symbol(token.as_bytes()).unwrap().atom);
+    let typecode = symbols.next().unwrap();
+    let goal = db
+        .grammar_result()
+        .parse_math_string(typecode, &mut symbols, nset)
+        .unwrap();
+    let axiom = db
+        .stmt_parse_result()
+        .get_formula(&db.statement("ax-5").unwrap())
+        .unwrap();
+    let subst = goal.unify(axiom).unwrap();
+    let scope = db.scope_result();
+    let report = db.check_dv(
+        scope.get(b"ax-5").unwrap(),
+        &subst,
+        scope.get(theorem.as_bytes()).unwrap(),
+    );
+    assert_eq!(
+        report.is_valid(),
+        report.violations.is_empty() && report.missing.is_empty()
+    );
+    let violations = report
+        .violations
+        .iter()
+        .map(|violation| {
+            format!(
+                "{} {} {}",
+                as_str(nset.atom_name(violation.mandatory.0)),
+                as_str(nset.atom_name(violation.mandatory.1)),
+                as_str(nset.atom_name(violation.variable))
+            )
+        })
+        .collect();
+    (violations, report.missing_statements(db))
+}
+
+#[test]
+fn test_check_dv() {
+    let db = dv_db();
+    let goal = "|- ( A. y ph -> A. x A. y ph )";
+    assert_eq!(check(&db, "th2", goal), (vec![], vec![]));
+    assert_eq!(
+        check(&db, "th1", goal),
+        (vec![], vec!["$d ph x $.".to_owned()])
+    );
+    assert_eq!(
+        check(&db, "th2", "|- ( A. x ph -> A. x A. x ph )"),
+        (vec!["x ph x".to_owned()], vec![])
+    );
+    assert_eq!(
+        check(&db, "th1", "|- ( ( ps -> ph ) -> A. y ( ps -> ph ) )"),
+        (
+            vec![],
+            vec!["$d y ps $.".to_owned(), "$d y ph $.".to_owned()]
+        )
+    );
+}
@@ -0,0 +1,270 @@
+//! Editing the statements of a loaded database in memory.
+//!
+//! The methods in this module change the source text of a single statement
+//! and reparse only the segment which contains it, keeping its `SegmentId`.
+//! All analysis passes are invalidated as by `Database::parse`, but since the
+//! other segments are unchanged, they are rerun incrementally and only redo
+//! the work which depends on the edited segment.
+//!
+//! The edited text is also spliced into the source information of the file
+//! the statement came from, so diagnostics keep pointing at the right lines.
+//! Nothing is written to disk; a later call to `Database::parse` reloads the
+//! files from disk and discards the edits.
+//!
+//! Each edit returns the diagnostics it introduced: the parse, scope and
+//! verify diagnostics which were not present before the edit.  Diagnostics
+//! are compared by file, message, arguments and the source text they point
+//! at, since statement addresses in the edited segment change.
+
+use crate::database::Database;
+use crate::diag::DiagnosticClass;
+use crate::diag::Notation;
+use crate::parser::Comparer;
+use crate::parser::Span;
+use crate::parser::StatementAddress;
+use crate::parser::StatementType;
+use crate::util::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+/// Diagnostic classes compared before and after an edit.
+const EDIT_CLASSES: [DiagnosticClass; 3] = [
+    DiagnosticClass::Parse,
+    DiagnosticClass::Scope,
+    DiagnosticClass::Verify,
+];
+
+/// Identifies the statement an edit applies to.
+#[derive(Copy, Clone, Debug)]
+pub enum EditTarget<'a> {
+    /// The statement with the given label.
+    Label(&'a str),
+    /// The statement at the given address.
+    Address(StatementAddress),
+}
+
+impl<'a> From<&'a str> for EditTarget<'a> {
+    fn from(label: &'a str) -> Self {
+        EditTarget::Label(label)
+    }
+}
+
+impl From<StatementAddress> for EditTarget<'_> {
+    fn from(address: StatementAddress) -> Self {
+        EditTarget::Address(address)
+    }
+}
+
+/// The error type for the editing methods of [`Database`].
+///
+/// When an edit fails, the content of the database is unchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// No statement has the given label.
+    UnknownLabel(String),
+    /// The address does not refer to a loaded statement.
+    UnknownAddress(StatementAddress),
+    /// File inclusions and the end of file marker cannot be edited.
+    NotEditable(StatementAddress),
+    /// The new text would add or remove a file inclusion.
+    ChangesInclusions,
+    /// The statement with the given label is not a `$p` statement.
+    NotProvable(String),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EditError::UnknownLabel(ref label) => write!(f, "Unknown label {label}"),
+            EditError::UnknownAddress(address) => write!(f, "No statement at {address:?}"),
+            EditError::NotEditable(address) => {
+                write!(f, "Statement at {address:?} cannot be edited")
+            }
+            EditError::ChangesInclusions => {
+                write!(f, "Edits cannot add or remove file inclusions")
+            }
+            EditError::NotProvable(ref label) => write!(f, "{label} is not a $p statement"),
+        }
+    }
+}
+
+impl error::Error for EditError {}
+
+/// The part of a notation which survives an edit elsewhere in the segment.
+type NotationKey = (String, &'static str, Vec<(&'static str, String)>, Vec<u8>);
+
+fn notation_key(notation: &Notation) -> NotationKey {
+    let start = (notation.source.span.start + notation.span.start) as usize;
+    let end = (notation.source.span.start + notation.span.end) as usize;
+    (
+        notation.source.name.clone(),
+        notation.message,
+        notation.args.clone(),
+        notation.source.text[start..end].to_vec(),
+    )
+}
+
+impl Database {
+    /// Inserts new statements after the given statement, on a new line with
+    /// the same indentation.  `text` may contain any number of statements and
+    /// comments, but no file inclusions.
+    ///
+    /// Returns the diagnostics introduced by the edit.
+    pub fn insert_statement<'a>(
+        &mut self,
+        after: impl Into<EditTarget<'a>>,
+        text: &str,
+    ) -> Result<Vec<Notation>, EditError> {
+        self.edit_statement(after.into(), |full, span, buffer| {
+            let leading = Span::new(full.start as usize, span.start as usize).as_ref(buffer);
+            let indent = leading
+                .iter()
+                .rposition(|&ch| ch == b'\n')
+                .map_or(leading, |pos| &leading[pos + 1..]);
+            let new_text = [b"\n", indent, text.as_bytes()].concat();
+            (Span::new(span.end as usize, span.end as usize), new_text)
+        })
+    }
+
+    /// Replaces the given statement with new text, keeping the white space
+    /// and comments around it.  `text` may contain any number of statements
+    /// and comments, but no file inclusions.
+    ///
+    /// Returns the diagnostics introduced by the edit.
+    pub fn replace_statement<'a>(
+        &mut self,
+        target: impl Into<EditTarget<'a>>,
+        text: &str,
+    ) -> Result<Vec<Notation>, EditError> {
+        self.edit_statement(target.into(), |_, span, _| (span, text.as_bytes().to_vec()))
+    }
+
+    /// Deletes the given statement, along with the white space before it.
+    ///
+    /// Returns the diagnostics introduced by the edit.
+    pub fn delete_statement<'a>(
+        &mut self,
+        target: impl Into<EditTarget<'a>>,
+    ) -> Result<Vec<Notation>, EditError> {
+        self.edit_statement(target.into(), |full, _, _| (full, Vec::new()))
+    }
+
+    /// Applies several replacements at once, reparsing each segment only
+    /// once.  Each edit is given by the address of a statement, a range
+    /// relative to the start of its full span, and the new text for that
+    /// range.  The ranges must not overlap.
+    ///
+    /// Unlike the statement editing methods, this does not compute the
+    /// diagnostics introduced by the edits.
+    pub(crate) fn replace_ranges(
+        &mut self,
+        mut edits: Vec<(StatementAddress, Span, Vec<u8>)>,
+    ) -> Result<(), EditError> {
+        if edits.is_empty() {
+            return Ok(());
+        }
+        let order = self.parse_result().order.clone();
+        // an edit reparses the rest of its buffer, which moves the statements
+        // of the later segments in it, but keeps their addresses
+        edits.sort_by(|left, right| {
+            order
+                .cmp(&left.0, &right.0)
+                .then(left.1.start.cmp(&right.1.start))
+        });
+        let mut segments = self.parse_result().as_ref().clone();
+        let mut rest = &edits[..];
+        while let Some(&(first, ..)) = rest.first() {
+            let id = first.segment_id;
+            let count = rest
+                .iter()
+                .position(|(address, ..)| address.segment_id != id)
+                .unwrap_or(rest.len());
+            let (group, next) = rest.split_at(count);
+            let resolved: Vec<(usize, usize, &[u8])> = group
+                .iter()
+                .map(|(address, range, text)| {
+                    let start = segments.statement(*address).span_full().start as usize;
+                    (
+                        start + range.start as usize,
+                        start + range.end as usize,
+                        &text[..],
+                    )
+                })
+                .collect();
+            let buffer = segments.segment(id).buffer.clone();
+            let (range_start, range_end) = (resolved[0].0, resolved[resolved.len() - 1].1);
+            let mut text = Vec::new();
+            let mut pos = range_start;
+            for &(start, end, new) in &resolved {
+                text.extend_from_slice(&buffer[pos..start]);
+                text.extend_from_slice(new);
+                pos = end;
+            }
+            segments.replace_text(id, Span::new(range_start, range_end), &text)?;
+            rest = next;
+        }
+        self.replace_segments(segments);
+        Ok(())
+    }
+
+    /// Finds the address of an edited statement.
+    fn edit_address(&mut self, target: EditTarget<'_>) -> Result<StatementAddress, EditError> {
+        let address = match target {
+            EditTarget::Label(label) => {
+                self.name_pass()
+                    .lookup_label(label.as_bytes())
+                    .ok_or_else(|| EditError::UnknownLabel(label.to_owned()))?
+                    .address
+            }
+            EditTarget::Address(address) => address,
+        };
+        let sref = self
+            .parse_result()
+            .segment_opt(address.segment_id)
+            .and_then(|segment| {
+                let index = usize::try_from(address.index).ok()?;
+                segment.into_iter().nth(index)
+            })
+            .ok_or(EditError::UnknownAddress(address))?;
+        match sref.statement_type() {
+            StatementType::Eof | StatementType::FileInclude => Err(EditError::NotEditable(address)),
+            _ => Ok(address),
+        }
+    }
+
+    /// Applies an edit to the buffer of the segment containing a statement.
+    ///
+    /// `edit` is passed the full span of the statement including leading
+    /// white space, its span without it, and the segment buffer, and returns
+    /// the range to replace and the text to replace it with.
+    fn edit_statement<F>(
+        &mut self,
+        target: EditTarget<'_>,
+        edit: F,
+    ) -> Result<Vec<Notation>, EditError>
+    where
+        F: FnOnce(Span, Span, &[u8]) -> (Span, Vec<u8>),
+    {
+        let address = self.edit_address(target)?;
+        let mut before: HashMap<NotationKey, usize> = HashMap::default();
+        for notation in self.diag_notations(&EDIT_CLASSES) {
+            *before.entry(notation_key(&notation)).or_default() += 1;
+        }
+
+        let sref = self.parse_result().statement(address);
+        let (range, text) = edit(sref.span_full(), sref.span(), &sref.segment().buffer);
+        let mut segments = self.parse_result().as_ref().clone();
+        segments.replace_text(address.segment_id, range, &text)?;
+        self.replace_segments(segments);
+
+        let mut introduced = Vec::new();
+        for notation in self.diag_notations(&EDIT_CLASSES) {
+            match before.get_mut(&notation_key(&notation)) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => introduced.push(notation),
+            }
+        }
+        Ok(introduced)
+    }
+}
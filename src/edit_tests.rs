@@ -0,0 +1,95 @@
+use crate::database::Database;
+use crate::database::DbOptions;
+use crate::database_tests::mkdb_edit;
+use crate::database_tests::PROGRESS_DB;
+use crate::diag::DiagnosticClass;
+use crate::diag::Notation;
+use crate::edit::EditError;
+
+fn notation_text(notation: &Notation) -> &[u8] {
+    let start = notation.source.span.start + notation.span.start;
+    let end = notation.source.span.start + notation.span.end;
+    &notation.source.text[start as usize..end as usize]
+}
+
+#[test]
+fn test_edit_statements() {
+    let mut db = mkdb_edit(PROGRESS_DB);
+    let diags = db
+        .replace_statement("mp2", "mp2 $p |- ps $= wph mp2.1 mp2.2 ax-mp $.")
+        .unwrap();
+    assert_eq!(diags.len(), 1);
+    assert_eq!(
+        diags[0].message,
+        "Too few statements on stack to satisfy step's mandatory hypotheses"
+    );
+    assert!(notation_text(&diags[0]).starts_with(b"mp2 $p"));
+
+    // fixing the proof introduces nothing, and removes the error
+    let diags = db
+        .replace_statement("mp2", "mp2 $p |- ps $= wph wps mp2.1 mp2.2 ax-mp $.")
+        .unwrap();
+    assert!(diags.is_empty());
+    assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+
+    let diags = db.insert_statement("wi", "bad $p |- ph $= wph $.").unwrap();
+    assert_eq!(diags.len(), 1);
+    let source = &diags[0].source.text;
+    assert!(String::from_utf8_lossy(source).contains("    wi $a wff ( ph -> ps ) $.\n    bad $p"));
+
+    let address = db.statement("bad").unwrap().address();
+    assert!(db.delete_statement(address).unwrap().is_empty());
+    assert!(db.statement("bad").is_none());
+    assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+    assert_eq!(
+        db.parse_result()
+            .source_info(address.segment_id)
+            .text
+            .as_slice(),
+        PROGRESS_DB
+    );
+
+    assert_eq!(
+        db.delete_statement("nope").unwrap_err(),
+        EditError::UnknownLabel("nope".to_owned())
+    );
+    assert_eq!(
+        db.insert_statement("wi", "$[ other.mm $]").unwrap_err(),
+        EditError::ChangesInclusions
+    );
+    assert_eq!(
+        db.parse_result()
+            .source_info(address.segment_id)
+            .text
+            .as_slice(),
+        PROGRESS_DB
+    );
+}
+
+#[test]
+fn test_edit_before_include() {
+    let main = b"$c |- wff $. $v ph $. wph $f wff ph $.\nax-1 $a |- ph $.\n$[ inc.mm $]\nlate $p |- ph $= wph $.\n";
+    let inc = b"ax-2 $a wff ph $.\n";
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    db.parse(
+        "main.mm".to_owned(),
+        vec![
+            ("main.mm".to_owned(), main.to_vec()),
+            ("inc.mm".to_owned(), inc.to_vec()),
+        ],
+    );
+    let before = db.diag_notations(&[DiagnosticClass::Verify]);
+    assert_eq!(before.len(), 1);
+    assert_eq!(notation_text(&before[0]), b"late $p |- ph $= wph $.");
+
+    // the statement after the inclusion moves back in the file
+    let diags = db.delete_statement("ax-1").unwrap();
+    assert!(diags.is_empty());
+    let after = db.diag_notations(&[DiagnosticClass::Verify]);
+    assert_eq!(after.len(), 1);
+    assert_eq!(notation_text(&after[0]), b"late $p |- ph $= wph $.");
+    assert!(db.statement("ax-2").is_some());
+}
@@ -1,20 +1,41 @@
 //! Export support for mmj2 proof files.
+//!
+//! `Database::export_proofs` writes the proofs of many theorems at once, each
+//! into a file of its own, chosen by selectors:
+//!
+//! * A label, which may contain the wildcards `*` for any sequence of
+//!   characters and `?` for any single character, such as `ax-*`.
+//! * `section:` followed by a heading name, which may contain the same
+//!   wildcards, for all the theorems under the matching headings of the
+//!   database outline, such as `section:Mathbox for *`.
+//!
+//! A failure to export one theorem is reported with its label, and does not
+//! stop the export of the others.
 
 use crate::diag::Diagnostic;
+use crate::outline::OutlineNode;
 use crate::parser::as_str;
+use crate::parser::Comparer;
+use crate::parser::StatementAddress;
 use crate::parser::StatementRef;
 use crate::parser::StatementType;
-use crate::parser::TokenRef;
 use crate::proof::ProofStyle;
 use crate::proof::ProofTreeArray;
 use crate::proof::ProofTreePrinter;
+use crate::scopeck::Hyp;
+use crate::show::ProofLayout;
+use crate::util::HashMap;
+use crate::util::HashSet;
 use crate::Database;
 use regex::Regex;
+use std::cmp::Ordering;
 use std::error;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::Write;
-use std::str;
+use std::path::Path;
+use std::path::PathBuf;
 
 /// The error type for [`Database::export_mmp`].
 #[derive(Debug)]
@@ -25,6 +46,12 @@ pub enum ExportError {
     Verify(Diagnostic),
     /// Formatting error
     Format(fmt::Error),
+    /// The theorem label does not name a `$p` statement
+    UnknownLabel,
+    /// The label names a statement which is not a `$p` statement
+    NotProvable,
+    /// The selector does not match any theorem
+    NoMatch,
 }
 
 impl From<io::Error> for ExportError {
@@ -49,6 +76,9 @@ impl fmt::Display for ExportError {
             ExportError::Io(ref err) => write!(f, "IO error: {}", err),
             ExportError::Verify(ref err) => write!(f, "{:?}", err),
             ExportError::Format(ref err) => write!(f, "Format error: {:?}", err),
+            ExportError::UnknownLabel => write!(f, "Unknown theorem label"),
+            ExportError::NotProvable => write!(f, "Not a $p statement"),
+            ExportError::NoMatch => write!(f, "No matching theorem"),
         }
     }
 }
@@ -57,14 +87,241 @@ impl error::Error for ExportError {
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             ExportError::Io(ref err) => Some(err),
-            ExportError::Verify(_) => None,
+            ExportError::Verify(_)
+            | ExportError::UnknownLabel
+            | ExportError::NotProvable
+            | ExportError::NoMatch => None,
             ExportError::Format(ref err) => Some(err),
         }
     }
 }
 
+/// The outcome of exporting the proofs of several theorems.
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    /// The files written.
+    pub written: Vec<PathBuf>,
+    /// The labels of the theorems, or the selectors, which could not be
+    /// exported, with the reason.
+    pub failed: Vec<(String, ExportError)>,
+}
+
+/// Matches a text against a pattern where `*` stands for any sequence of
+/// characters and `?` for any single character.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // the position of the last `*` in the pattern, and the text position it
+    // was tried at
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&ch) if ch == b'?' || ch == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == b'*')
+}
+
+/// Lists the headings of an outline in database order, with their depth.
+fn flatten_outline<'a>(
+    node: &'a OutlineNode,
+    depth: usize,
+    out: &mut Vec<(usize, &'a OutlineNode)>,
+) {
+    out.push((depth, node));
+    for child in &node.children {
+        flatten_outline(child, depth + 1, out);
+    }
+}
+
 impl Database {
+    /// Returns the typecode of the logical steps of proofs, as opposed to
+    /// syntax steps, or `None` if the database does not declare it, in which
+    /// case all steps are treated as logical.
+    ///
+    /// This is the typecode given in `DbOptions::provable_typecode`, or else
+    /// the one declared by the last `$j syntax ... as ...;` command, or else
+    /// `|-`.
+    pub(crate) fn provable_typecode(&self) -> Option<&[u8]> {
+        let sset = self.parse_result();
+        let provable_tc = match sset.options.provable_typecode {
+            Some(ref typecode) => typecode.as_bytes(),
+            None => sset
+                .segments()
+                .into_iter()
+                .flat_map(|sref| &sref.segment.commands)
+                .filter_map(|(_, command)| match &**command {
+                    [cmd, sort, as_, _] if **cmd == *b"syntax" && **as_ == *b"as" => Some(&**sort),
+                    _ => None,
+                })
+                .next_back()
+                .unwrap_or(b"|-"),
+        };
+        self.name_result()
+            .lookup_symbol(provable_tc)
+            .map(|_| provable_tc)
+    }
+
+    /// Returns the label of the last `$a` or `$p` statement before the given
+    /// statement, after which mmj2 is to place the theorem of a worksheet.
+    fn loc_after(&self, stmt: StatementRef<'_>) -> Option<&[u8]> {
+        self.statements()
+            .take_while(|sref| sref.address() != stmt.address())
+            .filter(|sref| {
+                matches!(
+                    sref.statement_type(),
+                    StatementType::Axiom | StatementType::Provable
+                )
+            })
+            .last()
+            .map(|sref| sref.label())
+    }
+
+    /// Writes the proof of each theorem chosen by the selectors into the
+    /// directory `dir`, which is created if needed, in the given layout.  The
+    /// file of a theorem is named after its label, with the extension `mmp`
+    /// for mmj2 worksheets and `txt` otherwise.
+    ///
+    /// See the [module documentation](crate::export) for the selectors.
+    ///
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`], and
+    /// [`Database::outline_pass`] for section selectors
+    #[must_use]
+    pub fn export_proofs(
+        &self,
+        dir: &Path,
+        selectors: &[&str],
+        layout: ProofLayout,
+    ) -> ExportReport {
+        let mut report = ExportReport::default();
+        let statements = self.select_theorems(selectors, &mut report);
+        if let Err(err) = fs::create_dir_all(dir) {
+            report
+                .failed
+                .push((dir.display().to_string(), ExportError::Io(err)));
+            return report;
+        }
+        let extension = if layout == ProofLayout::Mmp {
+            "mmp"
+        } else {
+            "txt"
+        };
+        for stmt in statements {
+            let label = as_str(stmt.label());
+            let path = dir.join(format!("{label}.{extension}"));
+            // the proof is written out at once, so that a failure leaves no
+            // partial file behind
+            let mut text = Vec::new();
+            match self
+                .show_proof(stmt, layout, false, &mut text)
+                .and_then(|()| fs::write(&path, text).map_err(ExportError::Io))
+            {
+                Ok(()) => report.written.push(path),
+                Err(err) => report.failed.push((label.to_owned(), err)),
+            }
+        }
+        report
+    }
+
+    /// Returns the `$p` statements chosen by the selectors in the order they
+    /// are selected, without repetitions, and records the selectors which do
+    /// not choose any in the report.
+    fn select_theorems(
+        &self,
+        selectors: &[&str],
+        report: &mut ExportReport,
+    ) -> Vec<StatementRef<'_>> {
+        let mut seen = HashSet::default();
+        let mut out = Vec::new();
+        for &selector in selectors {
+            let found: Result<Vec<StatementRef<'_>>, ExportError> =
+                if let Some(heading) = selector.strip_prefix("section:") {
+                    Ok(self.section_theorems(heading))
+                } else if selector.contains(&['*', '?'][..]) {
+                    Ok(self
+                        .statements()
+                        .filter(|stmt| {
+                            stmt.statement_type() == StatementType::Provable
+                                && glob_match(selector.as_bytes(), stmt.label())
+                        })
+                        .collect())
+                } else {
+                    match self.statement(selector) {
+                        Some(stmt) if stmt.statement_type() == StatementType::Provable => {
+                            Ok(vec![stmt])
+                        }
+                        Some(_) => Err(ExportError::NotProvable),
+                        None => Err(ExportError::UnknownLabel),
+                    }
+                };
+            match found {
+                Ok(found) if found.is_empty() => {
+                    report
+                        .failed
+                        .push((selector.to_owned(), ExportError::NoMatch));
+                }
+                Ok(found) => {
+                    out.extend(found.into_iter().filter(|stmt| seen.insert(stmt.address())));
+                }
+                Err(err) => report.failed.push((selector.to_owned(), err)),
+            }
+        }
+        out
+    }
+
+    /// Returns the `$p` statements under the headings whose name matches the
+    /// pattern, up to the next heading of the same or a higher level.
+    fn section_theorems(&self, pattern: &str) -> Vec<StatementRef<'_>> {
+        let mut headings = Vec::new();
+        if let Some(outline) = self.get_outline() {
+            flatten_outline(outline, 0, &mut headings);
+        }
+        let order = &self.parse_result().order;
+        let mut ranges: Vec<(StatementAddress, Option<StatementAddress>)> = Vec::new();
+        for (ix, &(depth, node)) in headings.iter().enumerate().skip(1) {
+            if glob_match(pattern.as_bytes(), node.get_name().as_bytes()) {
+                let end = headings[ix + 1..]
+                    .iter()
+                    .find(|&&(next_depth, _)| next_depth <= depth)
+                    .map(|(_, next)| next.stmt_address);
+                ranges.push((node.stmt_address, end));
+            }
+        }
+        self.statements()
+            .filter(|stmt| {
+                stmt.statement_type() == StatementType::Provable
+                    && ranges.iter().any(|&(start, end)| {
+                        order.cmp(&start, &stmt.address()) == Ordering::Less
+                            && match end {
+                                Some(end) => order.cmp(&stmt.address(), &end) == Ordering::Less,
+                                None => true,
+                            }
+                    })
+            })
+            .collect()
+    }
+
     /// Export an mmp file for a given statement.
+    ///
+    /// The worksheet lists the hypotheses of the theorem, the logical steps
+    /// of its proof and its `$d` conditions, followed by the proof itself, so
+    /// that mmj2 can unify it again.  The steps of an incomplete proof which
+    /// depend on an unknown step `?` are written without a formula, and the
+    /// unknown steps they use are referred to as `?`.
     pub fn export_mmp<W: Write>(
         &self,
         stmt: StatementRef<'_>,
@@ -73,8 +330,9 @@ impl Database {
         let thm_label = stmt.label();
         writeln!(
             out,
-            "$( <MM> <PROOF_ASST> THEOREM={}  LOC_AFTER=?\n",
-            as_str(thm_label)
+            "$( <MM> <PROOF_ASST> THEOREM={}  LOC_AFTER={}\n",
+            as_str(thm_label),
+            self.loc_after(stmt).map_or("?", as_str)
         )?;
         if let Some(comment) = stmt.associated_comment() {
             let mut span = comment.span();
@@ -100,86 +358,147 @@ impl Database {
     ) -> Result<(), ExportError> {
         let sset = self.parse_result();
         let nset = self.name_result();
+        let scope = self.scope_result();
+        let frame = scope.get(thm_label).ok_or(ExportError::UnknownLabel)?;
+        let thm = self
+            .statement(as_str(thm_label))
+            .ok_or(ExportError::UnknownLabel)?;
+        let provable_tc = self.provable_typecode();
+        let logical = |tc: &[u8]| provable_tc.map_or(true, |ptc| ptc == tc);
+        let unknown = arr.unknown_exprs();
+        let indent = arr.indent();
+        let math_string = |stmt: StatementRef<'_>| {
+            let tokens: Vec<&str> = (0..stmt.math_len())
+                .map(|i| as_str(stmt.math_at(i).slice))
+                .collect();
+            tokens.join(" ")
+        };
 
-        // TODO(Mario): remove hardcoded logical step symbol
-        let provable_tc = b"|-";
-        let provable_tc = nset.lookup_symbol(provable_tc).map(|_| provable_tc);
+        // The hypotheses of the theorem come first, numbered from 1
+        let mut hyp_numbers = HashMap::default();
+        // Each line is the step indentation, the step prefix and its formula
+        let mut lines: Vec<(u16, String, String)> = vec![];
+        for hyp in &*frame.hypotheses {
+            if let Hyp::Essential(addr, _) = *hyp {
+                let stmt = sset.statement(addr);
+                let number = hyp_numbers.len() + 1;
+                hyp_numbers.insert(addr, number);
+                let depth = arr
+                    .trees
+                    .iter()
+                    .position(|tree| tree.address == addr)
+                    .map_or(0, |ix| indent[ix]);
+                lines.push((
+                    depth,
+                    format!("h{}::{} ", number, as_str(stmt.label())),
+                    math_string(stmt),
+                ));
+            }
+        }
 
-        // This array maps the proof tree index to 0 for syntax proofs and a 1-based
-        // index for logical steps
+        // This array maps the proof tree index to 0 for syntax proofs and
+        // unknown steps, and to the step number for logical steps
         let mut logical_steps: Vec<usize> = vec![];
-        let mut ix = 0usize;
-        // This is indexed based on the numbering in logical_steps, so
-        // if logical_steps[i] = j+1 then arr.trees[i] corresponds to (i, typecode[i], lines[j])
-        let mut lines: Vec<(usize, TokenRef<'_>, String)> = vec![];
-        for tree in &arr.trees {
+        let mut ix = hyp_numbers.len();
+        for (cur, tree) in arr.trees.iter().enumerate() {
+            if tree.is_unknown() {
+                logical_steps.push(0);
+                continue;
+            }
+            if let Some(&number) = hyp_numbers.get(&tree.address) {
+                logical_steps.push(number);
+                continue;
+            }
             let stmt = sset.statement(tree.address);
             let label = stmt.label();
             let tc = stmt.math_at(0);
-            let logical = provable_tc.map_or(true, |tref| *tref == *tc);
-
-            let cur = logical_steps.len();
-            logical_steps.push(if logical {
-                ix += 1;
-                ix
-            } else {
-                0
-            });
+            if !logical(&tc) {
+                logical_steps.push(0);
+                continue;
+            }
+            ix += 1;
+            logical_steps.push(ix);
 
             // Because a step only references previous steps in the array,
             // we are clear to start output before finishing the loop
-            if logical {
-                let mut line = match stmt.statement_type() {
-                    // Floating will not happen unless we don't recognize the grammar
-                    StatementType::Essential | StatementType::Floating => format!("h{}", ix),
-                    _ => {
-                        if cur == arr.qed {
-                            "qed".to_string()
-                        } else {
-                            ix.to_string()
-                        }
-                    }
-                };
-                let mut delim = ':';
-                for &hyp in &tree.children {
-                    let hix = logical_steps[hyp];
-                    if hix != 0 {
-                        line.push(delim);
-                        delim = ',';
-                        line.push_str(&hix.to_string());
-                    }
-                }
-                if delim == ':' {
+            let mut line = if cur == arr.qed {
+                "qed".to_string()
+            } else {
+                ix.to_string()
+            };
+            let hyps = scope.get(label).map_or(&[][..], |frame| &frame.hypotheses);
+            let mut delim = ':';
+            for (&hyp, frame_hyp) in tree.children.iter().zip(hyps) {
+                let hix = logical_steps[hyp];
+                if hix != 0 {
                     line.push(delim);
+                    line.push_str(&hix.to_string());
+                } else if arr.trees[hyp].is_unknown()
+                    && logical(nset.atom_name(frame_hyp.typecode()))
+                {
+                    line.push(delim);
+                    line.push('?');
+                } else {
+                    continue;
                 }
-                line.push(':');
-                line.push_str(str::from_utf8(label).unwrap());
-                line.push(' ');
-                lines.push((cur, tc, line));
+                delim = ',';
+            }
+            if delim == ':' {
+                line.push(delim);
             }
+            line.push(':');
+            line.push_str(as_str(label));
+            line.push(' ');
+            // the final step of an incomplete proof may depend on an
+            // unknown step, but its formula is the assertion of the theorem
+            let formula = if cur == arr.qed {
+                math_string(thm)
+            } else if unknown[cur] {
+                String::new()
+            } else {
+                [as_str(&tc), as_str(&arr.exprs[cur])].concat()
+            };
+            lines.push((indent[cur], line, formula));
+        }
+        if arr.trees[arr.qed].is_unknown() {
+            lines.push((0, "qed:: ".to_string(), math_string(thm)));
         }
 
-        let indent = arr.indent();
         let spaces = lines
             .iter()
-            .map(|&(cur, _, ref line)| line.len() as i16 - indent[cur] as i16)
+            .map(|&(depth, ref line, _)| line.len() as i16 - depth as i16)
             .max()
-            .unwrap() as u16;
-        for &mut (cur, tc, ref mut line) in &mut lines {
-            for _ in 0..(spaces + indent[cur] - line.len() as u16) {
+            .unwrap_or(0) as u16;
+        for &mut (depth, ref mut line, ref formula) in &mut lines {
+            for _ in 0..(spaces + depth - line.len() as u16) {
                 line.push(' ')
             }
-            line.push_str(str::from_utf8(&tc).unwrap());
-            line.push_str(&String::from_utf8_lossy(&arr.exprs[cur]));
-            writeln!(out, "{}", line)?;
+            line.push_str(formula);
+            writeln!(out, "{}", line.trim_end())?;
         }
+
+        let dvs: Vec<String> = frame
+            .mandatory_dv
+            .iter()
+            .map(|&(var1, var2)| {
+                format!(
+                    "$d {} {}",
+                    as_str(nset.atom_name(frame.var_list[var1])),
+                    as_str(nset.atom_name(frame.var_list[var2]))
+                )
+            })
+            .collect();
+        if !dvs.is_empty() {
+            writeln!(out, "\n{}", dvs.join("\n"))?;
+        }
+
         writeln!(
             out,
             "\n$={}",
             ProofTreePrinter {
                 sset,
                 nset,
-                scope: self.scope_result(),
+                scope,
                 thm_label,
                 style: ProofStyle::Compressed,
                 arr,
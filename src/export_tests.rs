@@ -0,0 +1,152 @@
+use crate::database_tests::mkdb_edit;
+use crate::database_tests::PROGRESS_DB;
+use crate::proof::ProofStyle;
+use crate::show::ProofLayout;
+use std::env;
+use std::fs;
+use std::process;
+
+const MMP_DB: &[u8] = b"
+    $c |- wff ( ) -> $.
+    $v ph ps $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps ) $.
+        ax-mp $a |- ps $.
+    $}
+    id $a |- ( ph -> ph ) $.
+    ${
+        $d ph ps $.
+        mp2.1 $e |- ph $.
+        mp2.2 $e |- ( ph -> ps ) $.
+        $( Modus ponens with an unused hypothesis. $)
+        mp2 $p |- ps $= wph wps mp2.1 ? ax-mp $.
+    $}
+    th $p |- ps $= ? wps ? ? id ax-mp $.
+    th2 $p |- ps $= ( id ax-mp ) ?A??BC $.
+";
+
+#[test]
+fn test_export_mmp() {
+    let mut db = mkdb_edit(MMP_DB);
+    db.scope_pass();
+    let export = |label: &str| {
+        let mut out = Vec::new();
+        db.export_mmp(db.statement(label).unwrap(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(
+        export("mp2"),
+        "$( <MM> <PROOF_ASST> THEOREM=mp2  LOC_AFTER=id
+
+* Modus ponens with an unused hypothesis.
+
+h1::mp2.1      |- ph
+h2::mp2.2     |- ( ph -> ps )
+qed:1,?:ax-mp |- ps
+
+$d ph ps
+
+$=    ( ax-mp ) ABC?E $.
+
+$)
+"
+    );
+    let th = "$( <MM> <PROOF_ASST> THEOREM=th  LOC_AFTER=mp2
+
+1::id
+qed:?,1:ax-mp |- ps
+
+$=    ( id ax-mp ) ?A??BC $.
+
+$)
+";
+    assert_eq!(export("th"), th);
+    assert_eq!(
+        export("th2"),
+        th.replace("THEOREM=th  LOC_AFTER=mp2", "THEOREM=th2  LOC_AFTER=th")
+    );
+
+    let mut out = Vec::new();
+    db.show_proof(
+        db.statement("th").unwrap(),
+        ProofLayout::Lemmon,
+        false,
+        &mut out,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "1         ?
+2         wps   wff ps
+3 1       id
+4 1,2,1,3 ax-mp |- ps
+"
+    );
+    // the verifier still rejects incomplete proofs
+    assert_eq!(db.verify_result().diagnostics().len(), 3);
+}
+
+#[test]
+fn test_export_proofs() {
+    let text = [
+        PROGRESS_DB,
+        b"$(
+#*#*#*#
+  Mathbox for Alice
+#*#*#*#
+$)
+    a.1 $p wff ( ph -> ps ) $= wph wps wi $.
+    a.2 $p wff ( ps -> ph ) $= wps wph wi $.
+$(
+#*#*#*#
+  Mathbox for Bob
+#*#*#*#
+$)
+    b.1 $p wff ( ph -> ph ) $= wph wph wi $.
+    b.2 $p wff ph $= wps $.
+",
+    ]
+    .concat();
+    let mut db = mkdb_edit(&text);
+    db.scope_pass();
+    db.outline_pass();
+    let dir = env::temp_dir().join(format!("knife-export-{}", process::id()));
+    let report = db.export_proofs(
+        &dir,
+        &["section:*Alice", "a.*", "b.?", "wi", "nope", "c.*"],
+        ProofLayout::Style(ProofStyle::Normal),
+    );
+    let written: Vec<String> = report
+        .written
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(written, ["a.1.txt", "a.2.txt", "b.1.txt"]);
+    assert_eq!(
+        fs::read_to_string(dir.join("a.2.txt")).unwrap(),
+        "  wps wph wi $.\n"
+    );
+    let failed: Vec<String> = report
+        .failed
+        .iter()
+        .map(|(label, err)| format!("{label}: {err}"))
+        .collect();
+    assert_eq!(
+        failed,
+        [
+            "wi: Not a $p statement",
+            "nope: Unknown theorem label",
+            "c.*: No matching theorem",
+            "b.2: ProofWrongExprEnd",
+        ]
+    );
+
+    let report = db.export_proofs(&dir, &["section:Mathbox for Bob"], ProofLayout::Mmp);
+    assert_eq!(report.written, [dir.join("b.1.mmp")]);
+    fs::remove_dir_all(&dir).unwrap();
+}
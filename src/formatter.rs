@@ -0,0 +1,396 @@
+//! Formatting database sources in the house style of `set.mm`.
+//!
+//! `Database::format_source` regenerates the text of each source file with the
+//! white space between and inside statements normalized, in the manner of the
+//! `write source /rewrap` command of metamath.exe:
+//!
+//! * A statement which starts a line is indented by `indent` columns for each
+//!   enclosing `${ $}` group, plus one level at the top.  A statement which
+//!   follows another one on the same line stays there.
+//! * Blank lines between statements are kept, trailing white space is
+//!   removed, and each file ends with a single newline.
+//! * Math strings are filled into lines of `line_width` columns, continuing
+//!   four columns to the right of the statement indentation.  Proofs start on
+//!   a new line two columns to the right and are filled the same way, with the
+//!   step letters of compressed proofs split at any point, as
+//!   `ProofTreePrinter` does.
+//! * Consecutive `$d` statements on adjacent lines are packed onto shared
+//!   lines, two spaces apart.
+//! * Comments are refilled, with continuation lines aligned after the `$( `.
+//!   Blank lines inside a comment separate paragraphs.  Gaps of two spaces or
+//!   more become two spaces, as does a line break after a period, question or
+//!   exclamation mark; other gaps become a single space.
+//!
+//! Some text is kept as it is, apart from trailing white space: comments which
+//! start in the first column or with a line break, such as section headings
+//! and file headers, `$t` and `$j` comments, comments containing `<HTML>`,
+//! statements containing comments and statements with parse errors.  Files
+//! which are not valid UTF-8 are not formatted.
+//!
+//! Formatting only changes white space, and formatting a formatted file again
+//! leaves it unchanged.
+
+use crate::database::Database;
+use crate::diag::Diagnostic;
+use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::proof::LineWrap;
+use crate::util::HashMap;
+use crate::writer::FileChange;
+use std::str;
+
+/// Layout parameters for [`Database::format_source`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Number of columns to fill lines to.  Longer lines are only produced
+    /// for words which do not fit on a line of their own.
+    pub line_width: u16,
+    /// Number of columns of indentation for each level of `${ $}` nesting.
+    pub indent: u16,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            line_width: 79,
+            indent: 2,
+        }
+    }
+}
+
+/// The keyword of a statement whose math string is reformatted.
+const fn keyword(stype: StatementType) -> Option<&'static str> {
+    match stype {
+        StatementType::Axiom => Some("$a"),
+        StatementType::Provable => Some("$p"),
+        StatementType::Essential => Some("$e"),
+        StatementType::Floating => Some("$f"),
+        StatementType::Disjoint => Some("$d"),
+        StatementType::Constant => Some("$c"),
+        StatementType::Variable => Some("$v"),
+        _ => None,
+    }
+}
+
+/// Returns `true` if a comment is kept as it is.  `lead` is the white space
+/// before it.  Comments with parse errors, such as a missing `$)`, are
+/// always kept.
+fn is_verbatim_comment(stype: StatementType, lead: &str, text: &str, has_errors: bool) -> bool {
+    if stype != StatementType::Comment
+        || has_errors
+        || text.len() < 4
+        || !text.starts_with("$(")
+        || !text.ends_with("$)")
+    {
+        return true;
+    }
+    let content = &text[2..text.len() - 2];
+    let first_column = lead.is_empty() || lead.ends_with('\n');
+    first_column
+        || content
+            .trim_start_matches([' ', '\t', '\r'])
+            .starts_with('\n')
+        || content.contains("<HTML>")
+}
+
+/// The formatted text of one source file.
+#[derive(Default)]
+struct FileFormatter {
+    out: String,
+    /// Type of the last statement written to the file.
+    last: Option<StatementType>,
+    /// Set if the file could not be formatted.
+    skip: bool,
+}
+
+impl FileFormatter {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    /// The width of the text on the current line.
+    fn column(&self) -> u16 {
+        let start = self.out.rfind('\n').map_or(0, |pos| pos + 1);
+        self.out[start..].chars().count() as u16
+    }
+
+    fn push_spaces(&mut self, count: u16) {
+        for _ in 0..count {
+            self.out.push(' ');
+        }
+    }
+
+    /// Ends the current line so that the output ends with at least `newlines`
+    /// line breaks, removing trailing white space.  Leading blank lines of
+    /// the file are dropped.
+    fn start_line(&mut self, newlines: usize) {
+        let len = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(len);
+        if self.out.is_empty() {
+            return;
+        }
+        let existing = self.out.len() - self.out.trim_end_matches('\n').len();
+        for _ in existing..newlines {
+            self.out.push('\n');
+        }
+    }
+
+    /// Writes text as it is, apart from trailing white space on its lines.
+    fn push_verbatim(&mut self, text: &str) {
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.out.push('\n');
+            }
+            self.out.push_str(line.trim_end_matches([' ', '\t']));
+        }
+    }
+
+    /// Writes a statement, with the white space before it.
+    fn statement(
+        &mut self,
+        options: FormatOptions,
+        depth: u16,
+        sref: StatementRef<'_>,
+        has_errors: bool,
+    ) {
+        let segment = sref.segment();
+        let full = str::from_utf8(sref.span_full().as_ref(&segment.buffer)).unwrap_or_default();
+        let (lead, text) = full.split_at(full.len() - full.trim_start().len());
+        let stype = sref.statement_type();
+        let newlines = lead.matches('\n').count();
+
+        match stype {
+            StatementType::Eof => {
+                self.start_line(newlines);
+                return;
+            }
+            StatementType::Comment
+            | StatementType::TypesettingComment
+            | StatementType::HeadingComment(_)
+            | StatementType::AdditionalInfoComment
+                if is_verbatim_comment(stype, lead, text, has_errors) =>
+            {
+                if newlines == 0 && !self.at_line_start() {
+                    self.out.push_str(lead);
+                } else {
+                    self.start_line(newlines.max(1));
+                    let indent = lead.rfind('\n').map_or(lead, |pos| &lead[pos + 1..]);
+                    self.out.push_str(indent);
+                }
+                self.push_verbatim(text);
+                self.last = Some(stype);
+                return;
+            }
+            _ => {}
+        }
+
+        let indent = options.indent * (depth + 1);
+        let reformat = keyword(stype).is_some() && !has_errors && !text.contains("$(");
+        if stype == StatementType::Disjoint
+            && self.last == Some(StatementType::Disjoint)
+            && newlines <= 1
+            && !self.at_line_start()
+        {
+            let width = 2
+                + (0..sref.math_len())
+                    .map(|ix| sref.math_at(ix).slice.len() as u16 + 1)
+                    .sum::<u16>()
+                + 3;
+            if !reformat || self.column() + 2 + width > options.line_width {
+                self.start_line(1);
+                self.push_spaces(indent);
+            } else {
+                self.out.push_str("  ");
+            }
+        } else if newlines == 0 && !self.at_line_start() {
+            self.out.push_str(lead);
+        } else {
+            self.start_line(newlines.max(1));
+            self.push_spaces(indent);
+        }
+
+        if stype == StatementType::Comment {
+            self.comment(options, text);
+        } else if reformat {
+            self.math_statement(options, indent, sref);
+        } else {
+            self.push_verbatim(text);
+        }
+        self.last = Some(stype);
+    }
+
+    /// Writes a refilled comment, which must be terminated.
+    fn comment(&mut self, options: FormatOptions, text: &str) {
+        let col = self.column();
+        let content = &text[2..text.len() - 2];
+        let mut paragraphs: Vec<Vec<(&str, u16)>> = vec![vec![]];
+        let mut last_word = "";
+        for line in content.split('\n') {
+            if line.trim().is_empty() {
+                if !paragraphs.last().unwrap().is_empty() {
+                    paragraphs.push(vec![]);
+                }
+                continue;
+            }
+            let mut rest = line;
+            let mut line_start = true;
+            while let Some(start) = rest.find(|ch: char| !ch.is_whitespace()) {
+                let end = rest[start..]
+                    .find(char::is_whitespace)
+                    .map_or(rest.len(), |len| start + len);
+                let word = &rest[start..end];
+                let sentence_end = last_word.ends_with(['.', '?', '!']);
+                let gap = if line_start {
+                    if sentence_end {
+                        2
+                    } else {
+                        1
+                    }
+                } else if start >= 2 {
+                    2
+                } else {
+                    1
+                };
+                paragraphs.last_mut().unwrap().push((word, gap));
+                last_word = word;
+                line_start = false;
+                rest = &rest[end..];
+            }
+        }
+        if paragraphs.last().unwrap().is_empty() && paragraphs.len() > 1 {
+            paragraphs.pop();
+        }
+
+        self.out.push_str("$(");
+        let count = paragraphs.len();
+        for (index, paragraph) in paragraphs.into_iter().enumerate() {
+            if index > 0 {
+                self.out.push_str("\n\n");
+                self.push_spaces(col + 2);
+            }
+            let chr = self.column();
+            let mut wrap = LineWrap::new(&mut self.out, chr, col + 3, options.line_width);
+            for (word_index, (word, gap)) in paragraph.into_iter().enumerate() {
+                let gap = if word_index == 0 { 1 } else { gap };
+                let _ = wrap.write_word_gap(word, gap);
+            }
+            if index + 1 == count {
+                let _ = wrap.write_word("$)");
+            }
+        }
+    }
+
+    /// Writes a statement with a reformatted math string and proof.
+    fn math_statement(&mut self, options: FormatOptions, indent: u16, sref: StatementRef<'_>) {
+        let stype = sref.statement_type();
+        let label = str::from_utf8(sref.label()).unwrap_or_default();
+        let keyword = keyword(stype).unwrap_or_default();
+        let first = if label.is_empty() { keyword } else { label };
+        self.out.push_str(first);
+        let chr = self.column();
+        let mut wrap = LineWrap::new(&mut self.out, chr, indent + 4, options.line_width);
+        if !label.is_empty() {
+            let _ = wrap.write_word(keyword);
+        }
+        for ix in 0..sref.math_len() {
+            let _ = wrap.write_word(str::from_utf8(sref.math_at(ix).slice).unwrap_or_default());
+        }
+        if stype != StatementType::Provable {
+            let _ = wrap.write_word("$.");
+            return;
+        }
+        let _ = wrap.write_word("$=");
+
+        let proof: Vec<&str> = (0..sref.proof_len())
+            .map(|ix| str::from_utf8(sref.proof_slice_at(ix)).unwrap_or_default())
+            .collect();
+        self.out.push('\n');
+        self.push_spaces(indent + 1);
+        let mut wrap = LineWrap::new(&mut self.out, indent + 1, indent + 2, options.line_width);
+        let labels_end = proof.iter().position(|&token| token == ")");
+        match labels_end {
+            Some(end) if proof[0] == "(" => {
+                for token in &proof[..=end] {
+                    let _ = wrap.write_word(token);
+                }
+                if end + 1 < proof.len() {
+                    let _ = wrap.write_split(&proof[end + 1..].concat());
+                }
+            }
+            _ => {
+                for token in &proof {
+                    let _ = wrap.write_word(token);
+                }
+            }
+        }
+        let _ = wrap.write_word("$.");
+    }
+}
+
+impl Database {
+    /// Formats the source files of the database, and returns the files whose
+    /// formatted text differs from their current text.  The `old` text of
+    /// each change is the current text, as given by
+    /// [`Database::source_files`], and may include edits.
+    ///
+    /// See the [module documentation](crate::formatter) for the rules.
+    #[must_use]
+    pub fn format_source(&self, options: FormatOptions) -> Vec<FileChange> {
+        let sset = self.parse_result();
+        let mut files: Vec<FileFormatter> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::default();
+        let mut depth = 0_u16;
+        for sref in sset.segments() {
+            if sref
+                .diagnostics
+                .iter()
+                .any(|(_, diag)| matches!(diag, Diagnostic::IoError(_)))
+            {
+                continue;
+            }
+            let name = &sset.source_info(sref.id).name;
+            let file = *index.entry(name.clone()).or_insert_with(|| {
+                files.push(FileFormatter::default());
+                files.len() - 1
+            });
+            let file = &mut files[file];
+            file.skip |= str::from_utf8(sref.span().as_ref(&sref.buffer)).is_err();
+            for stmt in sref {
+                if stmt.statement_type() == StatementType::CloseGroup {
+                    depth = depth.saturating_sub(1);
+                }
+                if !file.skip {
+                    let has_errors = sref.diagnostics.iter().any(|&(ix, _)| ix == stmt.index());
+                    file.statement(options, depth, stmt, has_errors);
+                }
+                if stmt.statement_type() == StatementType::OpenGroup {
+                    depth += 1;
+                }
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (name, old) in self.source_files() {
+            let file = &mut files[index[&name]];
+            if file.skip {
+                continue;
+            }
+            let len = file.out.trim_end().len();
+            file.out.truncate(len);
+            if !file.out.is_empty() {
+                file.out.push('\n');
+            }
+            let new = std::mem::take(&mut file.out).into_bytes();
+            if new != old {
+                let in_memory = self.parse_result().preloaded_text(&name).is_some();
+                changes.push(FileChange {
+                    name,
+                    old: Some(old),
+                    new,
+                    in_memory,
+                });
+            }
+        }
+        changes
+    }
+}
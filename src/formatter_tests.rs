@@ -0,0 +1,113 @@
+use crate::database_tests::mkdb_edit;
+use crate::database_tests::PROGRESS_DB;
+use crate::formatter::FormatOptions;
+
+const UNFORMATTED_DB: &str = "$( Header comment
+   in the first column $)
+$c |- wff ( ) -> $.  $( constants $)
+$v ph ps ch $.
+wph $f wff ph $.   
+wps $f wff ps $.
+wch $f wff ch $.
+wi $a wff ( ph -> ps ) $.
+${
+$d ph ps $.
+$d ph ch $.
+min $e |- ph $.
+maj $e |- ( ph -> ps ) $.
+      $( Modus ponens, with a comment long enough to be refilled over more than one line.
+  Second sentence.
+
+  Second paragraph. $)
+ax-mp $a |- ps $.
+$}
+${ mp2.1 $e |- ( ph -> ( ph -> ( ph -> ( ph -> ( ph -> ( ph -> ( ph -> ph ) ) ) ) ) ) ) $.
+mp2 $p |- ps $= ( wi ax-mp ) ABCDEFGHIJKLMNOPQRSTABCDEFGHIJKLMNOPQRSTABCDEFGHIJKLMNOPQRSTABCDEFGHIJ $.
+$}
+
+
+";
+
+const FORMATTED_DB: &str = "$( Header comment
+   in the first column $)
+  $c |- wff ( ) -> $.  $( constants $)
+  $v ph ps ch $.
+  wph $f wff ph $.
+  wps $f wff ps $.
+  wch $f wff ch $.
+  wi $a wff ( ph -> ps ) $.
+  ${
+    $d ph ps $.  $d ph ch $.
+    min $e |- ph $.
+    maj $e |- ( ph -> ps ) $.
+    $( Modus ponens, with a comment long enough to be refilled over more than
+       one line.  Second sentence.
+
+       Second paragraph. $)
+    ax-mp $a |- ps $.
+  $}
+  ${ mp2.1 $e |- ( ph -> ( ph -> ( ph -> ( ph -> ( ph -> ( ph -> ( ph -> ph ) )
+        ) ) ) ) ) $.
+    mp2 $p |- ps $=
+      ( wi ax-mp ) ABCDEFGHIJKLMNOPQRSTABCDEFGHIJKLMNOPQRSTABCDEFGHIJKLMNOPQRST
+      ABCDEFGHIJ $.
+  $}
+";
+
+#[test]
+fn test_format_source() {
+    let db = mkdb_edit(UNFORMATTED_DB.as_bytes());
+    let changes = db.format_source(FormatOptions::default());
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].name, "test.mm");
+    assert_eq!(changes[0].old.as_deref(), Some(UNFORMATTED_DB.as_bytes()));
+    assert_eq!(String::from_utf8_lossy(&changes[0].new), FORMATTED_DB);
+
+    let db = mkdb_edit(FORMATTED_DB.as_bytes());
+    assert!(db.format_source(FormatOptions::default()).is_empty());
+}
+
+#[test]
+fn test_format_unterminated_comment() {
+    // a comment without `$)` is kept as it is, and not closed
+    for comment in ["$(", "$( x", "$( x\n  y"] {
+        let db = mkdb_edit(format!("$c a $.\n  {comment}").as_bytes());
+        for change in db.format_source(FormatOptions::default()) {
+            let new = String::from_utf8(change.new).unwrap();
+            assert!(new.trim_end().ends_with(comment));
+        }
+    }
+}
+
+#[test]
+fn test_format_source_indent() {
+    let db = mkdb_edit(PROGRESS_DB);
+    let options = FormatOptions {
+        line_width: 30,
+        indent: 4,
+    };
+    let changes = db.format_source(options);
+    assert_eq!(
+        String::from_utf8_lossy(&changes[0].new),
+        "    $c |- wff ( ) -> $.
+    $v ph ps $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps )
+            $.
+        ax-mp $a |- ps $.
+    $}
+    ${
+        mp2.1 $e |- ph $.
+        mp2.2 $e |- ( ph -> ps
+            ) $.
+        mp2 $p |- ps $=
+          wph wps mp2.1 mp2.2
+          ax-mp $.
+    $}
+"
+    );
+}
@@ -18,11 +18,16 @@
 //      in the formula to be substituted.
 
 use crate::bit_set::Bitset;
+use crate::grammar::Grammar;
 use crate::nameck::Atom;
 use crate::nameck::Nameset;
 use crate::parser::as_str;
+use crate::parser::Comparer;
+use crate::parser::StatementType;
 use crate::parser::SymbolType;
 use crate::parser::TokenIter;
+use crate::parser::NO_STATEMENT;
+use crate::scopeck::Frame;
 use crate::scopeck::Hyp;
 use crate::segment_set::SegmentSet;
 use crate::tree::NodeId;
@@ -33,6 +38,8 @@ use crate::util::HashMap;
 use crate::verify::ProofBuilder;
 use crate::Database;
 use core::ops::Index;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::iter::FromIterator;
@@ -48,6 +55,173 @@ pub type Symbol = Atom;
 /// An atom representing a label (nameck suggests `LAtom` for this)
 pub type Label = Atom;
 
+/// The number of low bits of a work variable atom holding its number
+const WORK_VARIABLE_NUMBER_BITS: u32 = 23;
+
+/// A work variable, standing for a formula of a given typecode which is not
+/// known yet, like the work variables `&W1` or `&C2` of mmj2.
+///
+/// Work variables are not declared in the database. In formulas, they are
+/// variable nodes labelled with atoms which the nameset never allocates,
+/// encoding the index of the typecode of the work variable in the grammar,
+/// and its number. A work variable is named after its typecode: `&`, the
+/// shortest prefix of the typecode in upper case which no other typecode
+/// shares, and its number, like `&W1` for the first work variable of typecode
+/// `wff`, or `&SETV1` for typecode `setvar` if there is a typecode `set`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WorkVariable(Atom);
+
+impl WorkVariable {
+    /// Returns the work variable with the given typecode and number,
+    /// or `None` if the typecode is not one of the grammar or the number is
+    /// out of range.
+    #[must_use]
+    pub fn new(grammar: &Grammar, typecode: TypeCode, number: u32) -> Option<Self> {
+        let index = grammar.typecode_index(typecode)? as u32;
+        if number == 0
+            || number >= 1 << WORK_VARIABLE_NUMBER_BITS
+            || index >= Atom::RESERVED >> WORK_VARIABLE_NUMBER_BITS
+        {
+            return None;
+        }
+        Some(WorkVariable(Atom::from_raw(
+            Atom::RESERVED | index << WORK_VARIABLE_NUMBER_BITS | number,
+        )))
+    }
+
+    /// Returns the work variable the given atom stands for, if any.
+    #[must_use]
+    pub const fn from_atom(atom: Atom) -> Option<Self> {
+        if atom.raw() >= Atom::RESERVED {
+            Some(WorkVariable(atom))
+        } else {
+            None
+        }
+    }
+
+    /// Parses the name of a work variable, like `&W1`.
+    #[must_use]
+    pub fn from_name(db: &Database, name: &str) -> Option<Self> {
+        let name = name.strip_prefix('&')?;
+        let grammar = db.grammar_result();
+        let typecodes = grammar.typecodes();
+        let mut found = typecodes.iter().filter_map(|&typecode| {
+            let number = name
+                .strip_prefix(typecode_prefix(db, typecode).as_str())?
+                .parse()
+                .ok()?;
+            Some((typecode, number))
+        });
+        let (typecode, number) = found.next()?;
+        if found.any(|(other, _)| other != typecode) {
+            return None;
+        }
+        Self::new(grammar, typecode, number)
+    }
+
+    /// The atom labelling this work variable in formulas, both as a label and as a symbol.
+    #[must_use]
+    pub const fn atom(self) -> Atom {
+        self.0
+    }
+
+    /// The number of this work variable, unique among the work variables of its typecode.
+    #[must_use]
+    pub const fn number(self) -> u32 {
+        self.0.raw() & ((1 << WORK_VARIABLE_NUMBER_BITS) - 1)
+    }
+
+    /// The index of the typecode of this work variable in the grammar
+    const fn typecode_index(self) -> usize {
+        ((self.0.raw() & !Atom::RESERVED) >> WORK_VARIABLE_NUMBER_BITS) as usize
+    }
+
+    /// The typecode of this work variable, `None` if it was not built for this grammar.
+    #[must_use]
+    pub fn typecode(self, grammar: &Grammar) -> Option<TypeCode> {
+        grammar.typecode_at(self.typecode_index())
+    }
+
+    /// The name of this work variable, like `&W1`.
+    #[must_use]
+    pub fn name(self, db: &Database) -> String {
+        let prefix = self
+            .typecode(db.grammar_result())
+            .map_or_else(|| "?".to_owned(), |typecode| typecode_prefix(db, typecode));
+        format!("&{prefix}{}", self.number())
+    }
+}
+
+/// Returns the prefix of the names of the work variables of a typecode: the
+/// shortest prefix of the typecode in upper case which is not a prefix of
+/// another typecode, or the whole typecode if there is none.
+fn typecode_prefix(db: &Database, typecode: TypeCode) -> String {
+    let nset = db.name_result();
+    let upper = |typecode| String::from_utf8_lossy(nset.atom_name(typecode)).to_uppercase();
+    let name = upper(typecode);
+    let others = db
+        .grammar_result()
+        .typecodes()
+        .iter()
+        .map(|&other| upper(other))
+        .filter(|other| *other != name)
+        .collect::<Vec<_>>();
+    name.char_indices()
+        .map(|(ix, c)| &name[..ix + c.len_utf8()])
+        .find(|prefix| others.iter().all(|other| !other.starts_with(prefix)))
+        .unwrap_or(&name)
+        .to_owned()
+}
+
+/// Allocates fresh work variables, numbered from 1 for each typecode.
+#[derive(Clone, Debug, Default)]
+pub struct WorkVariables(HashMap<usize, u32>);
+
+impl WorkVariables {
+    /// Returns a work variable of the given typecode which was not allocated
+    /// nor reserved yet, or `None` if the typecode is not one of the grammar.
+    pub fn fresh(&mut self, grammar: &Grammar, typecode: TypeCode) -> Option<WorkVariable> {
+        let index = grammar.typecode_index(typecode)?;
+        let last = self.0.entry(index).or_default();
+        let work_variable = WorkVariable::new(grammar, typecode, *last + 1)?;
+        *last += 1;
+        Some(work_variable)
+    }
+
+    /// Reserves the work variables occurring in the given formula, so that
+    /// they are not allocated again.
+    pub fn reserve(&mut self, formula: &Formula) {
+        for work_variable in formula.work_variables() {
+            let last = self.0.entry(work_variable.typecode_index()).or_default();
+            *last = (*last).max(work_variable.number());
+        }
+    }
+}
+
+/// Returns the name of a symbol of a formula, which may be a work variable.
+pub(crate) fn symbol_name(db: &Database, symbol: Symbol) -> Cow<'_, str> {
+    WorkVariable::from_atom(symbol).map_or_else(
+        || Cow::Borrowed(as_str(db.name_result().atom_name(symbol))),
+        |work_variable| Cow::Owned(work_variable.name(db)),
+    )
+}
+
+/// Returns the typecode of a variable, given the label of its floating hypothesis,
+/// or of a work variable.
+fn variable_typecode(db: &Database, label: Label) -> Option<TypeCode> {
+    if let Some(work_variable) = WorkVariable::from_atom(label) {
+        return work_variable.typecode(db.grammar_result());
+    }
+    let nset = db.name_result();
+    let address = nset.lookup_label(nset.atom_name(label))?.address;
+    let sref = db.parse_result().statement(address);
+    if sref.math_len() == 0 {
+        return None;
+    }
+    let typecode = nset.lookup_symbol(sref.math_at(0).slice)?.atom;
+    Some(typecode)
+}
+
 #[derive(Clone, Default)]
 /// A set of substitutions, mapping variables to a formula
 /// We also could have used `dyn Index<&Label, Output=Box<Formula>>`
@@ -84,12 +258,257 @@ impl Substitutions {
         self.0.extend(substitutions.0.clone());
     }
 
+    /// Iterates over the substituted variables and their substitutions, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Label, &Formula)> + '_ {
+        self.0.iter().map(|(&label, formula)| (label, formula))
+    }
+
     /// Gets the formula the given label is to be substituted with.
     #[inline]
     #[must_use]
     pub fn get(&self, label: Label) -> Option<&Formula> {
         self.0.get(&label)
     }
+
+    /// Extends these substitutions into a most general unifier of the two
+    /// given formulas, i.e. the most general substitutions making them equal.
+    ///
+    /// Unlike [`Formula::unify`], the variables of both formulas (database
+    /// variables as well as work variables) may be substituted, so variables
+    /// which are not meant to be shared between the two formulas shall be
+    /// renamed first, e.g. with [`Formula::instantiate`].
+    /// When a work variable is unified with a database variable, the work
+    /// variable is the one substituted.
+    /// On success, the substitutions are normalized, see [`Self::normalize`].
+    /// Returns `false` if the formulas cannot be unified, in which case the
+    /// substitutions may have been partially extended.
+    pub fn unify(&mut self, formula: &Formula, other: &Formula) -> bool {
+        self.unify_all(&[(formula, other)])
+    }
+
+    /// Extends these substitutions into a most general unifier of all the
+    /// given pairs of formulas simultaneously, like a theorem's hypotheses
+    /// and conclusion against the formulas of a goal.
+    /// See [`Self::unify`].
+    pub fn unify_all(&mut self, pairs: &[(&Formula, &Formula)]) -> bool {
+        for (formula, other) in pairs {
+            if self
+                .sub_unify(formula, formula.root, other, other.root)
+                .is_none()
+            {
+                return false;
+            }
+        }
+        self.normalize();
+        true
+    }
+
+    /// Unifies two sub-formulas, resolving the variables which already have a
+    /// substitution and adding substitutions in triangular form.
+    fn sub_unify(
+        &mut self,
+        formula: &Formula,
+        node_id: NodeId,
+        other: &Formula,
+        other_node_id: NodeId,
+    ) -> Option<()> {
+        if let Some(bound) = self.resolve(formula, node_id) {
+            return self.sub_unify(&bound, bound.root, other, other_node_id);
+        }
+        if let Some(bound) = self.resolve(other, other_node_id) {
+            return self.sub_unify(formula, node_id, &bound, bound.root);
+        }
+        let label = formula.tree[node_id];
+        let other_label = other.tree[other_node_id];
+        match (
+            formula.is_variable(node_id),
+            other.is_variable(other_node_id),
+        ) {
+            (true, true) if label == other_label => Some(()),
+            (true, true)
+                if WorkVariable::from_atom(label).is_none()
+                    && WorkVariable::from_atom(other_label).is_some() =>
+            {
+                // Rather substitute work variables with database variables than the opposite
+                self.bind(other_label, formula, node_id)
+            }
+            (true, _) => self.bind(label, other, other_node_id),
+            (false, true) => self.bind(other_label, formula, node_id),
+            (false, false) => {
+                let has_children = formula.tree.has_children(node_id);
+                if label != other_label || has_children != other.tree.has_children(other_node_id) {
+                    return None;
+                }
+                for (s_id, o_id) in formula
+                    .tree
+                    .children_iter(node_id)
+                    .zip(other.tree.children_iter(other_node_id))
+                {
+                    self.sub_unify(formula, s_id, other, o_id)?;
+                }
+                Some(())
+            }
+        }
+    }
+
+    /// Returns the formula substituted for the given node, if it is a variable
+    /// which has a substitution.
+    fn resolve(&self, formula: &Formula, node_id: NodeId) -> Option<Formula> {
+        if formula.is_variable(node_id) {
+            self.0.get(&formula.tree[node_id]).cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Substitutes a variable with a sub-formula, unless the variable occurs
+    /// in it.
+    fn bind(&mut self, label: Label, formula: &Formula, node_id: NodeId) -> Option<()> {
+        if self.occurs(label, formula, node_id) {
+            return None;
+        }
+        self.0.insert(label, formula.sub_formula(node_id));
+        Some(())
+    }
+
+    /// Returns whether the given variable occurs in a sub-formula, once
+    /// substitutions are performed.
+    fn occurs(&self, label: Label, formula: &Formula, node_id: NodeId) -> bool {
+        if formula.is_variable(node_id) {
+            if formula.tree[node_id] == label {
+                return true;
+            }
+            if let Some(bound) = self.0.get(&formula.tree[node_id]) {
+                return self.occurs(label, bound, bound.root);
+            }
+        }
+        formula
+            .tree
+            .children_iter(node_id)
+            .any(|child_node_id| self.occurs(label, formula, child_node_id))
+    }
+
+    /// Normalizes these substitutions, performing substitutions in the
+    /// substituted formulas until no substituted variable occurs in them.
+    /// Applying normalized substitutions twice is the same as applying them once.
+    /// The substitutions shall not be cyclic.
+    pub fn normalize(&mut self) {
+        loop {
+            let pending = self
+                .0
+                .iter()
+                .filter(|(_, formula)| {
+                    formula
+                        .variables()
+                        .iter()
+                        .any(|label| self.0.contains_key(label))
+                })
+                .map(|(&label, _)| label)
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                return;
+            }
+            for label in pending {
+                let formula = self.0[&label].substitute(self);
+                self.0.insert(label, formula);
+            }
+        }
+    }
+
+    /// Returns the composition of these substitutions with the given ones,
+    /// i.e. the substitutions equivalent to applying these substitutions,
+    /// and then the other ones.
+    #[must_use]
+    pub fn compose(&self, other: &Substitutions) -> Substitutions {
+        let mut composition = Substitutions(
+            self.0
+                .iter()
+                .map(|(&label, formula)| (label, formula.substitute(other)))
+                .collect(),
+        );
+        for (&label, formula) in &other.0 {
+            composition
+                .0
+                .entry(label)
+                .or_insert_with(|| formula.clone());
+        }
+        composition
+    }
+
+    /// Substitutes each work variable occurring in the given formulas, and
+    /// which has no substitution yet, with a distinct variable of the same
+    /// typecode among `variables`, which neither occurs in the formulas nor
+    /// is one of the `avoid` variables.  The variables are tried in order;
+    /// see [`scoped_variables`] for the variables in scope at a theorem.
+    /// Variables are given by the labels of their floating hypotheses.
+    /// Returns `false` if there are not enough variables,
+    /// in which case the substitutions may have been partially extended.
+    pub fn eliminate_work_variables(
+        &mut self,
+        db: &Database,
+        variables: &[Label],
+        formulas: &[&Formula],
+        avoid: &[Label],
+    ) -> bool {
+        let mut used = avoid.to_vec();
+        let mut work_variables = vec![];
+        for formula in formulas {
+            for label in formula.variables() {
+                if WorkVariable::from_atom(label).is_some() {
+                    if !self.0.contains_key(&label) && !work_variables.contains(&label) {
+                        work_variables.push(label);
+                    }
+                } else if !used.contains(&label) {
+                    used.push(label);
+                }
+            }
+        }
+        for label in work_variables {
+            let typecode = variable_typecode(db, label);
+            let variable = variables.iter().copied().find(|&candidate| {
+                !used.contains(&candidate) && variable_typecode(db, candidate) == typecode
+            });
+            if let Some((variable, typecode)) = variable.zip(typecode) {
+                used.push(variable);
+                self.0
+                    .insert(label, Formula::from_variable(variable, typecode));
+            } else {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Returns the labels of the floating hypotheses in scope at a frame, those
+/// of the variables of the frame first, and then in database order.
+///
+/// This walks the whole database: compute it once per theorem for
+/// [`Substitutions::eliminate_work_variables`].
+#[must_use]
+pub fn scoped_variables(db: &Database, frame: &Frame) -> Vec<Label> {
+    let sset = db.parse_result();
+    let nset = db.name_result();
+    let scope = db.scope_result();
+    let pos = frame.valid.start;
+    let mut variables: Vec<(Label, Atom)> = db
+        .statements()
+        .filter(|sref| sref.statement_type() == StatementType::Floating && sref.math_len() == 2)
+        .filter_map(|sref| {
+            let valid = scope.get(sref.label())?.valid;
+            let in_scope = sset.order.cmp(&pos, &valid.start) == Ordering::Greater
+                && (valid.end == NO_STATEMENT
+                    || pos.segment_id == valid.start.segment_id && pos.index < valid.end);
+            if !in_scope {
+                return None;
+            }
+            let label = nset.lookup_label(sref.label())?.atom;
+            let variable = nset.lookup_symbol(sref.math_at(1).slice)?.atom;
+            Some((label, variable))
+        })
+        .collect();
+    variables.sort_by_key(|&(_, variable)| !frame.var_list.contains(&variable));
+    variables.into_iter().map(|(label, _)| label).collect()
 }
 
 /// A [`Substitutions`] reference in the context of a [`Database`].
@@ -112,10 +531,7 @@ impl<'a> Debug for SubstitutionsRef<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut dm = f.debug_map();
         for (label, formula) in &self.substitutions.0 {
-            dm.entry(
-                &as_str(self.db.name_result().atom_name(*label)),
-                &formula.as_ref(self.db),
-            );
+            dm.entry(&symbol_name(self.db, *label), &formula.as_ref(self.db));
         }
         dm.finish()
     }
@@ -138,6 +554,29 @@ impl Formula {
         FormulaRef { db, formula: self }
     }
 
+    /// Builds the formula made of a single variable, given by the label of its
+    /// floating hypothesis (or the atom of a work variable), and its typecode.
+    #[must_use]
+    pub fn from_variable(label: Label, typecode: TypeCode) -> Formula {
+        let mut formula_builder = FormulaBuilder::default();
+        formula_builder.reduce(label, 0, 0, true);
+        formula_builder.build(typecode)
+    }
+
+    /// Builds the formula made of a single work variable, or `None` if the
+    /// work variable was not built for this grammar.
+    #[must_use]
+    pub fn from_work_variable(grammar: &Grammar, work_variable: WorkVariable) -> Option<Formula> {
+        let typecode = work_variable.typecode(grammar)?;
+        Some(Self::from_variable(work_variable.atom(), typecode))
+    }
+
+    /// Returns the typecode of this formula.
+    #[must_use]
+    pub const fn typecode(&self) -> TypeCode {
+        self.typecode
+    }
+
     /// Debug only, dumps the internal structure of the formula.
     pub fn dump(&self, nset: &Nameset) {
         println!("  Root: {}", self.root);
@@ -156,6 +595,87 @@ impl Formula {
         Some(self.tree[node_id])
     }
 
+    /// Returns the labels of the variables occurring in this formula, each
+    /// once, in order of first appearance.
+    #[must_use]
+    pub fn variables(&self) -> Vec<Label> {
+        let mut variables = Vec::new();
+        self.sub_variables(self.root, &mut variables);
+        variables
+    }
+
+    /// Returns the nodes of this formula in prefix order, each as its label,
+    /// its number of children, and whether it is a variable.
+    pub(crate) fn preorder(&self) -> Vec<(Label, usize, bool)> {
+        let mut nodes = Vec::new();
+        self.sub_preorder(self.root, &mut nodes);
+        nodes
+    }
+
+    /// Collects the nodes of a sub-formula in prefix order
+    fn sub_preorder(&self, node_id: NodeId, nodes: &mut Vec<(Label, usize, bool)>) {
+        let index = nodes.len();
+        nodes.push((self.tree[node_id], 0, self.is_variable(node_id)));
+        for child_node_id in self.tree.children_iter(node_id) {
+            nodes[index].1 += 1;
+            self.sub_preorder(child_node_id, nodes);
+        }
+    }
+
+    /// Returns the work variables occurring in this formula, each once,
+    /// in order of first appearance.
+    #[must_use]
+    pub fn work_variables(&self) -> Vec<WorkVariable> {
+        self.variables()
+            .into_iter()
+            .filter_map(WorkVariable::from_atom)
+            .collect()
+    }
+
+    /// Returns whether work variables occur in this formula.
+    #[must_use]
+    pub fn has_work_variables(&self) -> bool {
+        !self.work_variables().is_empty()
+    }
+
+    /// Substitutes each variable of this formula which has no substitution
+    /// yet with a fresh work variable of the same typecode.
+    /// This is typically used on an assertion, to represent the result of
+    /// applying it while some of its variables are not determined.
+    /// Work variables already occurring in this formula are left as is.
+    pub fn instantiate(
+        &self,
+        db: &Database,
+        substitutions: &mut Substitutions,
+        work_variables: &mut WorkVariables,
+    ) {
+        let grammar = db.grammar_result();
+        for label in self.variables() {
+            if WorkVariable::from_atom(label).is_some() || substitutions.0.contains_key(&label) {
+                continue;
+            }
+            if let Some(formula) = variable_typecode(db, label)
+                .and_then(|typecode| work_variables.fresh(grammar, typecode))
+                .and_then(|work_variable| Formula::from_work_variable(grammar, work_variable))
+            {
+                substitutions.0.insert(label, formula);
+            }
+        }
+    }
+
+    /// Collects the variables of a sub-formula
+    fn sub_variables(&self, node_id: NodeId, variables: &mut Vec<Label>) {
+        if self.is_variable(node_id) {
+            let label = self.tree[node_id];
+            if !variables.contains(&label) {
+                variables.push(label);
+            }
+        }
+        for child_node_id in self.tree.children_iter(node_id) {
+            self.sub_variables(child_node_id, variables);
+        }
+    }
+
     #[inline]
     /// Returns whether the node given by `node_id` is a variable.
     fn is_variable(&self, node_id: NodeId) -> bool {
@@ -194,6 +714,26 @@ impl Formula {
         Some(Box::new(substitutions))
     }
 
+    /// Returns the most general unifier of this formula and the given one,
+    /// where the variables of both formulas may be substituted.
+    /// See [`Substitutions::unify`].
+    #[must_use]
+    pub fn most_general_unifier(&self, other: &Formula) -> Option<Box<Substitutions>> {
+        let mut substitutions = Substitutions::default();
+        substitutions
+            .unify(self, other)
+            .then(|| Box::new(substitutions))
+    }
+
+    /// Unify this formula with the given formula model, extending the given
+    /// substitutions, which the variables of the model must agree with.
+    /// Returns `false` if the formulas cannot be unified, in which case the
+    /// substitutions may have been partially extended.
+    pub fn unify_with(&self, other: &Formula, substitutions: &mut Substitutions) -> bool {
+        self.sub_unify(self.root, other, other.root, substitutions)
+            .is_some()
+    }
+
     /// Unify a sub-formula
     fn sub_unify(
         &self,
@@ -318,6 +858,7 @@ impl<'a> FormulaRef<'a> {
         let mut f = Flatten {
             formula: self.formula,
             stack: vec![],
+            work_variable: None,
             sset: self.db.parse_result(),
             nset: self.db.name_result(),
         };
@@ -335,9 +876,8 @@ impl<'a> FormulaRef<'a> {
     /// See [`crate::verify`] for more about this format.
     pub fn append_to_stack_buffer(self, stack_buffer: &mut Vec<u8>) -> Range<usize> {
         let tos = stack_buffer.len();
-        let nset = &**self.db.name_result();
         for symbol in self {
-            fast_extend(stack_buffer, nset.atom_name(symbol));
+            fast_extend(stack_buffer, symbol_name(self.db, symbol).as_bytes());
             *stack_buffer.last_mut().unwrap() |= 0x80;
         }
         let n_tos = stack_buffer.len();
@@ -350,11 +890,13 @@ impl<'a> FormulaRef<'a> {
     /// This methos builds such a syntax proof for the formula into a [`crate::proof::ProofTree`],
     /// stores that proof tree in the provided [`ProofBuilder`] `arr`,
     /// and returns the index of that `ProofTree` within `arr`.
+    /// Returns `None` if the formula contains work variables, which have no
+    /// syntax proof.
     pub fn build_syntax_proof<I: Copy, A: Default + FromIterator<I>>(
         self,
         stack_buffer: &mut Vec<u8>,
         arr: &mut dyn ProofBuilder<Item = I, Accum = A>,
-    ) -> I {
+    ) -> Option<I> {
         self.sub_build_syntax_proof(self.root, stack_buffer, arr)
     }
 
@@ -370,17 +912,21 @@ impl<'a> FormulaRef<'a> {
         node_id: NodeId,
         stack_buffer: &mut Vec<u8>,
         arr: &mut dyn ProofBuilder<Item = I, Accum = A>,
-    ) -> I {
+    ) -> Option<I> {
         let nset = self.db.name_result();
 
-        let token = nset.atom_name(self.tree[node_id]);
-        let address = nset.lookup_label(token).unwrap().address;
-        let frame = self.db.scope_result().get(token).unwrap();
+        let label = self.tree[node_id];
+        if WorkVariable::from_atom(label).is_some() {
+            return None;
+        }
+        let token = nset.atom_name(label);
+        let address = nset.lookup_label(token)?.address;
+        let frame = self.db.scope_result().get(token)?;
         let children_hyps = self
             .tree
             .children_iter(node_id)
             .map(|s_id| self.sub_build_syntax_proof(s_id, stack_buffer, arr))
-            .collect::<Box<[I]>>();
+            .collect::<Option<Box<[I]>>>()?;
         let hyps = frame
             .hypotheses
             .iter()
@@ -393,7 +939,7 @@ impl<'a> FormulaRef<'a> {
             })
             .collect();
         let range = self.append_to_stack_buffer(stack_buffer);
-        arr.build(address, hyps, stack_buffer, range)
+        Some(arr.build(address, hyps, stack_buffer, range))
     }
 }
 
@@ -412,13 +958,8 @@ struct SubFormulaRef<'a> {
 
 impl<'a> Debug for SubFormulaRef<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let label_name = as_str(
-            self.f_ref
-                .db
-                .name_result()
-                .atom_name(self.f_ref.formula.tree[self.node_id]),
-        );
-        let mut dt = f.debug_tuple(label_name);
+        let label_name = symbol_name(self.f_ref.db, self.f_ref.formula.tree[self.node_id]);
+        let mut dt = f.debug_tuple(&label_name);
         for s_id in self.f_ref.formula.tree.children_iter(self.node_id) {
             dt.field(&SubFormulaRef {
                 node_id: s_id,
@@ -437,6 +978,7 @@ impl<'a> Debug for SubFormulaRef<'a> {
 pub struct Flatten<'a> {
     formula: &'a Formula,
     stack: Vec<(TokenIter<'a>, Option<SiblingIter<'a, Label>>)>,
+    work_variable: Option<Symbol>,
     sset: &'a SegmentSet,
     nset: &'a Nameset,
 }
@@ -444,6 +986,11 @@ pub struct Flatten<'a> {
 impl<'a> Flatten<'a> {
     fn step_into(&mut self, node_id: NodeId) {
         let label = self.formula.tree[node_id];
+        if WorkVariable::from_atom(label).is_some() {
+            // Work variables are their own single symbol
+            self.work_variable = Some(label);
+            return;
+        }
         let sref = self.sset.statement(
             self.nset
                 .lookup_label(self.nset.atom_name(label))
@@ -465,6 +1012,9 @@ impl<'a> Iterator for Flatten<'a> {
     type Item = Symbol;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(work_variable) = self.work_variable.take() {
+            return Some(work_variable);
+        }
         if self.stack.is_empty() {
             return None;
         }
@@ -500,7 +1050,7 @@ impl<'a> Display for FormulaRef<'a> {
         let nset = &**self.db.name_result();
         write!(f, "{}", as_str(nset.atom_name(self.typecode)))?;
         for symbol in *self {
-            write!(f, " {}", as_str(nset.atom_name(symbol)))?;
+            write!(f, " {}", symbol_name(self.db, symbol))?;
         }
         Ok(())
     }
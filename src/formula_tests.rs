@@ -1,3 +1,4 @@
+use crate::formula::{scoped_variables, Substitutions, WorkVariable, WorkVariables};
 use crate::grammar_tests::mkdb;
 use crate::parser::as_str;
 
@@ -85,3 +86,228 @@ fn test_substitute() {
     let result = stmt.substitute(&subst);
     assert!(result == *formula);
 }
+
+/// Parses a math string, which may contain work variables
+fn parse(db: &crate::Database, math: &str) -> crate::Formula {
+    let nset = db.name_result();
+    let mut symbols = math.split_whitespace().map(|token| {
+        nset.lookup_symbol(token.as_bytes())
+            .map(|symbol| symbol.atom)
+            .or_else(|| WorkVariable::from_name(db, token).map(WorkVariable::atom))
+            .unwrap()
+    });
+    let typecode = symbols.next().unwrap();
+    db.grammar_result()
+        .parse_math_string(typecode, &mut symbols, nset)
+        .unwrap()
+}
+
+#[test]
+/// Work variables are parsed and displayed like variables
+fn test_parse_work_variables() {
+    let mut db = mkdb(FORMULA_DB);
+    db.stmt_parse_pass();
+    let formula = parse(&db, "|- ( &C1 + 1 ) = &C2");
+    assert_eq!(formula.as_ref(&db).to_string(), "wff ( &C1 + 1 ) = &C2");
+    let work_variables = formula.work_variables();
+    assert_eq!(work_variables.len(), 2);
+    assert_eq!(work_variables[1].name(&db), "&C2");
+    assert!(formula.has_work_variables());
+    assert_eq!(parse(&db, "|- &W3").as_ref(&db).to_string(), "wff &W3");
+    assert!(!parse(&db, "|- A = 1").has_work_variables());
+    assert!(WorkVariable::from_name(&db, "&X1").is_none());
+    assert!(WorkVariable::from_name(&db, "&\u{e9}1").is_none());
+    assert!(WorkVariable::from_name(&db, "&C").is_none());
+}
+
+#[test]
+/// Typecodes starting with the same letter have distinct work variable names
+fn test_work_variable_prefixes() {
+    let mut db = mkdb(
+        b"
+    $c |- wff set setvar ( ) $.
+    $( $j syntax 'wff'; syntax 'set'; syntax 'setvar'; syntax '|-' as 'wff'; $)
+    $v x y $.
+    vx $f set x $.
+    vy $f setvar y $.
+    wxy $a wff ( x y ) $.
+",
+    );
+    db.stmt_parse_pass();
+    let nset = db.name_result().clone();
+    let grammar = db.grammar_result();
+    for (typecode, name) in [("wff", "&W1"), ("set", "&SET1"), ("setvar", "&SETV1")] {
+        let typecode = nset.lookup_symbol(typecode.as_bytes()).unwrap().atom;
+        let work_variable = WorkVariable::new(grammar, typecode, 1).unwrap();
+        assert_eq!(work_variable.name(&db), name);
+        assert_eq!(WorkVariable::from_name(&db, name), Some(work_variable));
+    }
+    assert!(WorkVariable::from_name(&db, "&S1").is_none());
+}
+
+#[test]
+/// Goals with work variables unify with assertions, and work variables
+/// can be instantiated by substitution
+fn test_unify_work_variables() {
+    let mut db = mkdb(FORMULA_DB);
+    let stmt_parse = db.stmt_parse_pass().clone();
+    let names = db.name_pass().clone();
+    let axiom = stmt_parse
+        .get_formula(&db.statement("ax-com").unwrap())
+        .unwrap();
+    let goal = parse(&db, "|- ( &C1 + 1 ) = ( 1 + &C1 )");
+    let subst = goal.unify(axiom).unwrap();
+    let a = names.lookup_label(b"cA").unwrap().atom;
+    assert_eq!(subst[a].as_ref(&db).to_string(), "wff &C1");
+
+    let mut instances = Substitutions::default();
+    let c1 = WorkVariable::from_name(&db, "&C1").unwrap();
+    instances.insert(c1.atom(), parse(&db, "class 2"));
+    assert_eq!(
+        goal.substitute(&instances).as_ref(&db).to_string(),
+        "wff ( 2 + 1 ) = ( 1 + 2 )"
+    );
+}
+
+#[test]
+/// Instantiating an assertion introduces fresh work variables,
+/// which can be eliminated again
+fn test_instantiate_eliminate() {
+    let mut db = mkdb(FORMULA_DB);
+    let stmt_parse = db.stmt_parse_pass().clone();
+    let names = db.name_pass().clone();
+    let axiom = stmt_parse
+        .get_formula(&db.statement("ax-com").unwrap())
+        .unwrap();
+    let a = names.lookup_label(b"cA").unwrap().atom;
+    let b = names.lookup_label(b"cB").unwrap().atom;
+
+    let mut work_variables = WorkVariables::default();
+    work_variables.reserve(&parse(&db, "|- &C4 = &C2"));
+    let mut subst = Substitutions::default();
+    subst.insert(a, parse(&db, "class 1"));
+    axiom.instantiate(&db, &mut subst, &mut work_variables);
+    let instance = axiom.substitute(&subst);
+    assert_eq!(
+        instance.as_ref(&db).to_string(),
+        "wff ( 1 + &C5 ) = ( &C5 + 1 )"
+    );
+    let class = db.name_result().lookup_symbol(b"class").unwrap().atom;
+    assert_eq!(
+        work_variables
+            .fresh(db.grammar_result(), class)
+            .unwrap()
+            .name(&db),
+        "&C6"
+    );
+
+    let scope = db.scope_pass().clone();
+    let frame = scope.get(b"formula").unwrap();
+    let variables = scoped_variables(&db, frame);
+    let mut elimination = Substitutions::default();
+    assert!(elimination.eliminate_work_variables(&db, &variables, &[&instance], &[]));
+    assert_eq!(
+        instance.substitute(&elimination).as_ref(&db).to_string(),
+        "wff ( 1 + A ) = ( A + 1 )"
+    );
+    let mut elimination = Substitutions::default();
+    assert!(elimination.eliminate_work_variables(&db, &variables, &[&instance], &[a]));
+    assert_eq!(
+        instance.substitute(&elimination).as_ref(&db).to_string(),
+        "wff ( 1 + B ) = ( B + 1 )"
+    );
+    let two = parse(&db, "|- &C1 = &C2");
+    let mut elimination = Substitutions::default();
+    assert!(!elimination.eliminate_work_variables(&db, &variables, &[&instance, &two], &[b]));
+}
+
+#[test]
+/// The most general unifier of ` ( &C1 + 1 ) = &C2 ` and ` ( A + B ) = ( B + A ) `
+/// is ` &C1 := A `, ` B := 1 ` and ` &C2 := ( 1 + A ) `
+fn test_most_general_unifier() {
+    let mut db = mkdb(FORMULA_DB);
+    let stmt_parse = db.stmt_parse_pass().clone();
+    let names = db.name_pass().clone();
+    let axiom = stmt_parse
+        .get_formula(&db.statement("ax-com").unwrap())
+        .unwrap();
+    let a = names.lookup_label(b"cA").unwrap().atom;
+    let b = names.lookup_label(b"cB").unwrap().atom;
+    let c1 = WorkVariable::from_name(&db, "&C1").unwrap().atom();
+    let c2 = WorkVariable::from_name(&db, "&C2").unwrap().atom();
+    let goal = parse(&db, "|- ( &C1 + 1 ) = &C2");
+    assert!(goal.unify(axiom).is_none());
+    let mgu = goal.most_general_unifier(axiom).unwrap();
+    assert!(mgu.get(a).is_none());
+    assert!(mgu[c1] == parse(&db, "class A"));
+    assert!(mgu[b] == parse(&db, "class 1"));
+    assert!(mgu[c2] == parse(&db, "class ( 1 + A )"));
+    let unified = goal.substitute(&mgu);
+    assert!(unified == axiom.substitute(&mgu));
+    assert_eq!(unified.as_ref(&db).to_string(), "wff ( A + 1 ) = ( 1 + A )");
+
+    // Variables on both sides
+    let left = parse(&db, "|- &C1 = ( &C2 + 1 )");
+    let right = parse(&db, "|- &C2 = &C3");
+    let mgu = left.most_general_unifier(&right).unwrap();
+    assert!(left.substitute(&mgu) == right.substitute(&mgu));
+
+    // Occurs check
+    let cyclic = parse(&db, "|- &C1 = ( &C1 + 1 )");
+    assert!(cyclic
+        .most_general_unifier(&parse(&db, "|- &C2 = &C2"))
+        .is_none());
+    assert!(goal.most_general_unifier(&parse(&db, "|- 1 = 2")).is_none());
+}
+
+#[test]
+/// Unification of several pairs of formulas, like the hypothesis and
+/// conclusion of `addeq1` against the formulas of a goal
+fn test_unify_all() {
+    let mut db = mkdb(FORMULA_DB);
+    let stmt_parse = db.stmt_parse_pass().clone();
+    let names = db.name_pass().clone();
+    let hyp = stmt_parse
+        .get_formula(&db.statement("addeq1.1").unwrap())
+        .unwrap();
+    let conclusion = stmt_parse
+        .get_formula(&db.statement("addeq1").unwrap())
+        .unwrap();
+    let a = names.lookup_label(b"cA").unwrap().atom;
+    let c3 = WorkVariable::from_name(&db, "&C3").unwrap().atom();
+    let goal_hyp = parse(&db, "|- &C1 = 2");
+    let goal = parse(&db, "|- ( 1 + 1 ) = &C3");
+    let mut subst = Substitutions::default();
+    assert!(subst.unify_all(&[(hyp, &goal_hyp), (conclusion, &goal)]));
+    assert!(subst[a] == parse(&db, "class 1"));
+    assert!(subst[c3] == parse(&db, "class ( 2 + 1 )"));
+    assert_eq!(
+        goal_hyp.substitute(&subst).as_ref(&db).to_string(),
+        "wff 1 = 2"
+    );
+
+    let mut subst = Substitutions::default();
+    let other_hyp = parse(&db, "|- 2 = 2");
+    assert!(!subst.unify_all(&[(hyp, &other_hyp), (conclusion, &goal)]));
+}
+
+#[test]
+/// Composing substitutions is the same as applying them in sequence
+fn test_compose() {
+    let mut db = mkdb(FORMULA_DB);
+    db.stmt_parse_pass();
+    let names = db.name_pass().clone();
+    let a = names.lookup_label(b"cA").unwrap().atom;
+    let b = names.lookup_label(b"cB").unwrap().atom;
+    let formula = parse(&db, "|- ( A + B ) = A");
+    let mut first = Substitutions::default();
+    first.insert(a, parse(&db, "class ( B + 1 )"));
+    let mut second = Substitutions::default();
+    second.insert(b, parse(&db, "class 2"));
+    let composition = first.compose(&second);
+    assert!(formula.substitute(&composition) == formula.substitute(&first).substitute(&second));
+    assert_eq!(
+        formula.substitute(&composition).as_ref(&db).to_string(),
+        "wff ( ( 2 + 1 ) + 2 ) = ( 2 + 1 )"
+    );
+}
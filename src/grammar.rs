@@ -4,7 +4,7 @@
 // Possibly: Remove branch/leaf and keep only the optional leaf? (then final leaf = no next node id)
 
 use crate::diag::Diagnostic;
-use crate::formula::{Formula, FormulaBuilder, Label, Symbol, TypeCode};
+use crate::formula::{Formula, FormulaBuilder, Label, Symbol, TypeCode, WorkVariable};
 use crate::nameck::{Atom, NameReader, Nameset};
 use crate::parser::{
     as_str, Segment, SegmentId, StatementAddress, StatementRef, StatementType, SymbolType, Token,
@@ -17,6 +17,7 @@ use std::collections::hash_map::Entry;
 use std::fmt;
 use std::fmt::Formatter;
 use std::sync::Arc;
+use std::time::Instant;
 use tinyvec::ArrayVec;
 
 #[cfg(feature = "dot")]
@@ -453,7 +454,13 @@ impl Grammar {
     }
 
     fn too_short(map: &HashMap<(SymbolType, Atom), NextNode>, nset: &Nameset) -> Diagnostic {
-        let expected_symbol = map.keys().find(|k| k.0 == SymbolType::Constant).unwrap().1;
+        // Prefer a constant, otherwise give the typecode of the expected variable
+        let expected_symbol = map
+            .keys()
+            .find(|k| k.0 == SymbolType::Constant)
+            .or_else(|| map.keys().next())
+            .unwrap()
+            .1;
         let expected_token = nset.atom_name(expected_symbol).into();
         Diagnostic::ParsedStatementTooShort(expected_token)
     }
@@ -1070,6 +1077,16 @@ impl Grammar {
         Ok(())
     }
 
+    /// Returns the typecodes of the sub-formulas expected at a branch
+    fn variable_typecodes(map: &HashMap<(SymbolType, Atom), NextNode>) -> Box<[TypeCode]> {
+        map.keys()
+            .filter_map(|k| match *k {
+                (SymbolType::Variable, typecode) => Some(typecode),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn do_shift(&self, symbol_iter: &mut dyn Iterator<Item = (usize, Symbol)>, nset: &Nameset) {
         if let Some((_ix, symbol)) = symbol_iter.next() {
             if self.debug {
@@ -1109,7 +1126,7 @@ impl Grammar {
 
         let mut formula_builder = FormulaBuilder::default();
         let mut symbol_enum = symbol_iter.enumerate().peekable();
-        let mut ix;
+        let mut ix = 0;
         let mut e = StackElement {
             node_id: self.root,
             expected_typecodes: expected_typecodes
@@ -1120,106 +1137,126 @@ impl Grammar {
         };
         let mut stack = vec![];
         loop {
-            match *self.nodes.get(e.node_id) {
+            let typecode = match *self.nodes.get(e.node_id) {
                 GrammarNode::Leaf { reduce, typecode } => {
                     // We found a leaf: REDUCE
                     Self::do_reduce(&mut formula_builder, reduce, nset);
-
-                    if e.expected_typecodes.contains(&typecode) {
-                        // We found an expected typecode, pop from the stack and continue
-                        if let Some(popped) = stack.pop() {
-                            e = popped;
-                            debug!(
-                                " ++ Finished parsing formula, found typecode {:?}, back to {}",
-                                as_str(nset.atom_name(typecode)),
-                                e.node_id
-                            );
-                            let map = self.get_branch(e.node_id);
-                            match map.get(&(SymbolType::Variable, typecode)) {
-                                Some(NextNode {
-                                    next_node_id,
-                                    leaf_label,
-                                }) => {
-                                    // Found a sub-formula: First optionally REDUCE and continue
-                                    for &reduce in leaf_label {
-                                        Self::do_reduce(&mut formula_builder, reduce, nset);
-                                    }
-
-                                    e.node_id = *next_node_id;
-                                    debug!("   Next Node: {:?}", e.node_id);
-                                }
-                                None => {
-                                    debug!("TODO");
-                                }
+                    typecode
+                }
+                GrammarNode::Branch { ref map } => {
+                    if let Some(&(index, symbol)) = symbol_enum.peek() {
+                        ix = index as i32;
+                        if let Some(work_variable) = WorkVariable::from_atom(symbol) {
+                            if e.node_id != self.root {
+                                // Parse the work variable as a sub-formula
+                                stack.push(e);
+                                e = StackElement {
+                                    node_id: self.root,
+                                    expected_typecodes: Self::variable_typecodes(map),
+                                };
+                                continue;
                             }
-                        } else if symbol_enum.peek().is_none() {
-                            // We popped the last element from the stack and we are at the end of the math string, success
-                            return Ok(formula_builder.build(typecode));
+                            // Work variables are parsed like variables: SHIFT and REDUCE
+                            debug!("   SHIFT work variable {:?}", work_variable);
+                            symbol_enum.next();
+                            formula_builder.reduce(symbol, 0, 0, true);
+                            work_variable
+                                .typecode(self)
+                                .ok_or(Diagnostic::UnparseableStatement(ix))?
                         } else {
-                            // There are still symbols to parse, continue from root
-                            let (next_node_id, leaf_label) =
-                                self.next_var_node(self.root, typecode).unwrap(); // TODO(tirix): error case
-                            for &reduce in leaf_label {
-                                Self::do_reduce(&mut formula_builder, reduce, nset);
+                            debug!("   {:?}", as_str(nset.atom_name(symbol)));
+
+                            if let Some(NextNode {
+                                next_node_id,
+                                leaf_label,
+                            }) = map.get(&(SymbolType::Constant, symbol))
+                            {
+                                // Found an atom matching one of our next nodes: First optionally REDUCE and continue
+                                for &reduce in leaf_label {
+                                    Self::do_reduce(&mut formula_builder, reduce, nset);
+                                }
+
+                                // Found an atom matching one of our next nodes: SHIFT, to the next node
+                                self.do_shift(&mut symbol_enum, nset);
+                                e.node_id = *next_node_id;
+                                debug!("   Next Node: {:?}", e.node_id);
+                            } else {
+                                // No matching constant, search among variables
+                                if map.is_empty() || e.node_id == self.root {
+                                    return Err(Diagnostic::UnparseableStatement(ix));
+                                }
+
+                                debug!(
+                                    " ++ Not in CST map, push stack element and expect {:?}",
+                                    map.keys()
+                                );
+                                stack.push(e);
+                                e = StackElement {
+                                    node_id: self.root,
+                                    expected_typecodes: Self::variable_typecodes(map),
+                                };
                             }
-                            e.node_id = next_node_id;
+                            continue;
                         }
                     } else {
-                        // We have not found the expected typecode, continue from root
-                        debug!(" ++ Wrong type obtained, continue.");
-                        let (next_node_id, leaf_label) =
-                            self.next_var_node(self.root, typecode).unwrap(); // TODO(tirix): error case
-                        for &reduce in leaf_label {
-                            Self::do_reduce(&mut formula_builder, reduce, nset);
-                        }
-                        e.node_id = next_node_id;
+                        return Err(Grammar::too_short(map, nset));
                     }
                 }
-                GrammarNode::Branch { ref map } => {
-                    if let Some(&(index, symbol)) = symbol_enum.peek() {
-                        ix = index as i32;
-                        debug!("   {:?}", as_str(nset.atom_name(symbol)));
+            };
 
-                        if let Some(NextNode {
+            if e.expected_typecodes.contains(&typecode) {
+                // We found an expected typecode, pop from the stack and continue
+                if let Some(popped) = stack.pop() {
+                    e = popped;
+                    debug!(
+                        " ++ Finished parsing formula, found typecode {:?}, back to {}",
+                        as_str(nset.atom_name(typecode)),
+                        e.node_id
+                    );
+                    let map = self.get_branch(e.node_id);
+                    match map.get(&(SymbolType::Variable, typecode)) {
+                        Some(NextNode {
                             next_node_id,
                             leaf_label,
-                        }) = map.get(&(SymbolType::Constant, symbol))
-                        {
-                            // Found an atom matching one of our next nodes: First optionally REDUCE and continue
+                        }) => {
+                            // Found a sub-formula: First optionally REDUCE and continue
                             for &reduce in leaf_label {
                                 Self::do_reduce(&mut formula_builder, reduce, nset);
                             }
 
-                            // Found an atom matching one of our next nodes: SHIFT, to the next node
-                            self.do_shift(&mut symbol_enum, nset);
                             e.node_id = *next_node_id;
                             debug!("   Next Node: {:?}", e.node_id);
-                        } else {
-                            // No matching constant, search among variables
-                            if map.is_empty() || e.node_id == self.root {
-                                return Err(Diagnostic::UnparseableStatement(ix));
-                            }
-
-                            debug!(
-                                " ++ Not in CST map, push stack element and expect {:?}",
-                                map.keys()
-                            );
-                            stack.push(e);
-                            e = StackElement {
-                                node_id: self.root,
-                                expected_typecodes: map
-                                    .keys()
-                                    .filter_map(|k| match *k {
-                                        (SymbolType::Variable, typecode) => Some(typecode),
-                                        _ => None,
-                                    })
-                                    .collect(),
-                            };
                         }
-                    } else {
-                        return Err(Grammar::too_short(map, nset));
+                        None => {
+                            debug!("TODO");
+                        }
                     }
+                } else if symbol_enum.peek().is_none() {
+                    // We popped the last element from the stack and we are at the end of the math string, success
+                    return Ok(formula_builder.build(typecode));
+                } else {
+                    // There are still symbols to parse, continue from root
+                    let (next_node_id, leaf_label) =
+                        self.next_var_node(self.root, typecode).ok_or_else(|| {
+                            // the symbols left cannot be parsed
+                            let index = symbol_enum.peek().map_or(ix, |&(index, _)| index as i32);
+                            Diagnostic::UnparseableStatement(index)
+                        })?;
+                    for &reduce in leaf_label {
+                        Self::do_reduce(&mut formula_builder, reduce, nset);
+                    }
+                    e.node_id = next_node_id;
+                }
+            } else {
+                // We have not found the expected typecode, continue from root
+                debug!(" ++ Wrong type obtained, continue.");
+                let (next_node_id, leaf_label) = self
+                    .next_var_node(self.root, typecode)
+                    .ok_or(Diagnostic::UnparseableStatement(ix))?;
+                for &reduce in leaf_label {
+                    Self::do_reduce(&mut formula_builder, reduce, nset);
                 }
+                e.node_id = next_node_id;
             }
         }
     }
@@ -1236,18 +1273,11 @@ impl Grammar {
 
         // Type token. It is safe to unwrap here since parser has checked for EmptyMathString error.
         let typecode = nset.get_atom(sref.math_at(0).slice);
-        let mut expected_typecode = typecode;
 
         // Skip syntactic axioms
-        if sref.statement_type() == StatementType::Axiom && expected_typecode != self.provable_type
-        {
+        if sref.statement_type() == StatementType::Axiom && typecode != self.provable_type {
             return Ok(None);
         }
-
-        // If this is a provable statement, prove that this is a wff. Otherwise just use the provided typecode
-        if expected_typecode == self.provable_type {
-            expected_typecode = self.logic_type;
-        }
         // At the time of writing, there are only 3 statements which are not provable but "syntactic theorems": weq, wel and bj-0
 
         debug!(
@@ -1259,16 +1289,56 @@ impl Grammar {
             .math_iter()
             .skip(1)
             .map(|token| names.lookup_symbol(token.slice).unwrap().atom);
-        let formula = self.parse_formula(&mut symbol_iter, &[expected_typecode], nset)?;
+        let formula = self.parse_math_string(typecode, &mut symbol_iter, nset)?;
         Ok(Some(formula))
     }
 
+    /// Parses the symbols of a math string following its typecode, as for a
+    /// statement of the database: if the typecode is the provable typecode,
+    /// the symbols are parsed as a formula of the logic typecode.
+    pub fn parse_math_string(
+        &self,
+        typecode: TypeCode,
+        symbol_iter: &mut impl Iterator<Item = Symbol>,
+        nset: &Nameset,
+    ) -> Result<Formula, Diagnostic> {
+        // If this is a provable statement, prove that this is a wff. Otherwise just use the provided typecode
+        let expected_typecode = if typecode == self.provable_type {
+            self.logic_type
+        } else {
+            typecode
+        };
+        self.parse_formula(symbol_iter, &[expected_typecode], nset)
+    }
+
     /// Returns the typecodes allowed in this grammar
     #[must_use]
     pub fn typecodes(&self) -> Box<[TypeCode]> {
         self.typecodes.clone().into_boxed_slice()
     }
 
+    /// Returns the typecode of provable statements, like `|-`
+    #[must_use]
+    pub const fn provable_typecode(&self) -> TypeCode {
+        self.provable_type
+    }
+
+    /// Returns the typecode provable statements are parsed as, like `wff`
+    #[must_use]
+    pub const fn logic_typecode(&self) -> TypeCode {
+        self.logic_type
+    }
+
+    /// Returns the index of the given typecode among the typecodes of this grammar
+    pub(crate) fn typecode_index(&self, typecode: TypeCode) -> Option<usize> {
+        self.typecodes.iter().position(|&t| t == typecode)
+    }
+
+    /// Returns the typecode at the given index among the typecodes of this grammar
+    pub(crate) fn typecode_at(&self, index: usize) -> Option<TypeCode> {
+        self.typecodes.get(index).copied()
+    }
+
     /// Lists the contents of the grammar's parse table. This can be used for debugging.
     pub fn dump(&self, db: &Database) {
         println!("Grammar tree has {:?} nodes.", self.nodes.len());
@@ -1555,22 +1625,33 @@ pub(crate) fn parse_statements(
     grammar: &Arc<Grammar>,
 ) {
     let mut ssrq = Vec::new();
-    for sref in segments.segments() {
+    let srefs = segments.segments();
+    let count = srefs.len();
+    for (index, sref) in srefs.into_iter().enumerate() {
         let segments2 = segments.clone();
         let nset = nset.clone();
         let grammar = grammar.clone();
         let id = sref.id;
-        ssrq.push(segments.exec.exec(sref.bytes(), move || {
-            let sref = segments2.segment(id);
-            let mut names = NameReader::new(&nset);
-            let id = sref.id;
-            (
-                id,
-                Arc::new(parse_statements_single(
-                    &segments2, &nset, &mut names, &grammar, id,
-                )),
-            )
-        }));
+        ssrq.push(
+            segments
+                .exec
+                .exec_segment("stmt_parse", sref.id, sref.bytes(), move || {
+                    let start = Instant::now();
+                    let sref = segments2.segment(id);
+                    let mut names = NameReader::new(&nset);
+                    let id = sref.id;
+                    let res = parse_statements_single(&segments2, &nset, &mut names, &grammar, id);
+                    segments2.progress.segment(
+                        "stmt_parse",
+                        start,
+                        index,
+                        count,
+                        &sref.buffer,
+                        false,
+                    );
+                    (id, Arc::new(res))
+                }),
+        );
     }
 
     stmt_parse.segments.clear();
@@ -0,0 +1,210 @@
+//! Source hygiene checks.
+//!
+//! The hygiene pass reports text which is valid Metamath, but breaks the
+//! conventions which set.mm enforces on its sources: tab characters, white
+//! space at the end of a line, lines longer than 79 characters, and non-ASCII
+//! characters.  The parser reports some of the non-ASCII characters as
+//! `BadCharacter` errors; this pass reports the others as warnings, and
+//! covers the remaining checks which the parser does not make.
+//!
+//! Each check can be turned off, and the line length changed, through
+//! `DbOptions::hygiene`.  Unless `strict_line_length` is set, long lines are
+//! allowed in `$t` comments, whose definitions cannot always be broken, and
+//! when they hold a single word such as a URL, possibly between comment
+//! markers.
+//!
+//! The findings are warnings, attached to the statement in whose text or
+//! leading white space the offending text starts, with a span covering just
+//! that text.  They are reported under `DiagnosticClass::Hygiene`.
+
+use crate::diag::Diagnostic;
+use crate::parser::SegmentRef;
+use crate::parser::Span;
+use crate::parser::StatementAddress;
+use crate::parser::StatementIndex;
+use crate::parser::StatementType;
+use crate::segment_set::SegmentSet;
+use crate::util::HashSet;
+
+/// Selects the checks made by the hygiene pass.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HygieneOptions {
+    /// Report tab characters.
+    pub tabs: bool,
+    /// Report spaces and tabs at the end of a line.
+    pub trailing_whitespace: bool,
+    /// Report lines with more characters than this, or `None` to allow lines
+    /// of any length.
+    pub max_line_length: Option<usize>,
+    /// Report long lines in `$t` comments and lines of a single word as well.
+    pub strict_line_length: bool,
+    /// Report characters outside of US-ASCII.
+    pub non_ascii: bool,
+}
+
+impl Default for HygieneOptions {
+    fn default() -> Self {
+        HygieneOptions {
+            tabs: true,
+            trailing_whitespace: true,
+            max_line_length: Some(79),
+            strict_line_length: false,
+            non_ascii: true,
+        }
+    }
+}
+
+/// The findings of the hygiene pass.
+#[derive(Debug, Default)]
+pub struct HygieneResult {
+    diagnostics: Vec<(StatementAddress, Diagnostic)>,
+}
+
+impl HygieneResult {
+    /// Returns the list of hygiene problems found.
+    #[must_use]
+    pub fn diagnostics(&self) -> Vec<(StatementAddress, Diagnostic)> {
+        self.diagnostics.clone()
+    }
+}
+
+/// Finds the maximal runs of bytes satisfying `pred` in a range of a buffer.
+fn runs(buffer: &[u8], range: Span, pred: impl Fn(u8) -> bool) -> Vec<Span> {
+    let mut out = Vec::new();
+    let mut start = None;
+    let (range_start, range_end) = (range.start as usize, range.end as usize);
+    for (pos, &ch) in buffer.iter().enumerate().take(range_end).skip(range_start) {
+        match (pred(ch), start) {
+            (true, None) => start = Some(pos),
+            (false, Some(run_start)) => {
+                out.push(Span::new(run_start, pos));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(run_start) = start {
+        out.push(Span::new(run_start, range_end));
+    }
+    out
+}
+
+/// Checks the text of one segment.
+fn check_segment(
+    options: &HygieneOptions,
+    sref: SegmentRef<'_>,
+) -> Vec<(StatementIndex, Diagnostic)> {
+    let buffer: &[u8] = &sref.buffer;
+    let range = sref.span();
+    let starts: Vec<usize> = sref
+        .into_iter()
+        .map(|stmt| stmt.span_full().start as usize)
+        .collect();
+    let index_at = |pos: usize| {
+        starts
+            .partition_point(|&start| start <= pos)
+            .saturating_sub(1) as StatementIndex
+    };
+    let in_range = |pos: usize| pos >= range.start as usize && pos < range.end as usize;
+    let mut out = Vec::new();
+
+    if options.tabs {
+        for span in runs(buffer, range, |ch| ch == b'\t') {
+            out.push((
+                index_at(span.start as usize),
+                Diagnostic::TabCharacter(span),
+            ));
+        }
+    }
+
+    if options.non_ascii {
+        let reported: HashSet<usize> = sref
+            .diagnostics
+            .iter()
+            .filter_map(|(_, diag)| match *diag {
+                Diagnostic::BadCharacter(pos, _) => Some(pos),
+                _ => None,
+            })
+            .collect();
+        for span in runs(buffer, range, |ch| ch >= 0x80) {
+            let (start, end) = (span.start as usize, span.end as usize);
+            if (start..end).any(|pos| reported.contains(&pos)) {
+                continue;
+            }
+            out.push((index_at(start), Diagnostic::NonAsciiText(span)));
+        }
+    }
+
+    if options.trailing_whitespace || options.max_line_length.is_some() {
+        // lines which start before the segment may end in it
+        let mut line_start = buffer[..range.start as usize]
+            .iter()
+            .rposition(|&ch| ch == b'\n')
+            .map_or(0, |pos| pos + 1);
+        while line_start < range.end as usize {
+            let next = buffer[line_start..]
+                .iter()
+                .position(|&ch| ch == b'\n')
+                .map_or(buffer.len(), |len| line_start + len);
+            let mut line_end = next;
+            if line_end > line_start && buffer[line_end - 1] == b'\r' {
+                line_end -= 1;
+            }
+            let line = &buffer[line_start..line_end];
+
+            if options.trailing_whitespace {
+                let content = line
+                    .iter()
+                    .rposition(|&ch| ch != b' ' && ch != b'\t')
+                    .map_or(0, |pos| pos + 1);
+                let start = line_start + content;
+                if start < line_end && in_range(start) {
+                    let span = Span::new(start, line_end);
+                    out.push((index_at(start), Diagnostic::TrailingWhitespace(span)));
+                }
+            }
+
+            if let Some(limit) = options.max_line_length {
+                // characters are counted as the bytes which do not continue
+                // a UTF-8 sequence
+                let char_start = line
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &ch)| ch & 0xC0 != 0x80)
+                    .nth(limit);
+                if let Some((offset, _)) = char_start {
+                    let start = line_start + offset;
+                    let single_word = line
+                        .split(u8::is_ascii_whitespace)
+                        .filter(|word| !word.is_empty() && word != b"$(" && word != b"$)")
+                        .nth(1)
+                        .is_none();
+                    let exempt = !options.strict_line_length
+                        && (single_word
+                            || sref.statement(index_at(start)).statement_type()
+                                == StatementType::TypesettingComment);
+                    if in_range(start) && !exempt {
+                        let span = Span::new(start, line_end);
+                        out.push((index_at(start), Diagnostic::LineTooLong(span, limit)));
+                    }
+                }
+            }
+
+            line_start = next + 1;
+        }
+    }
+
+    out.sort_by_key(|(index, _)| *index);
+    out
+}
+
+/// Runs the hygiene checks on all loaded segments.
+pub(crate) fn check(options: &HygieneOptions, sset: &SegmentSet) -> HygieneResult {
+    let mut diagnostics = Vec::new();
+    for sref in sset.segments() {
+        for (index, diag) in check_segment(options, sref) {
+            diagnostics.push((StatementAddress::new(sref.id, index), diag));
+        }
+    }
+    HygieneResult { diagnostics }
+}
@@ -0,0 +1,75 @@
+use crate::database::Database;
+use crate::database::DbOptions;
+use crate::database_tests::mkdb_edit;
+use crate::diag::Diagnostic;
+use crate::diag::DiagnosticClass;
+use crate::hygiene::HygieneOptions;
+use crate::parser::Span;
+
+const HYGIENE_DB: &str = "$c |- wff $.\t
+$v ph $.  
+$( A comment which is long enough to go past the limit of seventy-nine columns $)
+$( http://example.com/a/long/url/which/cannot/be/broken/and/is/allowed/to/stay $)
+$( $t htmldef \"ph\" as '<IMG SRC=\"_varphi.gif\" WIDTH=11 HEIGHT=19 ALT=\" ph\">'; $)
+wph $f wff ph $.
+";
+
+fn hygiene_findings(db: &mut Database) -> Vec<(&'static str, String)> {
+    let text = HYGIENE_DB.as_bytes();
+    db.hygiene_pass()
+        .diagnostics()
+        .into_iter()
+        .map(|(_, diag)| {
+            let (name, span) = match diag {
+                Diagnostic::TabCharacter(span) => ("tab", span),
+                Diagnostic::TrailingWhitespace(span) => ("trailing", span),
+                Diagnostic::LineTooLong(span, _) => ("long", span),
+                Diagnostic::NonAsciiText(span) => ("non-ascii", span),
+                _ => ("other", Span::NULL),
+            };
+            (
+                name,
+                String::from_utf8_lossy(span.as_ref(text)).into_owned(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_hygiene() {
+    let mut db = mkdb_edit(HYGIENE_DB.as_bytes());
+    assert_eq!(
+        hygiene_findings(&mut db),
+        vec![
+            ("tab", "\t".to_owned()),
+            ("trailing", "\t".to_owned()),
+            ("trailing", "  ".to_owned()),
+            ("long", "$)".to_owned()),
+        ]
+    );
+
+    let mut db = Database::new(DbOptions {
+        hygiene: HygieneOptions {
+            tabs: false,
+            max_line_length: Some(70),
+            strict_line_length: true,
+            ..HygieneOptions::default()
+        },
+        ..DbOptions::default()
+    });
+    db.parse(
+        "test.mm".to_owned(),
+        vec![("test.mm".to_owned(), HYGIENE_DB.as_bytes().to_owned())],
+    );
+    assert_eq!(
+        hygiene_findings(&mut db),
+        vec![
+            ("trailing", "\t".to_owned()),
+            ("trailing", "  ".to_owned()),
+            ("long", " columns $)".to_owned()),
+            ("long", "/to/stay $)".to_owned()),
+            ("long", " ph\">'; $)".to_owned()),
+        ]
+    );
+    assert_eq!(db.diag_notations(&[DiagnosticClass::Hygiene]).len(), 5);
+}
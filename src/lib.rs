@@ -54,27 +54,73 @@ mod segment_set;
 mod tree;
 mod util;
 
+pub mod construct;
 pub mod database;
 pub mod diag;
+pub mod dv;
+pub mod edit;
 pub mod export;
+pub mod formatter;
 pub mod formula;
 pub mod grammar;
+pub mod hygiene;
 pub mod line_cache;
+pub mod minimize;
 pub mod nameck;
 pub mod outline;
 pub mod parser;
+pub mod profile;
+pub mod progress;
 pub mod proof;
+pub mod prover;
+pub mod rewrite;
 pub mod scopeck;
+pub mod search;
+pub mod show;
+pub mod snapshot;
+pub mod unify;
 pub mod verify;
+pub mod worksheet;
+pub mod writer;
 
+#[cfg(test)]
+mod construct_tests;
+#[cfg(test)]
+mod database_tests;
+#[cfg(test)]
+mod dv_tests;
+#[cfg(test)]
+mod edit_tests;
+#[cfg(test)]
+mod export_tests;
+#[cfg(test)]
+mod formatter_tests;
 #[cfg(test)]
 mod formula_tests;
 #[cfg(test)]
 mod grammar_tests;
 #[cfg(test)]
+mod hygiene_tests;
+#[cfg(test)]
+mod minimize_tests;
+#[cfg(test)]
 mod parser_tests;
 #[cfg(test)]
+mod prover_tests;
+#[cfg(test)]
+mod rewrite_tests;
+#[cfg(test)]
+mod search_tests;
+#[cfg(test)]
+mod show_tests;
+#[cfg(test)]
+mod unify_tests;
+#[cfg(test)]
 mod util_tests;
+#[cfg(test)]
+mod worksheet_tests;
+#[cfg(test)]
+mod writer_tests;
 
 pub use database::Database;
 pub use formula::Formula;
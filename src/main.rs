@@ -2,14 +2,28 @@
 //! databases.  The entry point for all API operations is in the `database`
 //! module, as is a discussion of the data representation.
 
-use clap::{clap_app, crate_version};
+use clap::{clap_app, crate_version, AppSettings, ArgMatches};
 use metamath_knife::database::{Database, DbOptions};
 use metamath_knife::diag::{DiagnosticClass, Notation};
+use metamath_knife::formatter::FormatOptions;
+use metamath_knife::hygiene::HygieneOptions;
 use metamath_knife::line_cache::LineCache;
+use metamath_knife::minimize::MinimizeOptions;
+use metamath_knife::parser::as_str;
+use metamath_knife::parser::StatementType;
+use metamath_knife::profile;
+use metamath_knife::progress::ChromeTrace;
+use metamath_knife::proof::ProofStyle;
+use metamath_knife::search::SearchTarget;
+use metamath_knife::show::ProofLayout;
+use metamath_knife::worksheet::WorksheetError;
 use simple_logger::SimpleLogger;
+use std::fs::File;
 use std::io;
 use std::mem;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
 fn positive_integer(val: String) -> Result<(), String> {
     u32::from_str(&val)
@@ -21,6 +35,7 @@ fn main() {
     let app = clap_app!(("smetamath-knife") =>
         (version: crate_version!())
         (about: "A Metamath database verifier and processing tool")
+        (setting: AppSettings::SubcommandsNegateReqs)
         (@arg DATABASE: required_unless("TEXT") "Database file to load")
         (@arg TEXT: --text value_names(&["NAME", "TEXT"]) ...
             "Provide raw database content on the command line")
@@ -33,16 +48,101 @@ fn main() {
             "Parse all statements according to the database's grammar")
         (@arg verify_parse_stmt: --("verify-parse-stmt")
             "Check that printing parsed statements gives back the original formulas")
+        (@arg hygiene: -H --hygiene
+            "Check source hygiene: tabs, trailing white space, long lines and non-ASCII characters")
+        (@arg hygiene_skip: --("hygiene-skip") +takes_value +use_delimiter requires("hygiene")
+            possible_values(&["tabs", "trailing-whitespace", "long-lines", "non-ascii"])
+            "Comma-separated list of hygiene checks to skip")
+        (@arg max_line_length: --("max-line-length") +takes_value validator(positive_integer)
+            requires("hygiene") "Line length limit of the hygiene checks (default 79)")
+        (@arg strict_line_length: --("strict-line-length") requires("hygiene")
+            "Also report long lines in $t comments and lines holding a single word")
         (@arg print_grammar: -G --("print-grammar") "Print the database's grammar")
         (@arg print_formula: -F --("print-formula") "Dump the formulas of this database")
         (@arg debug: --debug
             "Activate debug logs, including for the grammar building and statement parsing")
         (@arg trace_recalc: --("trace-recalc") "Print segments as they are recalculated")
+        (@arg trace_file: --("trace-file") +takes_value
+            "Write a Chrome trace of all passes and segment jobs to this file")
         (@arg free: --free "Explicitly deallocate working memory before exit")
         (@arg repeat: --repeat "Demonstrate incremental verifier")
         (@arg jobs: -j --jobs +takes_value validator(positive_integer)
             "Number of threads to use for verification")
-        (@arg export: -e --export [LABEL] ... "Output a proof file")
+        (@arg export: -e --export [LABEL] ...
+            "Output a proof file for each theorem; labels may use * and ? wildcards, and section:NAME selects the theorems under a heading")
+        (@arg export_dir: --("export-dir") +takes_value requires("export")
+            "Directory to write the exported proofs to (default the current directory)")
+        (@arg export_format: --("export-format") +takes_value requires("export")
+            possible_values(&["mmp", "normal", "explicit", "compressed", "optimized", "packed", "packed-explicit", "lemmon", "tree"])
+            "Format of the exported proofs (default mmp)")
+        (@arg provable_typecode: --("provable-typecode") +takes_value
+            "Typecode of the logical steps of proofs (default from $j syntax, or |-)")
+        (@arg profile_proofs: --("profile-proofs")
+            "Verify each proof separately and print the slowest and largest ones")
+        (@arg profile_top: --("profile-top") +takes_value validator(positive_integer)
+            requires("profile_proofs") "Number of proofs to print for each metric (default 10)")
+        (@arg profile_output: --("profile-output") +takes_value requires("profile_proofs")
+            "Write the proof profile to this file, as JSON if it ends in .json and CSV otherwise")
+        (@subcommand fmt =>
+            (about: "Format the database source files in the style of set.mm")
+            (@arg DATABASE: +required "Database file to format, with the files it includes")
+            (@arg check: --check
+                "Print the changes as a diff instead of writing them, and exit with code 1 if there are any"))
+        (@subcommand rewrite_proofs =>
+            (name: "rewrite-proofs")
+            (about: "Rewrite the proofs of the database in the given style")
+            (@arg DATABASE: +required "Database file whose proofs to rewrite, with the files it includes")
+            (@arg style: --style +takes_value default_value("compressed")
+                possible_values(&["compressed", "optimized", "normal", "packed", "explicit", "packed-explicit"])
+                "Style of the rewritten proofs")
+            (@arg labels: --labels +takes_value +use_delimiter
+                "Comma-separated list of the theorems to rewrite (default all)")
+            (@arg check: --check
+                "Print the changes as a diff instead of writing them, and exit with code 1 if there are any"))
+        (@subcommand minimize_proofs =>
+            (name: "minimize-proofs")
+            (about: "Shorten the proofs of the database with earlier assertions, like MINIMIZE_WITH in metamath.exe")
+            (@arg DATABASE: +required "Database file whose proofs to minimize, with the files it includes")
+            (@arg labels: --labels +takes_value +use_delimiter
+                "Comma-separated list of the theorems to minimize (default all)")
+            (@arg with: --with +takes_value +use_delimiter
+                "Comma-separated list of the assertions which may be used (default all)")
+            (@arg forbid: --forbid +takes_value +use_delimiter
+                "Comma-separated list of the assertions which may not be used, nor the assertions depending on these axioms")
+            (@arg allow_new_axioms: --("allow-new-axioms")
+                "Use assertions depending on axioms which the original proof does not depend on")
+            (@arg override: --override
+                "Minimize proofs whose modification is discouraged, and use assertions whose new usage is discouraged")
+            (@arg style: --style +takes_value default_value("compressed")
+                possible_values(&["compressed", "optimized", "normal", "packed", "explicit", "packed-explicit"])
+                "Style of the minimized proofs")
+            (@arg check: --check
+                "Print the changes as a diff instead of writing them, and exit with code 1 if there are any"))
+        (@subcommand show_proof =>
+            (name: "show-proof")
+            (about: "Print the proof of a theorem")
+            (@arg DATABASE: +required "Database file containing the theorem")
+            (@arg LABEL: +required "Label of the theorem")
+            (@arg layout: --layout +takes_value default_value("lemmon")
+                possible_values(&["lemmon", "tree", "mmp", "compressed", "optimized", "normal", "packed", "explicit", "packed-explicit"])
+                "Step table, indented tree, mmj2 worksheet, or proof text in the given style")
+            (@arg hide_syntax: --("hide-syntax")
+                "Leave out the syntax steps of the lemmon and tree layouts"))
+        (@subcommand check_mmp =>
+            (name: "check-mmp")
+            (about: "Check mmj2 proof worksheets against a database")
+            (@arg DATABASE: +required "Database file the worksheets refer to")
+            (@arg WORKSHEET: +required +multiple "Proof worksheet files to check")
+            (@arg allow_incomplete: --("allow-incomplete")
+                "Do not report incomplete steps, nor count them as errors"))
+        (@subcommand search =>
+            (about: "Print the assertions whose conclusion is an instance of a pattern")
+            (@arg DATABASE: +required "Database file to search")
+            (@arg PATTERN: +required
+                "Math string, optionally with a typecode, whose variables match any sub-formula")
+            (@arg join: --join "Also match the essential hypotheses of the assertions")
+            (@arg hypotheses: --hypotheses conflicts_with("join")
+                "Only match the essential hypotheses of the assertions"))
     );
 
     #[cfg(feature = "dot")]
@@ -53,6 +153,23 @@ fn main() {
 
     let matches = app.get_matches();
 
+    let skip: Vec<&str> = matches
+        .values_of("hygiene_skip")
+        .map_or_else(Vec::new, |values| values.collect());
+    let hygiene = HygieneOptions {
+        tabs: !skip.contains(&"tabs"),
+        trailing_whitespace: !skip.contains(&"trailing-whitespace"),
+        max_line_length: if skip.contains(&"long-lines") {
+            None
+        } else {
+            Some(matches.value_of("max_line_length").map_or(79, |len| {
+                usize::from_str(len).expect("validator should check this")
+            }))
+        },
+        strict_line_length: matches.is_present("strict_line_length"),
+        non_ascii: !skip.contains(&"non-ascii"),
+    };
+
     let options = DbOptions {
         autosplit: matches.is_present("split"),
         timing: matches.is_present("timing"),
@@ -63,9 +180,14 @@ fn main() {
             || matches.is_present("verify_parse_stmt")
             || matches.is_present("export_grammar_dot")
             || matches.is_present("print_grammar")
-            || matches.is_present("print_formula"),
+            || matches.is_present("print_formula")
+            || matches.subcommand_matches("check-mmp").is_some()
+            || matches.subcommand_matches("search").is_some()
+            || matches.subcommand_matches("minimize-proofs").is_some(),
         jobs: usize::from_str(matches.value_of("jobs").unwrap_or("1"))
             .expect("validator should check this"),
+        hygiene,
+        provable_typecode: matches.value_of("provable_typecode").map(str::to_owned),
     };
 
     if matches.is_present("debug") {
@@ -74,20 +196,62 @@ fn main() {
 
     let mut db = Database::new(options);
 
+    let trace = matches.value_of("trace_file").map(|path| {
+        let trace = Arc::new(ChromeTrace::new());
+        db.set_progress_observer(Some(trace.clone()));
+        (path.to_owned(), trace)
+    });
+
     let mut data = Vec::new();
     if let Some(tvals) = matches.values_of_lossy("TEXT") {
         for kv in tvals.chunks(2) {
             data.push((kv[0].clone(), kv[1].clone().into_bytes()));
         }
     }
+    let fmt_matches = matches.subcommand_matches("fmt");
+    let rewrite_matches = matches.subcommand_matches("rewrite-proofs");
+    let minimize_matches = matches.subcommand_matches("minimize-proofs");
+    let show_matches = matches.subcommand_matches("show-proof");
+    let check_mmp_matches = matches.subcommand_matches("check-mmp");
+    let search_matches = matches.subcommand_matches("search");
     let start = matches
         .value_of("DATABASE")
+        .or_else(|| fmt_matches.and_then(|fmt| fmt.value_of("DATABASE")))
+        .or_else(|| rewrite_matches.and_then(|rewrite| rewrite.value_of("DATABASE")))
+        .or_else(|| minimize_matches.and_then(|minimize| minimize.value_of("DATABASE")))
+        .or_else(|| show_matches.and_then(|show| show.value_of("DATABASE")))
+        .or_else(|| check_mmp_matches.and_then(|check| check.value_of("DATABASE")))
+        .or_else(|| search_matches.and_then(|search| search.value_of("DATABASE")))
         .map(|x| x.to_owned())
         .unwrap_or_else(|| data[0].0.clone());
 
     loop {
         db.parse(start.clone(), data.clone());
 
+        if let Some(fmt) = fmt_matches {
+            std::process::exit(format_sources(&db, fmt));
+        }
+
+        if let Some(rewrite) = rewrite_matches {
+            std::process::exit(rewrite_proofs(&mut db, rewrite));
+        }
+
+        if let Some(minimize) = minimize_matches {
+            std::process::exit(minimize_proofs(&mut db, minimize));
+        }
+
+        if let Some(show) = show_matches {
+            std::process::exit(show_proof(&mut db, show));
+        }
+
+        if let Some(check) = check_mmp_matches {
+            std::process::exit(check_mmp(&mut db, check));
+        }
+
+        if let Some(search) = search_matches {
+            std::process::exit(search_formulas(&mut db, search));
+        }
+
         let mut types = vec![DiagnosticClass::Parse, DiagnosticClass::Scope];
 
         if matches.is_present("verify") {
@@ -102,6 +266,10 @@ fn main() {
             types.push(DiagnosticClass::StmtParse);
         }
 
+        if matches.is_present("hygiene") {
+            types.push(DiagnosticClass::Hygiene);
+        }
+
         if matches.is_present("verify_parse_stmt") {
             db.stmt_parse_pass();
             db.verify_parse_stmt();
@@ -132,12 +300,46 @@ fn main() {
             db.print_outline();
         }
 
-        if let Some(exps) = matches.values_of_lossy("export") {
-            for file in exps {
-                db.export(&file);
+        if let Some(selectors) = matches.values_of("export") {
+            let selectors: Vec<&str> = selectors.collect();
+            let dir = matches.value_of("export_dir").unwrap_or(".");
+            let layout = proof_layout(matches.value_of("export_format").unwrap_or("mmp"));
+            db.outline_pass();
+            let report = db.export_proofs(Path::new(dir), &selectors, layout);
+            for (label, err) in &report.failed {
+                eprintln!("Could not export {}: {}", label, err);
+            }
+            count += report.failed.len();
+        }
+
+        if matches.is_present("profile_proofs") {
+            let top = matches.value_of("profile_top").map_or(10, |count| {
+                usize::from_str(count).expect("validator should check this")
+            });
+            db.scope_pass();
+            let profiles = db.profile_proofs();
+            profile::print_top(&profiles, top);
+            if let Some(path) = matches.value_of("profile_output") {
+                File::create(path)
+                    .and_then(|mut file| {
+                        if path.ends_with(".json") {
+                            profile::write_json(&profiles, &mut file)
+                        } else {
+                            profile::write_csv(&profiles, &mut file)
+                        }
+                    })
+                    .unwrap_or_else(|err| {
+                        eprintln!("Could not write proof profile to {}: {}", path, err)
+                    });
             }
         }
 
+        if let Some((path, trace)) = &trace {
+            File::create(path)
+                .and_then(|mut file| trace.write(&mut file))
+                .unwrap_or_else(|err| eprintln!("Could not write trace to {}: {}", path, err));
+        }
+
         if matches.is_present("repeat") {
             let mut input = String::new();
             if io::stdin().read_line(&mut input).unwrap() == 0 {
@@ -155,6 +357,237 @@ fn main() {
     }
 }
 
+/// Formats the source files of the database, and returns the exit code.
+fn format_sources(db: &Database, matches: &ArgMatches<'_>) -> i32 {
+    let changes = db.format_source(FormatOptions::default());
+    if matches.is_present("check") {
+        for change in &changes {
+            print!("{}", change.unified_diff());
+        }
+        return if changes.is_empty() { 0 } else { 1 };
+    }
+    for change in &changes {
+        if let Err(err) = change.write() {
+            eprintln!("Could not write {}: {}", change.name, err);
+            return 1;
+        }
+    }
+    0
+}
+
+fn proof_style(name: &str) -> ProofStyle {
+    match name {
+        "optimized" => ProofStyle::OptimizedCompressed,
+        "normal" => ProofStyle::Normal,
+        "packed" => ProofStyle::Packed,
+        "explicit" => ProofStyle::Explicit,
+        "packed-explicit" => ProofStyle::PackedExplicit,
+        _ => ProofStyle::Compressed,
+    }
+}
+
+fn proof_layout(name: &str) -> ProofLayout {
+    match name {
+        "lemmon" => ProofLayout::Lemmon,
+        "tree" => ProofLayout::Tree,
+        "mmp" => ProofLayout::Mmp,
+        style => ProofLayout::Style(proof_style(style)),
+    }
+}
+
+fn show_proof(db: &mut Database, matches: &ArgMatches<'_>) -> i32 {
+    let label = matches.value_of("LABEL").unwrap();
+    let layout = proof_layout(matches.value_of("layout").unwrap());
+    db.name_pass();
+    db.scope_pass();
+    let stmt = match db.statement(label) {
+        Some(stmt) if stmt.statement_type() == StatementType::Provable => stmt,
+        Some(_) => {
+            eprintln!("{} is not a $p statement", label);
+            return 1;
+        }
+        None => {
+            eprintln!("Unknown label {}", label);
+            return 1;
+        }
+    };
+    let stdout = io::stdout();
+    let hide_syntax = matches.is_present("hide_syntax");
+    match db.show_proof(stmt, layout, hide_syntax, &mut stdout.lock()) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Could not show the proof of {}: {}", label, err);
+            1
+        }
+    }
+}
+
+/// Checks proof worksheets, printing their problems, and returns the exit
+/// code.
+fn check_mmp(db: &mut Database, matches: &ArgMatches<'_>) -> i32 {
+    db.name_pass();
+    db.scope_pass();
+    db.stmt_parse_pass();
+    let allow_incomplete = matches.is_present("allow_incomplete");
+    let mut count = 0;
+    for path in matches.values_of("WORKSHEET").unwrap() {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", path, err);
+                count += 1;
+                continue;
+            }
+        };
+        let worksheet = db.import_mmp(&text);
+        for (line, err) in &worksheet.diagnostics {
+            if allow_incomplete && matches!(err, WorksheetError::Incomplete) {
+                continue;
+            }
+            println!("{}:{}: {}", path, line, err);
+            count += 1;
+        }
+    }
+    i32::from(count > 0)
+}
+
+/// Prints the assertions matching a search pattern, with the statements
+/// which matched, and returns the exit code: 1 if nothing matched.
+fn search_formulas(db: &mut Database, matches: &ArgMatches<'_>) -> i32 {
+    db.name_pass();
+    db.scope_pass();
+    db.stmt_parse_pass();
+    let target = if matches.is_present("join") {
+        SearchTarget::All
+    } else if matches.is_present("hypotheses") {
+        SearchTarget::Hypotheses
+    } else {
+        SearchTarget::Conclusions
+    };
+    let found = match db.search(matches.value_of("PATTERN").unwrap(), target) {
+        Ok(found) => found,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    for found in &found {
+        let assertion = db.statement_by_address(found.address);
+        let sref = found
+            .hypothesis
+            .map_or(assertion, |hyp| db.statement_by_address(hyp));
+        let mut line = as_str(assertion.label()).to_owned();
+        if found.hypothesis.is_some() {
+            line.push(' ');
+            line.push_str(as_str(sref.label()));
+        }
+        line.push_str(match sref.statement_type() {
+            StatementType::Axiom => " $a",
+            StatementType::Essential => " $e",
+            _ => " $p",
+        });
+        for token in sref.math_iter() {
+            line.push(' ');
+            line.push_str(as_str(token.slice));
+        }
+        println!("{}", line);
+    }
+    i32::from(found.is_empty())
+}
+
+fn rewrite_proofs(db: &mut Database, matches: &ArgMatches<'_>) -> i32 {
+    let style = proof_style(matches.value_of("style").unwrap());
+    let labels: Option<Vec<&str>> = matches.values_of("labels").map(Iterator::collect);
+    let report = match db.rewrite_proofs(style, labels.as_deref()) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    for (label, diag) in &report.failed {
+        eprintln!("Could not rewrite the proof of {}: {:?}", label, diag);
+    }
+    let check = matches.is_present("check");
+    let changes = match db.write_back(check) {
+        Ok(changes) => changes,
+        Err(err) => {
+            eprintln!("Could not write the changes: {}", err);
+            return 1;
+        }
+    };
+    if check {
+        for change in &changes {
+            print!("{}", change.unified_diff());
+        }
+    }
+    i32::from((check && !changes.is_empty()) || !report.failed.is_empty())
+}
+
+/// Minimizes the proofs of the database, prints the shortened ones, and
+/// writes them back or prints the changes as for `rewrite_proofs`.
+fn minimize_proofs(db: &mut Database, matches: &ArgMatches<'_>) -> i32 {
+    let style = proof_style(matches.value_of("style").unwrap());
+    let labels: Option<Vec<&str>> = matches.values_of("labels").map(Iterator::collect);
+    let options = MinimizeOptions {
+        with: matches.values_of_lossy("with"),
+        forbid: matches.values_of_lossy("forbid").unwrap_or_default(),
+        allow_new_axioms: matches.is_present("allow_new_axioms"),
+        override_discouraged: matches.is_present("override"),
+    };
+    let report = match db.minimize_proofs(labels.as_deref(), &options) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let skipped = match db.apply_minimized(&report, style) {
+        Ok(skipped) => skipped,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    for minimized in &report.minimized {
+        if !skipped.contains(&minimized.label) {
+            println!(
+                "{}: {} -> {} steps, using {}",
+                minimized.label,
+                minimized.old_length,
+                minimized.new_length,
+                minimized.used.join(" ")
+            );
+        }
+    }
+    for label in &skipped {
+        eprintln!(
+            "Could not replace the proof of {}, which contains comments",
+            label
+        );
+    }
+    for label in &report.discouraged {
+        eprintln!("Proof modification of {} is discouraged", label);
+    }
+    for (label, diag) in &report.failed {
+        eprintln!("Could not minimize the proof of {}: {:?}", label, diag);
+    }
+    let check = matches.is_present("check");
+    let changes = match db.write_back(check) {
+        Ok(changes) => changes,
+        Err(err) => {
+            eprintln!("Could not write the changes: {}", err);
+            return 1;
+        }
+    };
+    if check {
+        for change in &changes {
+            print!("{}", change.unified_diff());
+        }
+    }
+    i32::from((check && !changes.is_empty()) || !report.failed.is_empty() || !skipped.is_empty())
+}
+
 fn print_annotation(lc: &mut LineCache, ann: Notation) {
     let mut args = String::new();
     for (id, val) in ann.args {
@@ -0,0 +1,620 @@
+//! Shortening proofs with the assertions of the database.
+//!
+//! `Database::minimize_proofs` is the equivalent of the `MINIMIZE_WITH`
+//! command of metamath.exe: it looks for steps of the proof of a `$p`
+//! statement which an earlier assertion proves directly from other steps of
+//! the same proof, and keeps the replacements which make the proof shorter,
+//! until no step can be replaced any more.  The candidates for a step are the
+//! assertions whose conclusion unifies with it, found through a
+//! `SearchIndex`; their essential hypotheses are then matched against the
+//! steps which do not depend on the replaced one, and their `$d` conditions
+//! are checked in the context of the theorem, so that the new proof is valid.
+//!
+//! The length of a proof is its number of distinct steps, which is also the
+//! number of steps of its compressed form.
+//!
+//! As with metamath.exe, unless the options say otherwise, the proofs of the
+//! statements whose comment contains "(Proof modification is discouraged.)"
+//! are left alone, and the assertions whose comment contains "(New usage is
+//! discouraged.)" are not used, nor are the assertions depending on axioms
+//! which the original proof does not depend on.
+//!
+//! Each proof is minimized as a separate job of the executor of the database,
+//! working on a snapshot of it.  The new proofs are only reported;
+//! `Database::apply_minimized` then splices them into the source text, in
+//! the same way as `Database::rewrite_proofs`.
+
+use crate::bit_set::Bitset;
+use crate::database::estimate_bytes;
+use crate::database::Promise;
+use crate::diag::Diagnostic;
+use crate::edit::EditError;
+use crate::formula::Substitutions;
+use crate::formula::WorkVariable;
+use crate::parser::StatementAddress;
+use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::parser::TokenIndex;
+use crate::proof::ProofStyle;
+use crate::proof::ProofTreeArray;
+use crate::scopeck::Frame;
+use crate::scopeck::Hyp;
+use crate::search::SearchIndex;
+use crate::util::HashMap;
+use crate::util::HashSet;
+use crate::Database;
+use crate::Formula;
+use std::sync::Arc;
+
+/// The tag of the statements whose proof shall not be minimized.
+const PROOF_MODIFICATION_DISCOURAGED: &str = "(Proof modification is discouraged.)";
+
+/// The tag of the assertions which shall not be used in new proofs.
+const NEW_USAGE_DISCOURAGED: &str = "(New usage is discouraged.)";
+
+/// The assertions which may be used to minimize proofs.
+#[derive(Debug, Clone, Default)]
+pub struct MinimizeOptions {
+    /// Labels of the assertions which may be used, or `None` for all the
+    /// assertions before each theorem.
+    pub with: Option<Vec<String>>,
+    /// Labels of statements which may not be used.  The assertions depending
+    /// on one of these, if it is an axiom, may not be used either.
+    pub forbid: Vec<String>,
+    /// Whether assertions depending on axioms which the original proof does
+    /// not depend on may be used.
+    pub allow_new_axioms: bool,
+    /// Whether to also minimize the proofs whose modification is discouraged,
+    /// and use the assertions whose new usage is discouraged.
+    pub override_discouraged: bool,
+}
+
+/// A proof which was shortened.
+#[derive(Debug, Clone)]
+pub struct MinimizedProof {
+    /// Label of the theorem.
+    pub label: String,
+    /// Address of the theorem.
+    pub address: StatementAddress,
+    /// Number of steps of the original proof.
+    pub old_length: usize,
+    /// Number of steps of the new proof.
+    pub new_length: usize,
+    /// Labels of the assertions applied to shorten the proof, in order.
+    pub used: Vec<String>,
+    /// The new proof.
+    pub proof: ProofTreeArray,
+}
+
+/// The outcome of minimizing the proofs of a database.
+#[derive(Debug, Default)]
+pub struct MinimizeReport {
+    /// The proofs which were shortened, in the order of the theorems.
+    pub minimized: Vec<MinimizedProof>,
+    /// Labels of the statements left alone because their proof modification
+    /// is discouraged.
+    pub discouraged: Vec<String>,
+    /// Labels of the statements whose proofs could not be minimized, with the
+    /// reason.
+    pub failed: Vec<(String, Diagnostic)>,
+}
+
+/// The assertions which may replace proof steps, shared by the jobs.
+struct Minimizer {
+    index: SearchIndex,
+    /// The assertions allowed by the options, if restricted
+    with: Option<HashSet<StatementAddress>>,
+    /// The forbidden assertions, and the discouraged ones
+    excluded: HashSet<StatementAddress>,
+    /// The forbidden axioms
+    forbidden: Bitset,
+    /// The axioms each assertion depends on
+    axioms: HashMap<StatementAddress, Bitset>,
+    allow_new_axioms: bool,
+}
+
+impl Database {
+    /// Searches shorter proofs for the given `$p` statements, or for all of
+    /// them if `labels` is `None`, with the assertions allowed by the options.
+    ///
+    /// See the [module documentation](crate::minimize) for the method.  The
+    /// proofs are minimized in parallel, and the database is left unchanged.
+    /// Fails if one of the labels does not exist or, for `labels`, is not a
+    /// `$p` statement.
+    pub fn minimize_proofs(
+        &mut self,
+        labels: Option<&[&str]>,
+        options: &MinimizeOptions,
+    ) -> Result<MinimizeReport, EditError> {
+        self.name_pass();
+        self.scope_pass();
+        self.stmt_parse_pass();
+        let snapshot = Arc::new(self.snapshot());
+        let db: &Database = &snapshot;
+
+        let lookup = |label: &str| {
+            db.statement(label)
+                .map(StatementRef::address)
+                .ok_or_else(|| EditError::UnknownLabel(label.to_owned()))
+        };
+        let targets: Vec<StatementAddress> = match labels {
+            Some(labels) => labels
+                .iter()
+                .map(|&label| {
+                    let address = lookup(label)?;
+                    if db.statement_by_address(address).statement_type() == StatementType::Provable
+                    {
+                        Ok(address)
+                    } else {
+                        Err(EditError::NotProvable(label.to_owned()))
+                    }
+                })
+                .collect::<Result<_, _>>()?,
+            None => db
+                .statements()
+                .filter(|sref| sref.statement_type() == StatementType::Provable)
+                .map(StatementRef::address)
+                .collect(),
+        };
+        let with = match options.with {
+            Some(ref labels) => Some(
+                labels
+                    .iter()
+                    .map(|label| lookup(label))
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        let axioms = axiom_usage(db);
+        let mut excluded = HashSet::default();
+        let mut forbidden = Bitset::new();
+        for label in &options.forbid {
+            let address = lookup(label)?;
+            if db.statement_by_address(address).statement_type() == StatementType::Axiom {
+                if let Some(used) = axioms.get(&address) {
+                    forbidden |= used;
+                }
+            }
+            excluded.insert(address);
+        }
+        if !options.override_discouraged {
+            excluded.extend(
+                db.statements()
+                    .filter(|sref| has_tag(*sref, NEW_USAGE_DISCOURAGED))
+                    .map(StatementRef::address),
+            );
+        }
+
+        let mut report = MinimizeReport::default();
+        let mut jobs = Vec::new();
+        for address in targets {
+            let sref = db.statement_by_address(address);
+            if !options.override_discouraged && has_tag(sref, PROOF_MODIFICATION_DISCOURAGED) {
+                report
+                    .discouraged
+                    .push(String::from_utf8_lossy(sref.label()).into_owned());
+            } else {
+                let span = sref.span_full();
+                jobs.push((address, estimate_bytes((span.end - span.start) as usize)));
+            }
+        }
+        let minimizer = Arc::new(Minimizer {
+            index: SearchIndex::new(db),
+            with,
+            excluded,
+            forbidden,
+            axioms,
+            allow_new_axioms: options.allow_new_axioms,
+        });
+        let promises = jobs
+            .iter()
+            .map(|&(address, estimate)| {
+                let snapshot = snapshot.clone();
+                let minimizer = minimizer.clone();
+                self.executor()
+                    .exec(estimate, move || minimizer.minimize(&snapshot, address))
+            })
+            .collect();
+        for ((address, _), result) in jobs.iter().zip(Promise::join(promises).wait()) {
+            match result {
+                Ok(Some(proof)) => report.minimized.push(proof),
+                Ok(None) => {}
+                Err(diag) => {
+                    let label = db.statement_by_address(*address).label();
+                    report
+                        .failed
+                        .push((String::from_utf8_lossy(label).into_owned(), diag));
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Replaces the proofs of a report of [`Database::minimize_proofs`] with
+    /// the new ones, in the given style.  The database must not have been
+    /// changed since the report was made.  Returns the labels of the theorems
+    /// whose proofs were left unchanged, because they contain comments.
+    ///
+    /// The changes are made in memory, see [`Database::rewrite_proofs`].
+    pub fn apply_minimized(
+        &mut self,
+        report: &MinimizeReport,
+        style: ProofStyle,
+    ) -> Result<Vec<String>, EditError> {
+        let mut edits = Vec::new();
+        let mut skipped = Vec::new();
+        for minimized in &report.minimized {
+            let sref = self.statement_by_address(minimized.address);
+            match self.replaced_proof(sref, &minimized.proof, style) {
+                Some((range, text)) => edits.push((minimized.address, range, text)),
+                None => skipped.push(minimized.label.clone()),
+            }
+        }
+        self.replace_ranges(edits)?;
+        Ok(skipped)
+    }
+}
+
+impl Minimizer {
+    /// Minimizes the proof of the theorem at the given address, returning
+    /// `None` if it cannot be shortened.
+    fn minimize(
+        &self,
+        db: &Database,
+        address: StatementAddress,
+    ) -> Result<Option<MinimizedProof>, Diagnostic> {
+        let sref = db.statement_by_address(address);
+        let mut arr = ProofTreeArray::new(db, sref)?;
+        if arr.is_incomplete() {
+            return Err(Diagnostic::ProofIncomplete);
+        }
+        let frame = db.scope_result().get(sref.label()).unwrap();
+        let old_length = arr.trees.len();
+        let mut formulas = HashMap::default();
+        let mut used = Vec::new();
+        while let Some((shorter, assertion)) = self.shorten(db, sref, frame, &arr, &mut formulas) {
+            arr = shorter;
+            let label = db.statement_by_address(assertion).label();
+            used.push(String::from_utf8_lossy(label).into_owned());
+        }
+        if used.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(MinimizedProof {
+            label: String::from_utf8_lossy(sref.label()).into_owned(),
+            address,
+            old_length,
+            new_length: arr.trees.len(),
+            used,
+            proof: arr,
+        }))
+    }
+
+    /// Returns whether an assertion may be used in the proof of a theorem
+    /// whose original proof depends on the given axioms.
+    fn allows(&self, assertion: StatementAddress, theorem_axioms: Option<&Bitset>) -> bool {
+        if self.excluded.contains(&assertion)
+            || self
+                .with
+                .as_ref()
+                .is_some_and(|with| !with.contains(&assertion))
+        {
+            return false;
+        }
+        self.axioms.get(&assertion).is_none_or(|used| {
+            used.into_iter().all(|axiom| {
+                !self.forbidden.has_bit(axiom)
+                    && (self.allow_new_axioms
+                        || theorem_axioms.is_some_and(|axioms| axioms.has_bit(axiom)))
+            })
+        })
+    }
+
+    /// Replaces one step of a proof with an application of an allowed
+    /// assertion, trying the steps from the last one, and returns the first
+    /// shorter proof found, with the assertion applied.
+    ///
+    /// The formulas of the steps are cached by expression.
+    fn shorten(
+        &self,
+        db: &Database,
+        sref: StatementRef<'_>,
+        frame: &Frame,
+        arr: &ProofTreeArray,
+        cache: &mut HashMap<Vec<u8>, Option<Formula>>,
+    ) -> Option<(ProofTreeArray, StatementAddress)> {
+        let nset = db.name_result();
+        let provable = nset.atom_name(db.grammar_result().provable_typecode());
+        let formulas = arr
+            .trees
+            .iter()
+            .zip(&arr.exprs)
+            .map(|(tree, expr)| {
+                let step = db.statement_by_address(tree.address);
+                if step.math_len() == 0 || step.math_at(0).slice != provable {
+                    return None;
+                }
+                cache
+                    .entry(expr.clone())
+                    .or_insert_with(|| parse_expression(db, expr))
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+        let theorem_axioms = self.axioms.get(&sref.address());
+
+        let goals = formulas
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(step, formula)| Some((step, formula.as_ref()?)));
+        for (step, goal) in goals {
+            let tree = &arr.trees[step];
+            if db.statement_by_address(tree.address).statement_type() == StatementType::Essential
+                || kept_steps(arr, step) + 1 >= arr.trees.len()
+            {
+                continue;
+            }
+            // the steps whose proof does not use this one, which the
+            // hypotheses of the new assertion may use
+            let mut depends = vec![false; arr.trees.len()];
+            let mut available = Vec::new();
+            for (index, tree) in arr.trees.iter().enumerate() {
+                depends[index] = index == step || tree.children.iter().any(|&child| depends[child]);
+                if let (false, Some(formula)) = (depends[index], formulas[index].as_ref()) {
+                    available.push((index, formula));
+                }
+            }
+
+            let mut best: Option<(ProofTreeArray, StatementAddress)> = None;
+            let applications = self
+                .index
+                .applicable(db, goal, Some(sref.address()))
+                .into_iter()
+                .filter(|application| self.allows(application.address, theorem_axioms))
+                .filter_map(|application| {
+                    let assertion = db.statement_by_address(application.address);
+                    Some((application, db.scope_result().get(assertion.label())?))
+                });
+            for (application, assertion_frame) in applications {
+                let mut accept = |bindings: &Substitutions, hypotheses: &[usize]| {
+                    let mut substitutions = Substitutions::default();
+                    for (label, formula) in application.substitutions.iter() {
+                        let formula = formula.substitute(bindings);
+                        if formula.has_work_variables() {
+                            return false;
+                        }
+                        substitutions.insert(label, formula);
+                    }
+                    if !db
+                        .check_dv(assertion_frame, &substitutions, frame)
+                        .is_valid()
+                    {
+                        return false;
+                    }
+                    let mut rebuild = Rebuild {
+                        db,
+                        arr,
+                        step,
+                        address: application.address,
+                        frame: assertion_frame,
+                        substitutions: &substitutions,
+                        hypotheses,
+                        copied: vec![None; arr.trees.len()],
+                        stack_buffer: Vec::new(),
+                        new: ProofTreeArray::default(),
+                    };
+                    if let Some(qed) = rebuild.copy(arr.qed) {
+                        let mut shorter = rebuild.new;
+                        let length = best
+                            .as_ref()
+                            .map_or(arr.trees.len(), |best| best.0.trees.len());
+                        if shorter.trees.len() < length {
+                            shorter.qed = qed;
+                            shorter.calc_indent();
+                            best = Some((shorter, application.address));
+                        }
+                    }
+                    true
+                };
+                match_hypotheses(
+                    &application.hypotheses,
+                    &available,
+                    &Substitutions::default(),
+                    &mut Vec::new(),
+                    &mut accept,
+                );
+            }
+            if best.is_some() {
+                return best;
+            }
+        }
+        None
+    }
+}
+
+/// Matches hypotheses with work variables against the formulas of proof
+/// steps, binding only the work variables, and calls `accept` with the
+/// bindings and the steps of each complete match, until it returns `true`.
+fn match_hypotheses(
+    hypotheses: &[Formula],
+    available: &[(usize, &Formula)],
+    bindings: &Substitutions,
+    chosen: &mut Vec<usize>,
+    accept: &mut dyn FnMut(&Substitutions, &[usize]) -> bool,
+) -> bool {
+    if let Some((first, rest)) = hypotheses.split_first() {
+        for &(index, formula) in available {
+            let mut next = bindings.clone();
+            if next.unify(first, formula)
+                && next
+                    .iter()
+                    .all(|(label, _)| WorkVariable::from_atom(label).is_some())
+            {
+                chosen.push(index);
+                if match_hypotheses(rest, available, &next, chosen, accept) {
+                    return true;
+                }
+                chosen.pop();
+            }
+        }
+        false
+    } else {
+        accept(bindings, chosen)
+    }
+}
+
+/// Returns the number of steps of a proof which are still used if the given
+/// step is replaced, not counting that step.
+fn kept_steps(arr: &ProofTreeArray, step: usize) -> usize {
+    let mut seen = vec![false; arr.trees.len()];
+    let mut stack = vec![arr.qed];
+    let mut count = 0;
+    while let Some(index) = stack.pop() {
+        if seen[index] {
+            continue;
+        }
+        seen[index] = true;
+        if index != step {
+            count += 1;
+            stack.extend(&arr.trees[index].children);
+        }
+    }
+    count
+}
+
+/// A copy of a proof where one step is proved by another assertion.
+struct Rebuild<'a> {
+    db: &'a Database,
+    arr: &'a ProofTreeArray,
+    /// The step to replace
+    step: usize,
+    /// The assertion replacing it, with its frame and substitutions
+    address: StatementAddress,
+    frame: &'a Frame,
+    substitutions: &'a Substitutions,
+    /// The steps proving the essential hypotheses of the assertion
+    hypotheses: &'a [usize],
+    /// The index of each step in the new proof, once copied
+    copied: Vec<Option<usize>>,
+    stack_buffer: Vec<u8>,
+    new: ProofTreeArray,
+}
+
+impl Rebuild<'_> {
+    /// Copies a step with its proof to the new proof, and returns its index,
+    /// or `None` if a substitution has no syntax proof.
+    fn copy(&mut self, index: usize) -> Option<usize> {
+        if let Some(copied) = self.copied[index] {
+            return Some(copied);
+        }
+        let (address, children) = if index == self.step {
+            let nset = self.db.name_result();
+            let mut essentials = self.hypotheses.iter();
+            let mut children = Vec::with_capacity(self.frame.hypotheses.len());
+            for hyp in &*self.frame.hypotheses {
+                children.push(match *hyp {
+                    Hyp::Floating(address, ..) => {
+                        let label = self.db.statement_by_address(address).label();
+                        let formula = &self.substitutions[nset.lookup_label(label).unwrap().atom];
+                        formula
+                            .as_ref(self.db)
+                            .build_syntax_proof(&mut self.stack_buffer, &mut self.new)?
+                    }
+                    Hyp::Essential(..) => self.copy(*essentials.next().unwrap())?,
+                });
+            }
+            (self.address, children)
+        } else {
+            let tree = &self.arr.trees[index];
+            let children = tree
+                .children
+                .iter()
+                .map(|&child| self.copy(child))
+                .collect::<Option<_>>()?;
+            (tree.address, children)
+        };
+        let copied = self.new.insert(address, children, &self.arr.exprs[index]);
+        self.copied[index] = Some(copied);
+        Some(copied)
+    }
+}
+
+/// Parses the expression of a provable step, as stored in a proof tree array.
+fn parse_expression(db: &Database, expr: &[u8]) -> Option<Formula> {
+    let nset = db.name_result();
+    let grammar = db.grammar_result();
+    let symbols = expr
+        .split(|&chr| chr == b' ')
+        .filter(|token| !token.is_empty())
+        .map(|token| Some(nset.lookup_symbol(token)?.atom))
+        .collect::<Option<Vec<_>>>()?;
+    grammar
+        .parse_math_string(grammar.provable_typecode(), &mut symbols.into_iter(), nset)
+        .ok()
+}
+
+/// Returns whether the comment of a statement contains a tag, which may be
+/// split across lines.  The comment may come before the hypotheses of the
+/// statement and the opening of their block.
+fn has_tag(sref: StatementRef<'_>, tag: &str) -> bool {
+    let segment = sref.segment();
+    for index in (0..sref.index()).rev() {
+        let comment = segment.statement(index);
+        match comment.statement_type() {
+            StatementType::Essential
+            | StatementType::Floating
+            | StatementType::Disjoint
+            | StatementType::OpenGroup => {}
+            StatementType::Comment => {
+                let text = comment.span().as_ref(&segment.segment.buffer);
+                let words = String::from_utf8_lossy(text)
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                return words.contains(tag);
+            }
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Returns the axioms each assertion depends on, as sets of the numbers of the
+/// `$a` statements of the provable typecode, in database order.  Syntax
+/// axioms are left out, and so are unknown labels in proofs.
+fn axiom_usage(db: &Database) -> HashMap<StatementAddress, Bitset> {
+    let nset = db.name_result();
+    let provable = nset.atom_name(db.grammar_result().provable_typecode());
+    let mut usage = HashMap::default();
+    let mut count = 0;
+    for sref in db.statements() {
+        match sref.statement_type() {
+            StatementType::Axiom if sref.math_len() > 0 && sref.math_at(0).slice == provable => {
+                let mut axioms = Bitset::new();
+                axioms.set_bit(count);
+                count += 1;
+                usage.insert(sref.address(), axioms);
+            }
+            StatementType::Provable => {
+                // the labels of a compressed proof are listed in parentheses
+                let compressed = sref.proof_len() > 0 && sref.proof_slice_at(0) == b"(";
+                let mut axioms = Bitset::new();
+                for ix in TokenIndex::from(compressed)..sref.proof_len() {
+                    let token = sref.proof_slice_at(ix);
+                    if compressed && token == b")" {
+                        break;
+                    }
+                    if let Some(used) = nset
+                        .lookup_label(token)
+                        .and_then(|lookup| usage.get(&lookup.address))
+                    {
+                        axioms |= used;
+                    }
+                }
+                usage.insert(sref.address(), axioms);
+            }
+            _ => {}
+        }
+    }
+    usage
+}
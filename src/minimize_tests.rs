@@ -0,0 +1,178 @@
+use crate::database::DbOptions;
+use crate::diag::DiagnosticClass;
+use crate::edit::EditError;
+use crate::grammar_tests::mkdb;
+use crate::minimize::MinimizeOptions;
+use crate::proof::ProofStyle;
+use crate::Database;
+
+const MINIMIZE_DB: &str = "
+    $c |- wff ( ) -> $.
+    $( $j syntax 'wff'; syntax '|-' as 'wff'; $)
+    $v ph ps ch $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wch $f wff ch $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps ) $.
+        ax-mp $a |- ps $.
+    $}
+    ax-1 $a |- ( ph -> ( ps -> ph ) ) $.
+    $( Inference introducing an antecedent. $)
+    ${
+        a1i.1 $e |- ph $.
+        a1i $p |- ( ps -> ph ) $= wph wps wph wi a1i.1 wph wps ax-1 ax-mp $.
+    $}
+    $( Theorem proved without ~ a1i . $)
+    ${
+        a1iw.1 $e |- ( ph -> ps ) $.
+        a1iw $p |- ( ch -> ( ph -> ps ) ) $=
+          wph wps wi wch wph wps wi wi a1iw.1 wph wps wi wch ax-1 ax-mp $.
+    $}
+";
+
+/// Returns the labels of the minimized proofs, with the assertions used
+fn minimize(text: &str, options: &MinimizeOptions) -> Vec<String> {
+    let mut db = mkdb(text.as_bytes());
+    let report = db.minimize_proofs(None, options).unwrap();
+    assert!(report.failed.is_empty());
+    report
+        .minimized
+        .iter()
+        .map(|minimized| format!("{} {}", minimized.label, minimized.used.join(" ")))
+        .collect()
+}
+
+#[test]
+fn test_minimize() {
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        jobs: 4,
+        ..DbOptions::default()
+    });
+    db.parse(
+        "test.mm".to_owned(),
+        vec![("test.mm".to_owned(), MINIMIZE_DB.as_bytes().to_owned())],
+    );
+    let report = db
+        .minimize_proofs(None, &MinimizeOptions::default())
+        .unwrap();
+    assert!(report.failed.is_empty());
+    assert!(report.discouraged.is_empty());
+    assert_eq!(report.minimized.len(), 1);
+    let minimized = &report.minimized[0];
+    assert_eq!(minimized.label, "a1iw");
+    assert_eq!(minimized.used, ["a1i"]);
+    assert_eq!((minimized.old_length, minimized.new_length), (8, 6));
+
+    // the new proof replaces the old one, and verifies
+    assert!(db
+        .apply_minimized(&report, ProofStyle::Normal)
+        .unwrap()
+        .is_empty());
+    db.name_pass();
+    let sref = db.statement("a1iw").unwrap();
+    let proof = (0..sref.proof_len())
+        .map(|ix| String::from_utf8_lossy(sref.proof_slice_at(ix)).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(proof.join(" "), "wph wps wi wch a1iw.1 a1i");
+    db.verify_pass();
+    assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+}
+
+#[test]
+fn test_minimize_options() {
+    let options = MinimizeOptions::default();
+    assert_eq!(minimize(MINIMIZE_DB, &options), ["a1iw a1i"]);
+    let options = MinimizeOptions {
+        with: Some(vec!["ax-mp".to_owned(), "ax-1".to_owned()]),
+        ..MinimizeOptions::default()
+    };
+    assert!(minimize(MINIMIZE_DB, &options).is_empty());
+    let options = MinimizeOptions {
+        forbid: vec!["ax-1".to_owned()],
+        ..MinimizeOptions::default()
+    };
+    assert!(minimize(MINIMIZE_DB, &options).is_empty());
+
+    // a1i depends on an axiom which the proof of a1iw does not use
+    let text = MINIMIZE_DB
+        .replace("ax-1 $a", "ax-1 $a |- ( ph -> ( ps -> ph ) ) $. ax-1b $a")
+        .replace("wph wps ax-1 ax-mp", "wph wps ax-1b ax-mp");
+    assert!(minimize(&text, &MinimizeOptions::default()).is_empty());
+    let options = MinimizeOptions {
+        allow_new_axioms: true,
+        ..MinimizeOptions::default()
+    };
+    assert_eq!(minimize(&text, &options), ["a1iw a1i"]);
+    let options = MinimizeOptions {
+        allow_new_axioms: true,
+        forbid: vec!["ax-1b".to_owned()],
+        ..MinimizeOptions::default()
+    };
+    assert!(minimize(&text, &options).is_empty());
+
+    let mut db = mkdb(MINIMIZE_DB.as_bytes());
+    assert_eq!(
+        db.minimize_proofs(Some(&["ax-1"]), &MinimizeOptions::default())
+            .unwrap_err(),
+        EditError::NotProvable("ax-1".to_owned())
+    );
+    let options = MinimizeOptions {
+        forbid: vec!["ax-2".to_owned()],
+        ..MinimizeOptions::default()
+    };
+    assert_eq!(
+        db.minimize_proofs(None, &options).unwrap_err(),
+        EditError::UnknownLabel("ax-2".to_owned())
+    );
+}
+
+#[test]
+fn test_minimize_discouraged() {
+    let text = MINIMIZE_DB.replace(
+        "an antecedent. $)",
+        "an antecedent.\n      (New usage is discouraged.) $)",
+    );
+    assert!(minimize(&text, &MinimizeOptions::default()).is_empty());
+    let options = MinimizeOptions {
+        override_discouraged: true,
+        ..MinimizeOptions::default()
+    };
+    assert_eq!(minimize(&text, &options), ["a1iw a1i"]);
+
+    let text = MINIMIZE_DB.replace(
+        "without ~ a1i . $)",
+        "without ~ a1i .  (Proof modification\n      is discouraged.) $)",
+    );
+    let mut db = mkdb(text.as_bytes());
+    let report = db
+        .minimize_proofs(None, &MinimizeOptions::default())
+        .unwrap();
+    assert!(report.minimized.is_empty());
+    assert_eq!(report.discouraged, ["a1iw"]);
+    let options = MinimizeOptions {
+        override_discouraged: true,
+        ..MinimizeOptions::default()
+    };
+    assert_eq!(minimize(&text, &options), ["a1iw a1i"]);
+}
+
+#[test]
+fn test_minimize_commented_proof() {
+    // proofs containing comments are reported, and left unchanged
+    let text = MINIMIZE_DB.replace("$=\n          wph", "$= $( kept $)\n          wph");
+    let mut db = mkdb(text.as_bytes());
+    let report = db
+        .minimize_proofs(None, &MinimizeOptions::default())
+        .unwrap();
+    assert_eq!(report.minimized.len(), 1);
+    assert_eq!(
+        db.apply_minimized(&report, ProofStyle::Normal).unwrap(),
+        ["a1iw"]
+    );
+    db.name_pass();
+    assert_eq!(db.statement("a1iw").unwrap().proof_len(), 15);
+}
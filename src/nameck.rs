@@ -30,11 +30,13 @@ use crate::parser::Token;
 use crate::parser::TokenAddress;
 use crate::parser::TokenPtr;
 use crate::segment_set::SegmentSet;
-use crate::util::HashMap;
 use crate::util::HashSet;
+use crate::util::PersistentMap;
+use crate::util::PersistentVec;
 use std::borrow::Borrow;
 use std::hash::Hash;
 use std::sync::Arc;
+use std::time::Instant;
 use std::u32;
 
 // An earlier version of this module was tasked with detecting duplicate symbol errors;
@@ -65,6 +67,22 @@ use std::u32;
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default, Hash)]
 pub struct Atom(u32);
 
+impl Atom {
+    /// Atoms allocated by the nameset are below this value; atoms above are
+    /// never allocated, and reserved for work variables.
+    pub(crate) const RESERVED: u32 = 1 << 31;
+
+    /// Builds an atom from its raw value.
+    pub(crate) const fn from_raw(raw: u32) -> Atom {
+        Atom(raw)
+    }
+
+    /// Returns the raw value of this atom.
+    pub(crate) const fn raw(self) -> u32 {
+        self.0
+    }
+}
+
 // currently we use Vecs for a lot of things in the index.  we might consider
 // changing it to make it more compact in memory, to the end of making database
 // clone even cheaper.
@@ -83,16 +101,19 @@ fn slot_remove<A: Eq, V>(slot: &mut NameSlot<A, V>, address: &A) {
     slot.retain(|x| x.0 != *address);
 }
 
-fn autoviv<K: Hash + Eq, V: Default>(map: &mut HashMap<K, V>, key: K) -> &mut V {
+fn autoviv<K: Hash + Eq + Clone, V: Default + Clone>(
+    map: &mut PersistentMap<K, V>,
+    key: K,
+) -> &mut V {
     map.entry(key).or_insert_with(Default::default)
 }
 
-fn deviv<K, Q: ?Sized, V, F>(map: &mut HashMap<K, V>, key: &Q, fun: F)
+fn deviv<K, Q: ?Sized, V, F>(map: &mut PersistentMap<K, V>, key: &Q, fun: F)
 where
     F: FnOnce(&mut V),
-    K: Borrow<Q> + Hash + Eq,
+    K: Borrow<Q> + Hash + Eq + Clone,
     Q: Hash + Eq,
-    V: Default + Eq,
+    V: Default + Eq + Clone,
 {
     let kill = match map.get_mut(key) {
         None => false,
@@ -127,21 +148,21 @@ struct LabelInfo {
 
 #[derive(Default, Debug, Clone)]
 struct AtomTable {
-    table: HashMap<Token, Atom>,
-    reverse: Vec<Token>,
+    table: PersistentMap<Token, Atom>,
+    reverse: PersistentVec<Token>,
 }
 
 fn intern(table: &mut AtomTable, tok: TokenPtr<'_>) -> Atom {
     let next = Atom(table.table.len() as u32 + 1);
-    assert!(next.0 < u32::max_value(), "atom table overflowed");
+    assert!(next.0 < Atom::RESERVED, "atom table overflowed");
     if let Some(&atom) = table.table.get(tok) {
         return atom;
     }
     table.table.insert(tok.into(), next);
     if table.reverse.is_empty() {
-        table.reverse.push(Token::default());
+        table.reverse.push_back(Token::default());
     }
-    table.reverse.push(tok.into());
+    table.reverse.push_back(tok.into());
     next
 }
 
@@ -158,10 +179,10 @@ pub struct Nameset {
 
     generation: usize,
     dv_gen: usize,
-    segments: HashMap<SegmentId, Arc<Segment>>,
+    segments: PersistentMap<SegmentId, Arc<Segment>>,
     dv_info: NameSlot<StatementAddress, Vec<Atom>>,
-    labels: HashMap<Token, LabelInfo>,
-    symbols: HashMap<Token, SymbolInfo>,
+    labels: PersistentMap<Token, LabelInfo>,
+    symbols: PersistentMap<Token, SymbolInfo>,
 }
 
 impl Nameset {
@@ -196,14 +217,20 @@ impl Nameset {
             self.remove_segment(seg_id);
         }
 
-        for sref in segs.segments() {
-            self.add_segment(sref.id, sref.segment);
+        let srefs = segs.segments();
+        let count = srefs.len();
+        for (index, sref) in srefs.into_iter().enumerate() {
+            let start = Instant::now();
+            let reused = !self.add_segment(sref.id, sref.segment);
+            segs.progress
+                .segment("nameck", start, index, count, &sref.buffer, reused);
         }
     }
 
-    fn add_segment(&mut self, id: SegmentId, segment: &Arc<Segment>) {
+    /// Returns false if the segment was already present.
+    fn add_segment(&mut self, id: SegmentId, segment: &Arc<Segment>) -> bool {
         if self.segments.contains_key(&id) {
-            return;
+            return false;
         }
 
         // for each entity in the segment that we're adding: find the slot, bump
@@ -276,6 +303,7 @@ impl Nameset {
                 vars,
             );
         }
+        true
     }
 
     fn remove_segment(&mut self, id: SegmentId) {
@@ -486,6 +486,18 @@ impl<'a> SegmentRef<'a> {
     pub fn bytes(self) -> usize {
         self.buffer.len()
     }
+
+    /// Returns the part of the buffer covered by this segment.  The segments
+    /// parsed from a buffer cover it without gaps, each one ending after its
+    /// file inclusion or at the end of the buffer.
+    #[must_use]
+    pub fn span(self) -> Span {
+        let statements = &self.segment.statements;
+        Span::new2(
+            statements[0].span.start,
+            statements[statements.len() - 1].span.end,
+        )
+    }
 }
 
 impl<'a> IntoIterator for SegmentRef<'a> {
@@ -9,6 +9,7 @@ use crate::parser::StatementType;
 use crate::parser::TokenRef;
 use crate::parser::NO_STATEMENT;
 use std::cmp::Ordering;
+use std::sync::Arc;
 
 #[test]
 #[allow(clippy::many_single_char_names)]
@@ -91,6 +92,21 @@ fn test_stref_v() {
     assert_eq!(zz, vec![b"Y", b"Z"]);
 }
 
+#[test]
+fn test_reparse_cache() {
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    let data = vec![("test.mm".to_owned(), b"$c X $. ${ $}".to_vec())];
+    db.parse("test.mm".to_owned(), data.clone());
+    let first = db.parse_result().segments()[0].segment.clone();
+    // the same text is found in the parse cache, and not parsed again
+    db.parse("test.mm".to_owned(), data);
+    let second = db.parse_result().segments()[0].segment;
+    assert!(Arc::ptr_eq(&first, second));
+}
+
 macro_rules! parse_test {
     ($name:ident, $text:expr, $diags:expr) => {
         #[test]
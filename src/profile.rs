@@ -0,0 +1,166 @@
+//! Per-proof verification metrics.
+//!
+//! The verify pass only reports errors, and its timing covers the whole
+//! database.  `Database::profile_proofs` verifies every proof again,
+//! recording how long each one took along with a few size metrics, so that
+//! pathological proofs can be found before they show up as a regression in
+//! the total verification time.
+//!
+//! The functions in this module sort and export the resulting records.
+
+use crate::parser::StatementAddress;
+use crate::util::json_string;
+use std::cmp::Reverse;
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+/// Metrics recorded while verifying a single `$p` statement.
+#[derive(Clone, Debug)]
+pub struct ProofProfile {
+    /// Address of the `$p` statement.
+    pub address: StatementAddress,
+    /// Label of the `$p` statement.
+    pub label: String,
+    /// True if the proof verified successfully.  Metrics for failed proofs
+    /// only cover the steps before the error.
+    pub valid: bool,
+    /// Time spent verifying the proof.
+    pub time: Duration,
+    /// Number of proof steps executed, counting hypotheses and recalled
+    /// saved steps.
+    pub steps: usize,
+    /// Largest number of entries on the proof stack.
+    pub max_depth: usize,
+    /// Number of distinct statements referenced by label in the proof.
+    pub referenced: usize,
+    /// Number of characters in the step string, for compressed proofs.
+    pub compressed_length: Option<usize>,
+}
+
+/// A criterion for ranking proofs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProfileKey {
+    /// Verification time.
+    Time,
+    /// Number of proof steps.
+    Steps,
+    /// Maximum stack depth.
+    Depth,
+    /// Number of distinct referenced statements.
+    Referenced,
+    /// Compressed proof length.
+    CompressedLength,
+}
+
+impl ProfileKey {
+    /// All keys, in the order they are reported.
+    pub const ALL: [ProfileKey; 5] = [
+        ProfileKey::Time,
+        ProfileKey::Steps,
+        ProfileKey::Depth,
+        ProfileKey::Referenced,
+        ProfileKey::CompressedLength,
+    ];
+
+    /// A short description of the key, for report headings.
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            ProfileKey::Time => "Slowest proofs",
+            ProfileKey::Steps => "Proofs with the most steps",
+            ProfileKey::Depth => "Proofs with the deepest stack",
+            ProfileKey::Referenced => "Proofs referencing the most statements",
+            ProfileKey::CompressedLength => "Longest compressed proofs",
+        }
+    }
+
+    fn value(self, profile: &ProofProfile) -> u128 {
+        match self {
+            ProfileKey::Time => profile.time.as_nanos(),
+            ProfileKey::Steps => profile.steps as u128,
+            ProfileKey::Depth => profile.max_depth as u128,
+            ProfileKey::Referenced => profile.referenced as u128,
+            ProfileKey::CompressedLength => profile.compressed_length.unwrap_or(0) as u128,
+        }
+    }
+}
+
+/// Returns the `count` largest proofs according to `key`, largest first.
+/// Ties keep database order.
+#[must_use]
+pub fn top(profiles: &[ProofProfile], key: ProfileKey, count: usize) -> Vec<&ProofProfile> {
+    let mut sorted: Vec<&ProofProfile> = profiles.iter().collect();
+    sorted.sort_by_key(|profile| Reverse(key.value(profile)));
+    sorted.truncate(count);
+    sorted
+}
+
+/// Prints the `count` largest proofs for each `ProfileKey`.
+pub fn print_top(profiles: &[ProofProfile], count: usize) {
+    for key in ProfileKey::ALL {
+        println!("{} (top {}):", key.description(), count);
+        for profile in top(profiles, key, count) {
+            println!(
+                "  {:<30} {:>8}us {:>8} steps {:>5} depth {:>5} refs {:>8} chars",
+                profile.label,
+                profile.time.as_micros(),
+                profile.steps,
+                profile.max_depth,
+                profile.referenced,
+                profile
+                    .compressed_length
+                    .map_or_else(|| "-".to_owned(), |len| len.to_string()),
+            );
+        }
+    }
+}
+
+/// Writes the records as CSV, with a header line.  Times are in microseconds;
+/// the compressed length is empty for proofs which are not compressed.
+pub fn write_csv<W: Write>(profiles: &[ProofProfile], out: &mut W) -> io::Result<()> {
+    writeln!(
+        out,
+        "label,valid,time_us,steps,max_depth,referenced,compressed_length"
+    )?;
+    for profile in profiles {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            profile.label,
+            profile.valid,
+            profile.time.as_micros(),
+            profile.steps,
+            profile.max_depth,
+            profile.referenced,
+            profile
+                .compressed_length
+                .map_or_else(String::new, |len| len.to_string()),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the records as a JSON array of objects, using the same field names
+/// as the CSV header.  The compressed length is `null` for proofs which are
+/// not compressed.
+pub fn write_json<W: Write>(profiles: &[ProofProfile], out: &mut W) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (index, profile) in profiles.iter().enumerate() {
+        write!(
+            out,
+            "{{\"label\":{},\"valid\":{},\"time_us\":{},\"steps\":{},\"max_depth\":{},\"referenced\":{},\"compressed_length\":{}}}",
+            json_string(&profile.label),
+            profile.valid,
+            profile.time.as_micros(),
+            profile.steps,
+            profile.max_depth,
+            profile.referenced,
+            profile
+                .compressed_length
+                .map_or_else(|| "null".to_owned(), |len| len.to_string()),
+        )?;
+        writeln!(out, "{}", if index + 1 < profiles.len() { "," } else { "" })?;
+    }
+    writeln!(out, "]")
+}
@@ -0,0 +1,273 @@
+//! Structured progress reporting for analysis passes.
+//!
+//! `DbOptions::timing` and `DbOptions::trace_recalc` print human-readable
+//! lines to standard output; a `ProgressObserver` registered with
+//! `Database::set_progress_observer` receives the same information as
+//! structured events, which is more convenient for progress bars and for
+//! collecting statistics about incremental reuse.
+//!
+//! Segment events are sent from the worker threads of the database's
+//! `Executor` as each job completes, so observers must be `Send + Sync` and
+//! should return quickly.  Events for different segments of the same pass may
+//! arrive in any order; `SegmentProgress::index` gives the logical position.
+//!
+//! `ChromeTrace` is an observer which records everything it sees for later
+//! inspection in a trace viewer.
+
+use crate::util::json_string;
+use std::fmt::Debug;
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::ThreadId;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Describes the processing of a single work unit by an analysis pass.
+///
+/// For the parse pass a work unit is a slice of a source file (see the
+/// discussion of autosplitting in `database`), and `index` and `count` are
+/// relative to that file; for all other passes it is a segment, and `index`
+/// and `count` are relative to the whole database.
+#[derive(Copy, Clone, Debug)]
+pub struct SegmentProgress<'a> {
+    /// Name of the pass, as printed by the `timing` option.
+    pub pass: &'static str,
+    /// Position of the work unit, counting from zero.
+    pub index: usize,
+    /// Total number of work units for this pass.
+    pub count: usize,
+    /// Section name of the work unit, from `parser::guess_buffer_name`.
+    pub name: &'a str,
+    /// Source size of the work unit in bytes.
+    pub bytes: usize,
+    /// True if a previous result was reused rather than recalculated.
+    pub reused: bool,
+    /// Time spent on the work unit, including checking for reusability.
+    ///
+    /// The event is sent on the thread which did the work, immediately after
+    /// it finished.
+    pub elapsed: Duration,
+}
+
+/// Receives progress events from the analysis passes of a `Database`.
+///
+/// All methods have empty default implementations, so an observer only needs
+/// to implement the events it is interested in.
+pub trait ProgressObserver: Debug + Send + Sync {
+    /// Called before a pass starts running.
+    fn pass_started(&self, _pass: &'static str) {}
+
+    /// Called after a pass has finished running.
+    fn pass_finished(&self, _pass: &'static str, _elapsed: Duration) {}
+
+    /// Called when a work unit has been recalculated or reused by a pass.
+    fn segment(&self, _progress: &SegmentProgress<'_>) {}
+}
+
+/// Progress reporting state shared by the database and its segment set: the
+/// `timing` option and a possibly absent observer.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Progress {
+    timing: bool,
+    observer: Option<Arc<dyn ProgressObserver>>,
+}
+
+impl Progress {
+    /// Creates a reporter without an observer.
+    pub(crate) const fn new(timing: bool) -> Self {
+        Progress {
+            timing,
+            observer: None,
+        }
+    }
+
+    /// Replaces the observer.
+    pub(crate) fn set_observer(&mut self, observer: Option<Arc<dyn ProgressObserver>>) {
+        self.observer = observer;
+    }
+
+    /// Forwards a pass start event, if there is an observer.
+    pub(crate) fn pass_started(&self, pass: &'static str) {
+        if let Some(obs) = &self.observer {
+            obs.pass_started(pass);
+        }
+    }
+
+    /// Prints the time taken by a pass if the `timing` option is set, and
+    /// forwards a pass end event if there is an observer.
+    pub(crate) fn pass_finished(&self, pass: &'static str, elapsed: Duration) {
+        if self.timing {
+            // no as_msecs :(
+            println!("{} {}ms", pass, (elapsed * 1000).as_secs());
+        }
+        if let Some(obs) = &self.observer {
+            obs.pass_finished(pass, elapsed);
+        }
+    }
+
+    /// Forwards a segment event, if there is an observer.
+    ///
+    /// The section name is only computed when somebody is listening.
+    pub(crate) fn segment(
+        &self,
+        pass: &'static str,
+        start: Instant,
+        index: usize,
+        count: usize,
+        buffer: &[u8],
+        reused: bool,
+    ) {
+        if let Some(obs) = &self.observer {
+            obs.segment(&SegmentProgress {
+                pass,
+                index,
+                count,
+                name: crate::parser::guess_buffer_name(buffer),
+                bytes: buffer.len(),
+                reused,
+                elapsed: start.elapsed(),
+            });
+        }
+    }
+}
+
+/// A `ProgressObserver` which records each pass and each segment job as a
+/// span, and can write them out in the Chrome trace event format.
+///
+/// The output can be loaded into `chrome://tracing` or Perfetto.  Each thread
+/// which did work shows up as a separate track, labelled in order of first
+/// appearance, which makes load imbalance between `Executor` workers visible.
+#[derive(Debug)]
+pub struct ChromeTrace {
+    epoch: Instant,
+    state: Mutex<TraceState>,
+}
+
+#[derive(Debug, Default)]
+struct TraceState {
+    /// Threads seen so far; the index is used as the trace thread id.
+    threads: Vec<(ThreadId, Option<String>)>,
+    spans: Vec<TraceSpan>,
+}
+
+#[derive(Debug)]
+struct TraceSpan {
+    name: String,
+    category: &'static str,
+    tid: usize,
+    start: Duration,
+    elapsed: Duration,
+    /// Index, count, bytes and reuse flag for segment spans.
+    segment: Option<(usize, usize, usize, bool)>,
+}
+
+impl Default for ChromeTrace {
+    fn default() -> Self {
+        ChromeTrace {
+            epoch: Instant::now(),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl ChromeTrace {
+    /// Creates an empty trace; all timestamps will be relative to now.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(
+        &self,
+        name: &str,
+        category: &'static str,
+        elapsed: Duration,
+        segment: Option<(usize, usize, usize, bool)>,
+    ) {
+        let start = self.epoch.elapsed().saturating_sub(elapsed);
+        let thread = thread::current();
+        let mut state = self.state.lock().unwrap();
+        let known = state.threads.iter().position(|t| t.0 == thread.id());
+        let tid = known.unwrap_or_else(|| {
+            state
+                .threads
+                .push((thread.id(), thread.name().map(str::to_owned)));
+            state.threads.len() - 1
+        });
+        state.spans.push(TraceSpan {
+            name: name.to_owned(),
+            category,
+            tid,
+            start,
+            elapsed,
+            segment,
+        });
+    }
+
+    /// Writes all spans recorded so far as a JSON trace event file.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        writeln!(out, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+        let mut first = true;
+        for (tid, (_, name)) in state.threads.iter().enumerate() {
+            let name = name.clone().unwrap_or_else(|| format!("thread {tid}"));
+            if !first {
+                writeln!(out, ",")?;
+            }
+            first = false;
+            write!(
+                out,
+                "{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":{}}}}}",
+                tid,
+                json_string(&name)
+            )?;
+        }
+        for span in &state.spans {
+            if !first {
+                writeln!(out, ",")?;
+            }
+            first = false;
+            write!(
+                out,
+                "{{\"ph\":\"X\",\"name\":{},\"cat\":{},\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{}",
+                json_string(&span.name),
+                json_string(span.category),
+                span.tid,
+                span.start.as_micros(),
+                span.elapsed.as_micros()
+            )?;
+            if let Some((index, count, bytes, reused)) = span.segment {
+                write!(
+                    out,
+                    ",\"args\":{{\"index\":{index},\"count\":{count},\"bytes\":{bytes},\"reused\":{reused}}}"
+                )?;
+            }
+            write!(out, "}}")?;
+        }
+        drop(state);
+        writeln!(out, "\n]}}")
+    }
+}
+
+impl ProgressObserver for ChromeTrace {
+    fn pass_finished(&self, pass: &'static str, elapsed: Duration) {
+        self.record(pass, "pass", elapsed, None);
+    }
+
+    fn segment(&self, progress: &SegmentProgress<'_>) {
+        self.record(
+            progress.name,
+            progress.pass,
+            progress.elapsed,
+            Some((
+                progress.index,
+                progress.count,
+                progress.bytes,
+                progress.reused,
+            )),
+        );
+    }
+}
@@ -9,7 +9,7 @@ use crate::segment_set::SegmentSet;
 use crate::util::HashMap;
 use crate::verify::{verify_one, ProofBuilder};
 use crate::Database;
-use std::cmp::{max, Ord, Ordering, PartialOrd};
+use std::cmp::{max, Ord, Ordering, PartialOrd, Reverse};
 use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::{BinaryHeap, VecDeque};
 use std::fmt::{self, Write};
@@ -20,7 +20,8 @@ use std::u16;
 /// A tree structure for storing proofs and grammar derivations.
 #[derive(Clone, Debug, Eq)]
 pub struct ProofTree {
-    /// The axiom/theorem being applied at the root.
+    /// The axiom/theorem being applied at the root, or the default address
+    /// for an unknown step `?` of an incomplete proof.
     pub address: StatementAddress,
     /// The hypotheses ($e and $f) in database order, indexes into the parent `ProofTreeArray`.
     pub children: Vec<usize>,
@@ -59,6 +60,18 @@ impl ProofTree {
             hash: hasher.finish(),
         }
     }
+
+    /// Returns `true` if this is an unknown step `?` of an incomplete proof.
+    #[must_use]
+    pub fn is_unknown(&self) -> bool {
+        is_unknown(self.address)
+    }
+}
+
+/// Returns `true` if the address is the one used for unknown steps, which is
+/// not the address of any statement since segments are numbered from 1.
+fn is_unknown(address: StatementAddress) -> bool {
+    address == StatementAddress::default()
 }
 
 /// An array of proof trees, used to collect steps of a proof
@@ -68,7 +81,8 @@ pub struct ProofTreeArray {
     map: HashMap<u64, usize>,
     /// The list of proof trees
     pub trees: Vec<ProofTree>,
-    /// The uncompressed strings for each proof tree
+    /// The uncompressed strings for each proof tree, empty for unknown steps
+    /// and the steps whose expression depends on them
     pub exprs: Vec<Vec<u8>>,
     /// The QED step
     pub qed: usize,
@@ -116,7 +130,8 @@ impl ProofTreeArray {
 
     /// Create a proof tree array from the proof a single $p statement,
     /// returning the result of the given proof builder, or an error if the
-    /// proof is faulty
+    /// proof is faulty.  The unknown steps `?` of an incomplete proof are
+    /// kept as unknown trees.
     pub(crate) fn new(db: &Database, stmt: StatementRef<'_>) -> Result<ProofTreeArray, Diagnostic> {
         let mut arr = ProofTreeArray::default();
         arr.qed = verify_one(db, &mut arr, stmt)?;
@@ -124,6 +139,44 @@ impl ProofTreeArray {
         Ok(arr)
     }
 
+    /// Adds a step with the given uncompressed expression, as stored in
+    /// `exprs`, and returns its index, or the index of the same step if it is
+    /// already in the array.
+    pub(crate) fn insert(
+        &mut self,
+        address: StatementAddress,
+        children: Vec<usize>,
+        expr: &[u8],
+    ) -> usize {
+        let tree = ProofTree::new(self, address, children);
+        self.index(&tree).unwrap_or_else(|| {
+            let ix = self.trees.len();
+            self.map.insert(tree.hash, ix);
+            self.trees.push(tree);
+            self.exprs.push(expr.to_vec());
+            ix
+        })
+    }
+
+    /// Returns `true` if the proof has unknown steps `?`.
+    #[must_use]
+    pub fn is_incomplete(&self) -> bool {
+        self.trees.iter().any(ProofTree::is_unknown)
+    }
+
+    /// Marks the steps whose expression is not known: the unknown steps, and
+    /// the steps with an empty expression which use one of them.
+    #[must_use]
+    pub fn unknown_exprs(&self) -> Vec<bool> {
+        let mut unknown = Vec::with_capacity(self.trees.len());
+        for (tree, expr) in self.trees.iter().zip(&self.exprs) {
+            let value = tree.is_unknown()
+                || expr.is_empty() && tree.children.iter().any(|&child| unknown[child]);
+            unknown.push(value);
+        }
+        unknown
+    }
+
     /// Get the minimum distance from each step to the QED step
     #[must_use]
     pub fn indent(&self) -> &[u16] {
@@ -211,10 +264,22 @@ impl ProofTreeArray {
     /// Write the proof as an RPN sequence with backrefs
     #[must_use]
     pub fn to_rpn(&self, parents: &[usize], explicit: bool) -> Vec<RPNStep> {
+        let saved: Vec<bool> = self
+            .trees
+            .iter()
+            .zip(parents)
+            .map(|(tree, &count)| count > 1 && !tree.children.is_empty())
+            .collect();
+        self.rpn_saving(&saved, explicit)
+    }
+
+    /// Write the proof as an RPN sequence, with backrefs to the steps marked
+    /// in `saved`, and every other step written out in full at each use
+    fn rpn_saving(&self, saved: &[bool], explicit: bool) -> Vec<RPNStep> {
         #[derive(Debug)]
         struct Env<'a> {
             arr: &'a ProofTreeArray,
-            parents: &'a [usize],
+            saved: &'a [bool],
             explicit: bool,
             out: Vec<RPNStep>,
             backrefs: Vec<usize>,
@@ -233,7 +298,7 @@ impl ProofTreeArray {
                     output_step(env, hix, n_hyp);
                 }
                 RPNStep::Normal {
-                    fwdref: if env.parents[step] > 1 && !tree.children.is_empty() {
+                    fwdref: if env.saved[step] {
                         env.count += 1;
                         env.backrefs[step] = env.count;
                         env.count
@@ -253,7 +318,7 @@ impl ProofTreeArray {
         }
         let mut env = Env {
             arr: self,
-            parents,
+            saved,
             explicit,
             out: vec![],
             backrefs: vec![0; self.trees.len()],
@@ -354,6 +419,17 @@ impl ProofBuilder for ProofTreeArray {
             ix
         })
     }
+
+    fn build_unknown(&mut self) -> Option<usize> {
+        let tree = ProofTree::new(self, StatementAddress::default(), vec![]);
+        Some(self.index(&tree).unwrap_or_else(|| {
+            let ix = self.trees.len();
+            self.map.insert(tree.hash, ix);
+            self.trees.push(tree);
+            self.exprs.push(vec![]);
+            ix
+        }))
+    }
 }
 
 /// List of possible proof output types.
@@ -369,6 +445,11 @@ pub enum ProofStyle {
     Explicit,
     /// `/packed/explicit` proof output. `/normal` with hypothesis names and backreferences.
     PackedExplicit,
+    /// `/compressed` proof output, with the label order and the reused steps
+    /// chosen to shorten the proof.  The choice is made on an estimate of the
+    /// number of step letters, so the proof is usually, but not always,
+    /// shorter than with `Compressed`.
+    OptimizedCompressed,
 }
 
 impl ProofStyle {
@@ -386,7 +467,10 @@ impl ProofStyle {
     pub const fn packed(self) -> bool {
         matches!(
             self,
-            ProofStyle::Compressed | ProofStyle::Packed | ProofStyle::PackedExplicit
+            ProofStyle::Compressed
+                | ProofStyle::Packed
+                | ProofStyle::PackedExplicit
+                | ProofStyle::OptimizedCompressed
         )
     }
 }
@@ -414,13 +498,83 @@ pub struct ProofTreePrinter<'a> {
     pub(crate) line_width: u16,
 }
 
+/// Fills words into lines of limited width.
+///
+/// Each word is written after a separating space if it fits before
+/// `line_width`, and otherwise at the start of a new line indented by `indent`
+/// spaces.  This is the line breaking used by `ProofTreePrinter`, and by the
+/// source formatter for math strings and comments.
+pub(crate) struct LineWrap<'a, W> {
+    out: &'a mut W,
+    /// A newline followed by the indentation of continuation lines
+    newline: String,
+    /// The width of the text written so far on the current line
+    chr: u16,
+    indent: u16,
+    line_width: u16,
+}
+
+impl<'a, W: Write> LineWrap<'a, W> {
+    /// Starts filling lines after `chr` characters already written on the
+    /// current line.
+    pub(crate) fn new(out: &'a mut W, chr: u16, indent: u16, line_width: u16) -> Self {
+        let mut newline = "\n".to_string();
+        for _ in 0..indent {
+            newline.push(' ');
+        }
+        LineWrap {
+            out,
+            newline,
+            chr,
+            indent,
+            line_width,
+        }
+    }
+
+    /// Writes a word, separated from the previous one by a space.
+    pub(crate) fn write_word(&mut self, word: &str) -> fmt::Result {
+        self.write_word_gap(word, 1)
+    }
+
+    /// Writes a word, separated from the previous one by `gap` spaces if they
+    /// are on the same line.
+    pub(crate) fn write_word_gap(&mut self, word: &str, gap: u16) -> fmt::Result {
+        let len = word.chars().count() as u16;
+        if self.chr + gap + len <= self.line_width {
+            self.chr += len + gap;
+            for _ in 0..gap {
+                self.out.write_char(' ')?;
+            }
+        } else {
+            self.chr = self.indent + len;
+            self.out.write_str(&self.newline)?;
+        }
+        self.out.write_str(word)
+    }
+
+    /// Writes a string which may be split at any character, such as the step
+    /// letters of a compressed proof, filling the current line first.
+    pub(crate) fn write_split(&mut self, mut letters: &str) -> fmt::Result {
+        loop {
+            let ll = (self.line_width - self.chr)
+                .checked_sub(1)
+                .unwrap_or(self.line_width - self.indent) as usize;
+            if ll < letters.len() {
+                let (left, right) = letters.split_at(ll);
+                letters = right;
+                self.write_word(left)?;
+            } else {
+                return self.write_word(letters);
+            }
+        }
+    }
+}
+
 /// The local variables of `ProofTreePrinter::fmt()`, extracted into a struct
 /// so that the inner functions can be broken out.
 struct ProofTreePrinterImpl<'a, 'b> {
     p: &'a ProofTreePrinter<'a>,
-    f: &'a mut fmt::Formatter<'b>,
-    indent: String,
-    chr: u16,
+    out: LineWrap<'a, fmt::Formatter<'b>>,
     stmt_lookup: HashMap<StatementAddress, (&'a str, Vec<&'a str>)>,
     backref_alloc: Vec<String>,
     backref_max: usize,
@@ -428,15 +582,7 @@ struct ProofTreePrinterImpl<'a, 'b> {
 
 impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
     fn write_word(&mut self, word: &str) -> fmt::Result {
-        let len = word.len() as u16;
-        if self.chr + len < self.p.line_width {
-            self.chr += len + 1;
-            self.f.write_char(' ')?;
-        } else {
-            self.chr = self.p.indent + len;
-            self.f.write_str(&self.indent)?;
-        }
-        self.f.write_str(word)
+        self.out.write_word(word)
     }
 
     fn estr(&self, hyp: Option<(StatementAddress, usize)>) -> String {
@@ -489,6 +635,10 @@ impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
         for tree in &self.p.arr.trees {
             let p = &self.p;
             if let Entry::Vacant(entry) = self.stmt_lookup.entry(tree.address) {
+                if tree.is_unknown() {
+                    entry.insert(("?", vec![]));
+                    continue;
+                }
                 let label = p.sset.statement(tree.address).label();
                 let hyps = if p.style.explicit() {
                     match p.scope.get(label) {
@@ -508,13 +658,208 @@ impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
     }
 
     fn fmt_compressed(&mut self) -> fmt::Result {
-        use StatementType::{Axiom, Essential, Floating, Provable};
         let parents = self.p.arr.count_parents();
         let rpn = self.p.arr.to_rpn(&parents, false);
-        let mut proof_ordered: Vec<(StatementRef<'_>, usize)> = vec![];
         let frame = self.p.scope.get(self.p.thm_label).unwrap();
+        self.write_compressed(&rpn, frame.mandatory_count)
+    }
+
+    fn fmt_optimized(&mut self) -> fmt::Result {
+        let frame = self.p.scope.get(self.p.thm_label).unwrap();
+        let essential_count = frame
+            .hypotheses
+            .iter()
+            .filter(|hyp| matches!(hyp, Hyp::Essential(..)))
+            .count();
+        let hyp_count = frame.mandatory_count + essential_count;
+        let rpn = self.optimized_rpn(hyp_count);
+        self.write_compressed(&rpn, hyp_count)
+    }
+
+    /// Numbers the mandatory hypotheses in the letters of a compressed
+    /// proof: the floating ones, then the essential ones.
+    fn hyp_numbers(&self) -> HashMap<StatementAddress, usize> {
+        let frame = self.p.scope.get(self.p.thm_label).unwrap();
+        let essentials: Vec<StatementAddress> = frame
+            .hypotheses
+            .iter()
+            .filter_map(|hyp| match *hyp {
+                Hyp::Essential(addr, _) => Some(addr),
+                Hyp::Floating(..) => None,
+            })
+            .collect();
+        let floating = frame.mandatory_hyps().iter().filter_map(|hyp| match *hyp {
+            Hyp::Floating(addr, ..) => Some(addr),
+            Hyp::Essential(..) => None,
+        });
+        floating
+            .chain(essentials)
+            .enumerate()
+            .map(|(number, addr)| (addr, number))
+            .collect()
+    }
+
+    /// Chooses the steps to save for backreferences so as to make the
+    /// compressed proof short.
+    ///
+    /// Saving a step used `r` times costs a `Z` and `r - 1` backreferences,
+    /// and saves writing its subproof `r - 1` more times.  The length of each
+    /// number depends on the choice of all saved steps, through the order of
+    /// the label list and the numbering of the backreferences, so the choice
+    /// is refined in a few rounds, starting from saving every step used more
+    /// than once, estimating the lengths from the previous round, and keeping
+    /// the shortest proof found.
+    fn optimized_rpn(&self, hyp_count: usize) -> Vec<RPNStep> {
+        const ROUNDS: usize = 8;
+        let arr = self.p.arr;
+        let hyps = self.hyp_numbers();
+        let is_leaf = |step: usize| arr.trees[step].children.is_empty();
+
+        // steps in the order their subproofs are first completed, which is
+        // also the order of the backreference numbers of the saved steps
+        let mut order = Vec::with_capacity(arr.trees.len());
+        let mut seen = vec![false; arr.trees.len()];
+        let mut stack = vec![(arr.qed, false)];
+        while let Some((step, expanded)) = stack.pop() {
+            if expanded {
+                order.push(step);
+            } else if !seen[step] {
+                seen[step] = true;
+                stack.push((step, true));
+                for &child in arr.trees[step].children.iter().rev() {
+                    stack.push((child, false));
+                }
+            }
+        }
+
+        let parents = arr.count_parents();
+        let mut saved: Vec<bool> = (0..arr.trees.len())
+            .map(|step| parents[step] > 1 && !is_leaf(step))
+            .collect();
+        let (mut best_len, mut best_rpn, mut step_len, mut label_count) =
+            self.compressed_len(&saved, &hyps, hyp_count);
+        for _ in 0..ROUNDS {
+            // estimated length of a backreference to each step
+            let mut backref_len = vec![0; arr.trees.len()];
+            let mut count = 0;
+            for &step in &order {
+                backref_len[step] = letter_count(hyp_count + label_count + count);
+                count += usize::from(saved[step]);
+            }
+
+            // length of writing each subproof again
+            let mut subproof_len = vec![0_usize; arr.trees.len()];
+            for step in 0..arr.trees.len() {
+                subproof_len[step] = arr.trees[step]
+                    .children
+                    .iter()
+                    .map(|&child| {
+                        if is_leaf(child) {
+                            subproof_len[child]
+                        } else {
+                            subproof_len[child].min(backref_len[child])
+                        }
+                    })
+                    .fold(step_len[step], usize::saturating_add);
+            }
+
+            // decide from the root down, since the number of uses of a step
+            // depends on whether its ancestors are saved
+            let mut uses = vec![0_usize; arr.trees.len()];
+            uses[arr.qed] = 1;
+            let mut next = vec![false; arr.trees.len()];
+            for step in (0..arr.trees.len()).rev() {
+                let count = uses[step];
+                if count == 0 {
+                    continue;
+                }
+                let again = count - 1;
+                next[step] = !is_leaf(step)
+                    && again > 0
+                    && again.saturating_mul(backref_len[step]).saturating_add(1)
+                        < again.saturating_mul(subproof_len[step]);
+                let child_uses = if next[step] { 1 } else { count };
+                for &child in &arr.trees[step].children {
+                    uses[child] = uses[child].saturating_add(child_uses);
+                }
+            }
+            if next == saved {
+                break;
+            }
+            saved = next;
+            let (len, rpn, lens, labels) = self.compressed_len(&saved, &hyps, hyp_count);
+            if len < best_len {
+                best_len = len;
+                best_rpn = rpn;
+            }
+            step_len = lens;
+            label_count = labels;
+        }
+        best_rpn
+    }
+
+    /// Computes the number of letters in the compressed proof which saves
+    /// the given steps, returning it with the RPN sequence, the length of the
+    /// letters of each step, and the number of labels in the label list.
+    fn compressed_len(
+        &self,
+        saved: &[bool],
+        hyps: &HashMap<StatementAddress, usize>,
+        hyp_count: usize,
+    ) -> (usize, Vec<RPNStep>, Vec<usize>, usize) {
+        let arr = self.p.arr;
+        let rpn = arr.rpn_saving(saved, false);
+        let mut uses: HashMap<StatementAddress, usize> = HashMap::default();
         for item in &rpn {
+            match *item {
+                RPNStep::Normal { addr, .. } if !is_unknown(addr) => {
+                    *uses.entry(addr).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+        let mut labels: Vec<(StatementAddress, usize)> = uses
+            .into_iter()
+            .filter(|(addr, _)| !hyps.contains_key(addr))
+            .collect();
+        // the labels used most get the smallest numbers
+        labels.sort_unstable_by_key(|&(_, count)| Reverse(count));
+        let mut numbers = hyps.clone();
+        numbers.extend(
+            labels
+                .iter()
+                .enumerate()
+                .map(|(pos, &(addr, _))| (addr, hyp_count + pos)),
+        );
+        let step_len: Vec<usize> = arr
+            .trees
+            .iter()
+            .map(|tree| numbers.get(&tree.address).map_or(1, |&n| letter_count(n)))
+            .collect();
+        let len = rpn
+            .iter()
+            .map(|item| match *item {
+                RPNStep::Normal { addr, .. } if is_unknown(addr) => 1,
+                RPNStep::Normal { fwdref, addr, .. } => {
+                    letter_count(numbers[&addr]) + usize::from(fwdref != 0)
+                }
+                RPNStep::Backref { backref, .. } => {
+                    letter_count(hyp_count + labels.len() + backref - 1)
+                }
+            })
+            .sum();
+        (len, rpn, step_len, labels.len())
+    }
+
+    fn write_compressed(&mut self, rpn: &[RPNStep], block_start: usize) -> fmt::Result {
+        use StatementType::{Axiom, Essential, Floating, Provable};
+        let mut proof_ordered: Vec<(StatementRef<'_>, usize)> = vec![];
+        let frame = self.p.scope.get(self.p.thm_label).unwrap();
+        for item in rpn {
             if let RPNStep::Normal { addr, .. } = *item {
+                if is_unknown(addr) {
+                    continue;
+                }
                 let stmt = self.p.sset.statement(addr);
                 match stmt.statement_type() {
                     Floating => {
@@ -545,7 +890,7 @@ impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
 
         let mut sorted_by_refs = (0..proof_ordered.len()).collect::<Vec<usize>>();
         sorted_by_refs.sort_by(|&a, &b| proof_ordered[b].1.cmp(&proof_ordered[a].1));
-        let mut i = frame.mandatory_count;
+        let mut i = block_start;
         let mut cutoff = 20;
         while cutoff <= i {
             i -= cutoff;
@@ -613,8 +958,12 @@ impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
         paren_stmt = ess_stmt;
 
         let mut letters: Vec<u8> = vec![];
-        for item in &rpn {
+        for item in rpn {
             let (is_fwdref, mut letter) = match *item {
+                RPNStep::Normal { addr, .. } if is_unknown(addr) => {
+                    letters.push(b'?');
+                    continue;
+                }
                 RPNStep::Normal { fwdref, addr, .. } => {
                     let stmt = self.p.sset.statement(addr);
                     let pos = if stmt.statement_type() == Floating {
@@ -654,25 +1003,10 @@ impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
             }
         }
 
-        let mut letters: &[u8] = &letters;
-        loop {
-            let ll = (self.p.line_width - self.chr)
-                .checked_sub(1)
-                .unwrap_or(self.p.line_width - self.p.indent) as usize;
-            if ll < letters.len() {
-                let (left, right) = letters.split_at(ll);
-                letters = right;
-                self.write_word(as_str(left))?;
-            } else {
-                return self.write_word(as_str(letters));
-            }
-        }
+        self.out.write_split(as_str(&letters))
     }
 
     fn fmt(&mut self) -> fmt::Result {
-        self.f
-            .write_str(&self.indent[(self.p.initial_chr + 2) as usize..])?;
-
         match self.p.style {
             ProofStyle::Normal | ProofStyle::Explicit => {
                 self.init_stmt_lookup();
@@ -688,11 +1022,23 @@ impl<'a, 'b> ProofTreePrinterImpl<'a, 'b> {
                 }
             }
             ProofStyle::Compressed => self.fmt_compressed()?,
+            ProofStyle::OptimizedCompressed => self.fmt_optimized()?,
         }
         self.write_word("$.")
     }
 }
 
+/// Returns the number of letters encoding the number `n` in a compressed proof.
+const fn letter_count(n: usize) -> usize {
+    let mut count = 1;
+    let mut rest = n / 20;
+    while rest != 0 {
+        rest = (rest - 1) / 5;
+        count += 1;
+    }
+    count
+}
+
 /// Given an array of items, such that `values[i]` is the cost of the `i`th item,
 /// and the items are labeled by `items` (so only the values `i = items[j]` are
 /// relevant), find the best fit of items whose total cost is no more than `size`,
@@ -726,15 +1072,12 @@ fn knapsack_fit(items: &[usize], values: &[u16], mut size: usize, included: &mut
 
 impl<'a> fmt::Display for ProofTreePrinter<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut indent = "\n".to_string();
-        for _ in 0..self.indent {
-            indent.push(' ');
+        for _ in self.initial_chr + 1..self.indent {
+            f.write_char(' ')?;
         }
         ProofTreePrinterImpl {
             p: self,
-            f,
-            indent,
-            chr: self.indent - 1,
+            out: LineWrap::new(f, self.indent - 1, self.indent, self.line_width),
             stmt_lookup: HashMap::default(),
             backref_alloc: vec![],
             backref_max: 0,
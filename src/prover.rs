@@ -0,0 +1,466 @@
+//! Bounded automated proof search.
+//!
+//! `Database::prove` searches a proof of a goal in the context of a theorem of
+//! the database, by backward chaining: a goal is closed either by an
+//! essential hypothesis of the theorem, or by an assertion before the theorem
+//! whose conclusion unifies with it, leaving the instances of the hypotheses
+//! of the assertion as new goals.  Variables of the assertion which do not
+//! occur in its conclusion become work variables, shared between the goals
+//! and bound as the goals are closed; the work variables remaining at the end
+//! are replaced with unused variables of the database.
+//!
+//! The search is a depth-first search with iterative deepening on the depth of
+//! the proof tree, bounded by a number of steps and an optional time limit.
+//! Each assertion is applied only if the substitution respects its
+//! disjoint variable conditions in the context of the theorem, so that the
+//! resulting proof is valid.
+
+use crate::formula::scoped_variables;
+use crate::formula::Label;
+use crate::formula::Substitutions;
+use crate::formula::WorkVariable;
+use crate::formula::WorkVariables;
+use crate::parser::as_str;
+use crate::parser::Comparer;
+use crate::parser::StatementAddress;
+use crate::parser::StatementType;
+use crate::proof::ProofTreeArray;
+use crate::scopeck::Frame;
+use crate::scopeck::Hyp;
+use crate::search::SearchIndex;
+use crate::verify::ProofBuilder;
+use crate::worksheet::Worksheet;
+use crate::Database;
+use crate::Formula;
+use std::cmp::Ordering;
+use std::fmt;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The bounds and the assertions of a proof search
+#[derive(Debug, Clone)]
+pub struct ProverOptions {
+    /// The maximal depth of the proof tree, in assertions applied from the goal
+    pub max_depth: usize,
+    /// The maximal number of assertion applications tried
+    pub max_steps: usize,
+    /// The maximal duration of the search, if any
+    pub timeout: Option<Duration>,
+    /// The labels of the assertions which may be used, `None` for all the
+    /// assertions before the theorem
+    pub allowed: Option<Vec<String>>,
+}
+
+impl Default for ProverOptions {
+    fn default() -> Self {
+        ProverOptions {
+            max_depth: 5,
+            max_steps: 100_000,
+            timeout: None,
+            allowed: None,
+        }
+    }
+}
+
+/// The reasons why no proof was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProveError {
+    /// The theorem is not a `$p` statement of the database
+    UnknownTheorem(String),
+    /// An allowed label is not an assertion before the theorem
+    UnknownLabel(String),
+    /// The worksheet has no step of that name
+    UnknownStep(String),
+    /// The worksheet step has no formula to prove
+    Incomplete,
+    /// No proof exists within the maximal depth
+    NotFound,
+    /// The step or time budget ran out before a proof was found
+    BudgetExhausted,
+}
+
+impl fmt::Display for ProveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ProveError::UnknownTheorem(ref label) => write!(f, "Unknown theorem {label}"),
+            ProveError::UnknownLabel(ref label) => {
+                write!(f, "{label} is not an assertion before the theorem")
+            }
+            ProveError::UnknownStep(ref name) => write!(f, "Unknown step {name}"),
+            ProveError::Incomplete => write!(f, "The step has no formula"),
+            ProveError::NotFound => write!(f, "No proof found within the maximal depth"),
+            ProveError::BudgetExhausted => write!(f, "The search budget ran out"),
+        }
+    }
+}
+
+impl std::error::Error for ProveError {}
+
+/// A node of a proof under construction
+#[derive(Debug, Clone)]
+enum Node {
+    /// A goal which is not closed yet
+    Open,
+    /// A goal closed by an essential hypothesis of the theorem
+    Hyp(StatementAddress),
+    /// A goal closed by an assertion, with the substitutions of its variables
+    /// and the nodes of its essential hypotheses
+    Apply(StatementAddress, Substitutions, Vec<usize>),
+}
+
+/// The state of a proof search
+#[derive(Debug, Clone)]
+struct State {
+    /// The nodes of the proof, the goal being the first one
+    nodes: Vec<Node>,
+    /// The formulas of the nodes, before the substitution of work variables
+    formulas: Vec<Formula>,
+    /// The open goals, with their depth
+    goals: Vec<(usize, usize)>,
+    /// The substitutions of the work variables bound so far
+    bindings: Substitutions,
+    /// The work variables allocated so far
+    work_variables: WorkVariables,
+}
+
+/// A proof search in the context of a theorem
+struct Prover<'a> {
+    db: &'a Database,
+    index: SearchIndex,
+    theorem: StatementAddress,
+    frame: &'a Frame,
+    /// The variables in scope at the theorem, replacing the work variables
+    variables: Vec<Label>,
+    hypotheses: Vec<(StatementAddress, &'a Formula)>,
+    allowed: Option<Vec<StatementAddress>>,
+    max_steps: usize,
+    deadline: Option<Instant>,
+    steps: usize,
+    exhausted: bool,
+}
+
+impl Database {
+    /// Searches a proof of the given goal, from the hypotheses of the given
+    /// theorem and the assertions before it, within the bounds of the options.
+    ///
+    /// On success, returns the proof as a proof tree array whose `qed` step
+    /// proves the goal; it may be rendered with the theorem as context, for
+    /// example with [`crate::proof::ProofTreePrinter`].
+    /// Requires the statements to have been parsed.
+    pub fn prove(
+        &self,
+        theorem: &str,
+        goal: &Formula,
+        options: &ProverOptions,
+    ) -> Result<ProofTreeArray, ProveError> {
+        let sref = self
+            .statement(theorem)
+            .filter(|sref| sref.statement_type() == StatementType::Provable)
+            .ok_or_else(|| ProveError::UnknownTheorem(theorem.to_owned()))?;
+        let frame = self
+            .scope_result()
+            .get(sref.label())
+            .ok_or_else(|| ProveError::UnknownTheorem(theorem.to_owned()))?;
+        let stmt_parse = self.stmt_parse_result();
+        let mut hypotheses = Vec::new();
+        for hyp in &*frame.hypotheses {
+            if let Hyp::Essential(address, _) = *hyp {
+                if let Some(formula) = stmt_parse.get_formula(&self.statement_by_address(address)) {
+                    hypotheses.push((address, formula));
+                }
+            }
+        }
+        let allowed = if let Some(ref labels) = options.allowed {
+            let order = &self.parse_result().order;
+            let mut allowed = Vec::with_capacity(labels.len());
+            for label in labels {
+                let address = self
+                    .statement(label)
+                    .filter(|allowed| {
+                        matches!(
+                            allowed.statement_type(),
+                            StatementType::Axiom | StatementType::Provable
+                        ) && order.cmp(&allowed.address(), &sref.address()) == Ordering::Less
+                    })
+                    .ok_or_else(|| ProveError::UnknownLabel(label.clone()))?
+                    .address();
+                allowed.push(address);
+            }
+            Some(allowed)
+        } else {
+            None
+        };
+
+        let mut prover = Prover {
+            db: self,
+            index: SearchIndex::new(self),
+            theorem: sref.address(),
+            frame,
+            variables: scoped_variables(self, frame),
+            hypotheses,
+            allowed,
+            max_steps: options.max_steps,
+            deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+            steps: 0,
+            exhausted: false,
+        };
+        let mut work_variables = WorkVariables::default();
+        work_variables.reserve(goal);
+        let state = State {
+            nodes: vec![Node::Open],
+            formulas: vec![goal.clone()],
+            goals: vec![(0, 0)],
+            bindings: Substitutions::default(),
+            work_variables,
+        };
+        for limit in 1..=options.max_depth {
+            if let Some(arr) = prover.search(&state, limit) {
+                return Ok(arr);
+            }
+            if prover.exhausted {
+                return Err(ProveError::BudgetExhausted);
+            }
+        }
+        Err(ProveError::NotFound)
+    }
+
+    /// Searches a proof of a step of a worksheet, like a step justified by
+    /// `?`, in the context of the theorem of the worksheet, which must be in
+    /// the database.  See [`Database::prove`].
+    pub fn prove_worksheet_step(
+        &self,
+        worksheet: &Worksheet,
+        step: &str,
+        options: &ProverOptions,
+    ) -> Result<ProofTreeArray, ProveError> {
+        let theorem = worksheet.theorem.as_deref().unwrap_or_default();
+        let formula = worksheet
+            .step(step)
+            .ok_or_else(|| ProveError::UnknownStep(step.to_owned()))?
+            .formula
+            .as_ref()
+            .ok_or(ProveError::Incomplete)?;
+        self.prove(theorem, formula, options)
+    }
+}
+
+impl Prover<'_> {
+    /// Returns whether the step or time budget ran out, recording it
+    fn out_of_budget(&mut self) -> bool {
+        if self.steps >= self.max_steps
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.exhausted = true;
+        }
+        self.exhausted
+    }
+
+    /// Closes the open goals of a state, applying assertions only to goals
+    /// of depth below the limit, and returns the proof once all are closed.
+    fn search(&mut self, state: &State, limit: usize) -> Option<ProofTreeArray> {
+        if state.goals.is_empty() {
+            return self.finish(state);
+        }
+        if self.out_of_budget() {
+            return None;
+        }
+
+        // Close the most constrained goal first: bare work variables last,
+        // then the goals with the fewest work variables.
+        let goals = state
+            .goals
+            .iter()
+            .map(|&(node, _)| state.formulas[node].substitute(&state.bindings))
+            .collect::<Vec<_>>();
+        let (index, goal) = goals
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, goal)| {
+                let work_variables = goal.work_variables().len();
+                let bare = goal.preorder().len() == 1 && work_variables == 1;
+                (bare, work_variables)
+            })
+            .expect("there are open goals");
+        let (node, depth) = state.goals[index];
+
+        // Close the goal with a hypothesis
+        for (address, hypothesis) in self.hypotheses.clone() {
+            let mut bindings = state.bindings.clone();
+            if bindings.unify(goal, hypothesis)
+                && bindings
+                    .iter()
+                    .all(|(label, _)| WorkVariable::from_atom(label).is_some())
+            {
+                let mut next = state.clone();
+                next.goals.remove(index);
+                next.nodes[node] = Node::Hyp(address);
+                next.bindings = bindings;
+                if let Some(arr) = self.search(&next, limit) {
+                    return Some(arr);
+                }
+            }
+        }
+        if depth >= limit {
+            return None;
+        }
+
+        // Close the goal with an assertion, opening its hypotheses as new goals
+        let applications =
+            self.index
+                .applicable_with(self.db, goal, Some(self.theorem), &state.work_variables);
+        for application in applications {
+            if let Some(ref allowed) = self.allowed {
+                if !allowed.contains(&application.address) {
+                    continue;
+                }
+            }
+            self.steps += 1;
+            if self.out_of_budget() {
+                return None;
+            }
+            let sref = self.db.statement_by_address(application.address);
+            if let Some(frame) = self.db.scope_result().get(sref.label()) {
+                if !self
+                    .db
+                    .check_dv(frame, &application.substitutions, self.frame)
+                    .is_valid()
+                {
+                    continue;
+                }
+            }
+            let mut next = state.clone();
+            next.goals.remove(index);
+            for (_, formula) in application.substitutions.iter() {
+                next.work_variables.reserve(formula);
+            }
+            let mut children = Vec::with_capacity(application.hypotheses.len());
+            for hypothesis in application.hypotheses {
+                let child = next.nodes.len();
+                next.nodes.push(Node::Open);
+                next.formulas.push(hypothesis);
+                next.goals.push((child, depth + 1));
+                children.push(child);
+            }
+            next.nodes[node] =
+                Node::Apply(application.address, application.substitutions, children);
+            next.bindings.extend(&application.goal_substitutions);
+            next.bindings.normalize();
+            if let Some(arr) = self.search(&next, limit) {
+                return Some(arr);
+            }
+        }
+        None
+    }
+
+    /// Builds the proof of a state whose goals are all closed, replacing the
+    /// remaining work variables with unused variables, or returns `None` if
+    /// that breaks a disjoint variable condition.
+    fn finish(&self, state: &State) -> Option<ProofTreeArray> {
+        let mut substituted = Vec::new();
+        for node in &state.nodes {
+            if let Node::Apply(_, ref substitutions, _) = *node {
+                for (_, formula) in substitutions.iter() {
+                    substituted.push(formula.substitute(&state.bindings));
+                }
+            }
+        }
+        let goal = state.formulas[0].substitute(&state.bindings);
+        let mut formulas = substituted.iter().collect::<Vec<_>>();
+        formulas.push(&goal);
+        let mut elimination = state.bindings.clone();
+        if !elimination.eliminate_work_variables(self.db, &self.variables, &formulas, &[]) {
+            return None;
+        }
+        elimination.normalize();
+
+        let mut nodes = state.nodes.clone();
+        for node in &mut nodes {
+            if let Node::Apply(address, ref substitutions, ref children) = *node {
+                let mut final_substitutions = Substitutions::default();
+                for (label, formula) in substitutions.iter() {
+                    final_substitutions.insert(label, formula.substitute(&elimination));
+                }
+                let frame = self
+                    .db
+                    .scope_result()
+                    .get(self.db.statement_by_address(address).label())?;
+                if !self
+                    .db
+                    .check_dv(frame, &final_substitutions, self.frame)
+                    .is_valid()
+                {
+                    return None;
+                }
+                *node = Node::Apply(address, final_substitutions, children.clone());
+            }
+        }
+
+        let mut arr = ProofTreeArray::default();
+        let mut stack_buffer = Vec::new();
+        let qed = self.build(
+            &nodes,
+            &state.formulas,
+            &elimination,
+            0,
+            &mut stack_buffer,
+            &mut arr,
+        )?;
+        arr.qed = qed;
+        arr.calc_indent();
+        Some(arr)
+    }
+
+    /// Adds the proof of a node to a proof tree array, and returns its index.
+    fn build(
+        &self,
+        nodes: &[Node],
+        formulas: &[Formula],
+        elimination: &Substitutions,
+        node: usize,
+        stack_buffer: &mut Vec<u8>,
+        arr: &mut ProofTreeArray,
+    ) -> Option<usize> {
+        let db = self.db;
+        let (address, hyps) = match nodes[node] {
+            Node::Open => return None,
+            Node::Hyp(address) => (address, vec![]),
+            Node::Apply(address, ref substitutions, ref children) => {
+                let sref = db.statement_by_address(address);
+                let frame = db.scope_result().get(sref.label())?;
+                let nset = db.name_result();
+                let mut children = children.iter();
+                let mut hyps = Vec::with_capacity(frame.hypotheses.len());
+                for hyp in &*frame.hypotheses {
+                    hyps.push(match *hyp {
+                        Hyp::Floating(address, ..) => {
+                            let label = db.statement_by_address(address).label();
+                            let substitution = substitutions.get(nset.lookup_label(label)?.atom)?;
+                            substitution
+                                .as_ref(db)
+                                .build_syntax_proof(stack_buffer, arr)?
+                        }
+                        Hyp::Essential(..) => self.build(
+                            nodes,
+                            formulas,
+                            elimination,
+                            *children.next()?,
+                            stack_buffer,
+                            arr,
+                        )?,
+                    });
+                }
+                (address, hyps)
+            }
+        };
+        let formula = formulas[node].substitute(elimination);
+        debug_assert!(
+            !formula.has_work_variables(),
+            "work variables left in {}",
+            as_str(db.statement_by_address(address).label())
+        );
+        let range = formula.as_ref(db).append_to_stack_buffer(stack_buffer);
+        Some(arr.build(address, hyps, stack_buffer, range))
+    }
+}
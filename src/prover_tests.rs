@@ -0,0 +1,195 @@
+use crate::diag::DiagnosticClass;
+use crate::grammar_tests::mkdb;
+use crate::proof::ProofStyle;
+use crate::proof::ProofTreeArray;
+use crate::proof::ProofTreePrinter;
+use crate::prover::ProveError;
+use crate::prover::ProverOptions;
+use crate::worksheet_tests::worksheet_db;
+use crate::Database;
+
+const MP2_DB: &[u8] = b"
+    $c |- wff ( ) -> $.
+    $( $j syntax 'wff'; syntax '|-' as 'wff'; $)
+    $v ph ps ch $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wch $f wff ch $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps ) $.
+        ax-mp $a |- ps $.
+    $}
+    ax-1 $a |- ( ph -> ( ps -> ph ) ) $.
+    ${
+        mp2.1 $e |- ph $.
+        mp2.2 $e |- ( ph -> ps ) $.
+        mp2.3 $e |- ( ps -> ch ) $.
+        mp2 $p |- ch $= ? $.
+    $}
+";
+
+/// Searches a proof of a goal in the context of a theorem
+fn prove(
+    db: &Database,
+    theorem: &str,
+    goal: &str,
+    options: &ProverOptions,
+) -> Result<ProofTreeArray, ProveError> {
+    db.prove(theorem, &db.parse_pattern(goal).unwrap(), options)
+}
+
+/// Renders a proof in the context of a theorem, without the final `$.`
+fn render(db: &Database, theorem: &str, arr: &ProofTreeArray, style: ProofStyle) -> String {
+    let printer = ProofTreePrinter {
+        sset: db.parse_result(),
+        nset: db.name_result(),
+        scope: db.scope_result(),
+        thm_label: db.statement(theorem).unwrap().label(),
+        style,
+        arr,
+        initial_chr: 2,
+        indent: 6,
+        line_width: 79,
+    };
+    printer
+        .to_string()
+        .split_whitespace()
+        .take_while(|&token| token != "$.")
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn test_prove() {
+    let db = worksheet_db();
+    let arr = prove(&db, "a1i", "|- ( ps -> ph )", &ProverOptions::default()).unwrap();
+    // the proof found is the one of the database
+    assert_eq!(
+        render(&db, "a1i", &arr, ProofStyle::Normal),
+        "wph wps wph wi a1i.1 wph wps ax-1 ax-mp"
+    );
+
+    let worksheet = db.import_mmp(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+qed:: |- ( ps -> ph )
+",
+    );
+    let arr = db
+        .prove_worksheet_step(&worksheet, "qed", &ProverOptions::default())
+        .unwrap();
+    assert_eq!(
+        render(&db, "a1i", &arr, ProofStyle::Normal),
+        "wph wps wph wi a1i.1 wph wps ax-1 ax-mp"
+    );
+    assert_eq!(
+        db.prove_worksheet_step(&worksheet, "1", &ProverOptions::default())
+            .unwrap_err(),
+        ProveError::UnknownStep("1".to_owned())
+    );
+}
+
+#[test]
+fn test_prove_verified() {
+    let mut db = mkdb(MP2_DB);
+    db.scope_pass();
+    db.stmt_parse_pass();
+    let arr = prove(&db, "mp2", "|- ch", &ProverOptions::default()).unwrap();
+    let proof = render(&db, "mp2", &arr, ProofStyle::Compressed);
+    assert!(!proof.contains('?'));
+
+    // the proof passes the verifier once stored in the database
+    let text = String::from_utf8(MP2_DB.to_vec())
+        .unwrap()
+        .replace("$= ? $.", &format!("$= {proof} $."));
+    let mut db = mkdb(text.as_bytes());
+    db.verify_pass();
+    assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+}
+
+#[test]
+fn test_prove_bounds() {
+    let db = worksheet_db();
+    let options = ProverOptions {
+        max_depth: 2,
+        ..ProverOptions::default()
+    };
+    assert_eq!(
+        prove(&db, "a1i", "|- ( ph -> ps )", &options).unwrap_err(),
+        ProveError::NotFound
+    );
+    let options = ProverOptions {
+        max_steps: 1,
+        ..ProverOptions::default()
+    };
+    assert_eq!(
+        prove(&db, "a1i", "|- ( ps -> ph )", &options).unwrap_err(),
+        ProveError::BudgetExhausted
+    );
+    let options = ProverOptions {
+        allowed: Some(vec!["ax-mp".to_owned()]),
+        ..ProverOptions::default()
+    };
+    assert_eq!(
+        prove(&db, "a1i", "|- ( ps -> ph )", &options).unwrap_err(),
+        ProveError::NotFound
+    );
+    let options = ProverOptions {
+        allowed: Some(vec!["ax-mp".to_owned(), "ax-1".to_owned()]),
+        ..ProverOptions::default()
+    };
+    assert!(prove(&db, "a1i", "|- ( ps -> ph )", &options).is_ok());
+    let options = ProverOptions {
+        allowed: Some(vec!["a1i".to_owned()]),
+        ..ProverOptions::default()
+    };
+    assert_eq!(
+        prove(&db, "a1i", "|- ( ps -> ph )", &options).unwrap_err(),
+        ProveError::UnknownLabel("a1i".to_owned())
+    );
+    assert_eq!(
+        prove(&db, "ax-1", "|- ( ps -> ph )", &ProverOptions::default()).unwrap_err(),
+        ProveError::UnknownTheorem("ax-1".to_owned())
+    );
+}
+
+#[test]
+fn test_prove_scoped_variables() {
+    // `ta` is not in scope at `th`, and may not replace the work variable
+    // introduced by `ax-d.1`
+    let text = "
+    $c |- wff ( ) -> $.
+    $( $j syntax 'wff'; syntax '|-' as 'wff'; $)
+    $v ph ch ta $.
+    wph $f wff ph $.
+    wch $f wff ch $.
+    wi $a wff ( ph -> ch ) $.
+    id $a |- ( ph -> ph ) $.
+    ${
+        wta $f wff ta $.
+        ax-d.1 $e |- ( ta -> ta ) $.
+        ax-d $a |- ( ph -> ch ) $.
+    $}
+    th $p |- ( ph -> ch ) $= ? $.
+";
+    let mut db = mkdb(text.as_bytes());
+    db.scope_pass();
+    db.stmt_parse_pass();
+    assert_eq!(
+        prove(&db, "th", "|- ( ph -> ch )", &ProverOptions::default()).unwrap_err(),
+        ProveError::NotFound
+    );
+
+    // with another variable in scope, the proof uses it
+    let text = text.replace("$v ph ch ta $.", "$v ph ch ta ps $. wps $f wff ps $.");
+    let mut db = mkdb(text.as_bytes());
+    db.scope_pass();
+    db.stmt_parse_pass();
+    let arr = prove(&db, "th", "|- ( ph -> ch )", &ProverOptions::default()).unwrap();
+    assert_eq!(
+        render(&db, "th", &arr, ProofStyle::Normal),
+        "wph wch wps wps id ax-d"
+    );
+}
@@ -0,0 +1,152 @@
+//! Rewriting the proofs of a database in a chosen proof style.
+//!
+//! `Database::rewrite_proofs` regenerates the proof of each `$p` statement
+//! with `ProofTreePrinter`, in the same way as the `save proof` command of
+//! metamath.exe, and splices it into the source text in place of the old one.
+//! The changes are made in memory through the editing methods, and can then
+//! be written to disk with `Database::write_back`.
+//!
+//! The new proof starts on a line of its own after the `$=`, two columns to
+//! the right of the statement label, and is filled into lines of 79 columns,
+//! which is where the source formatter puts it.  The math string and the
+//! text before it are kept as they are.
+//!
+//! Proofs which do not verify, including incomplete proofs, and proofs
+//! containing comments are left unchanged; the former are listed in the
+//! returned report.
+
+use crate::database::Database;
+use crate::diag::Diagnostic;
+use crate::edit::EditError;
+use crate::parser::Span;
+use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::proof::ProofStyle;
+use crate::proof::ProofTreeArray;
+use crate::proof::ProofTreePrinter;
+
+/// Width of the lines the rewritten proofs are filled into.
+const LINE_WIDTH: u16 = 79;
+
+/// The outcome of rewriting the proofs of a database.
+#[derive(Debug, Default)]
+pub struct RewriteReport {
+    /// Labels of the statements whose proofs were changed.
+    pub rewritten: Vec<String>,
+    /// Labels of the statements whose proofs could not be rewritten, with the
+    /// reason.
+    pub failed: Vec<(String, Diagnostic)>,
+}
+
+impl Database {
+    /// Rewrites the proofs of the given `$p` statements, or of all of them if
+    /// `labels` is `None`, in the given style.
+    ///
+    /// See the [module documentation](crate::rewrite) for the layout of the
+    /// new proofs.  Fails without changing anything if one of the labels
+    /// does not exist or is not a `$p` statement.
+    pub fn rewrite_proofs(
+        &mut self,
+        style: ProofStyle,
+        labels: Option<&[&str]>,
+    ) -> Result<RewriteReport, EditError> {
+        self.name_pass();
+        self.scope_pass();
+        let mut report = RewriteReport::default();
+        let mut edits = Vec::new();
+        {
+            let statements: Vec<StatementRef<'_>> = match labels {
+                Some(labels) => labels
+                    .iter()
+                    .map(|&label| {
+                        let sref = self
+                            .statement(label)
+                            .ok_or_else(|| EditError::UnknownLabel(label.to_owned()))?;
+                        if sref.statement_type() == StatementType::Provable {
+                            Ok(sref)
+                        } else {
+                            Err(EditError::NotProvable(label.to_owned()))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?,
+                None => self
+                    .statements()
+                    .filter(|sref| sref.statement_type() == StatementType::Provable)
+                    .collect(),
+            };
+            for sref in statements {
+                let label = String::from_utf8_lossy(sref.label()).into_owned();
+                match self.rewritten_proof(sref, style) {
+                    Ok(Some((range, text))) => {
+                        edits.push((sref.address(), range, text));
+                        report.rewritten.push(label);
+                    }
+                    Ok(None) => {}
+                    Err(diag) => report.failed.push((label, diag)),
+                }
+            }
+        }
+        self.replace_ranges(edits)?;
+        Ok(report)
+    }
+
+    /// Prints the proof of a statement in the given style, as
+    /// [`Database::replaced_proof`], failing if it does not verify.
+    fn rewritten_proof(
+        &self,
+        sref: StatementRef<'_>,
+        style: ProofStyle,
+    ) -> Result<Option<(Span, Vec<u8>)>, Diagnostic> {
+        let arr = ProofTreeArray::new(self, sref)?;
+        if arr.is_incomplete() {
+            return Err(Diagnostic::ProofIncomplete);
+        }
+        Ok(self.replaced_proof(sref, &arr, style))
+    }
+
+    /// Prints a proof of a statement in the given style, and returns the range
+    /// of the statement text to replace, relative to its full span, with the
+    /// new text, or `None` if the proof is to be left unchanged.
+    pub(crate) fn replaced_proof(
+        &self,
+        sref: StatementRef<'_>,
+        arr: &ProofTreeArray,
+        style: ProofStyle,
+    ) -> Option<(Span, Vec<u8>)> {
+        let buffer = &sref.segment().segment.buffer;
+        let full = sref.span_full();
+        let label_start = sref.span().start as usize;
+        let column = label_start
+            - buffer[..label_start]
+                .iter()
+                .rposition(|&ch| ch == b'\n')
+                .map_or(0, |pos| pos + 1);
+        // the proof is replaced from the end of the math string, which a
+        // verified statement does not leave empty
+        let start = sref.math_span(sref.math_len() - 1).end as usize;
+        let old = &buffer[start..full.end as usize];
+        if old.windows(2).any(|window| window == b"$(") {
+            return None;
+        }
+        let printer = ProofTreePrinter {
+            sset: self.parse_result(),
+            nset: self.name_result(),
+            scope: self.scope_result(),
+            thm_label: sref.label(),
+            style,
+            arr,
+            initial_chr: 0,
+            indent: column as u16 + 2,
+            line_width: LINE_WIDTH,
+        };
+        let new = format!(" $=\n{printer}").into_bytes();
+        if new == old {
+            return None;
+        }
+        let range = Span::new(
+            start - full.start as usize,
+            old.len() + start - full.start as usize,
+        );
+        Some((range, new))
+    }
+}
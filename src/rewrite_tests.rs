@@ -0,0 +1,119 @@
+use crate::database::Database;
+use crate::database_tests::mkdb_edit;
+use crate::database_tests::PROGRESS_DB;
+use crate::diag::DiagnosticClass;
+use crate::edit::EditError;
+use crate::proof::ProofStyle;
+
+#[test]
+fn test_rewrite_proofs() {
+    let text = [
+        PROGRESS_DB,
+        b"    mp3 $p |- ( ph -> ps ) $= wph wps wi $.\n",
+        b"    ${\n        mp4.1 $e |- ( ph -> ps ) $.\n        mp4 $p |- ( ph -> ps ) $= mp4.1 $.\n    $}\n",
+    ]
+    .concat();
+    let mut db = mkdb_edit(&text);
+    let source = |db: &Database| {
+        let (_, text) = &db.source_files()[0];
+        String::from_utf8(text.clone()).unwrap()
+    };
+
+    let report = db.rewrite_proofs(ProofStyle::Compressed, None).unwrap();
+    assert_eq!(report.rewritten, ["mp2", "mp4"]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, "mp3");
+    let compressed = String::from_utf8(text)
+        .unwrap()
+        .replace(
+            "mp2 $p |- ps $= wph wps mp2.1 mp2.2 ax-mp $.",
+            "mp2 $p |- ps $=\n          ( ax-mp ) ABCDE $.",
+        )
+        .replace(
+            "mp4 $p |- ( ph -> ps ) $= mp4.1 $.",
+            "mp4 $p |- ( ph -> ps ) $=\n          ( ) C $.",
+        );
+    assert_eq!(source(&db), compressed);
+    assert_eq!(db.diag_notations(&[DiagnosticClass::Verify]).len(), 1);
+
+    // canonical proofs are left alone
+    let report = db.rewrite_proofs(ProofStyle::Compressed, None).unwrap();
+    assert!(report.rewritten.is_empty());
+    assert_eq!(source(&db), compressed);
+
+    let report = db
+        .rewrite_proofs(ProofStyle::Normal, Some(&["mp2"]))
+        .unwrap();
+    assert_eq!(report.rewritten, ["mp2"]);
+    assert_eq!(
+        source(&db),
+        compressed.replace("( ax-mp ) ABCDE $.", "wph wps mp2.1 mp2.2 ax-mp $.")
+    );
+
+    assert_eq!(
+        db.rewrite_proofs(ProofStyle::Normal, Some(&["wi"]))
+            .unwrap_err(),
+        EditError::NotProvable("wi".to_owned())
+    );
+    assert_eq!(
+        db.rewrite_proofs(ProofStyle::Normal, Some(&["nope"]))
+            .unwrap_err(),
+        EditError::UnknownLabel("nope".to_owned())
+    );
+}
+
+#[test]
+fn test_optimized_compressed() {
+    // with 20 constants, backreferences take two letters, which is not
+    // shorter than writing `-. ph` again, but shorter than `-. -. -. ph`
+    let constants: Vec<String> = (0..20).map(|i| format!("c{i}")).collect();
+    let axioms: Vec<String> = (0..20).map(|i| format!("wc{i} $a wff c{i} $.")).collect();
+    let (constants, axioms) = (constants.join(" "), axioms.join("\n"));
+    let (mut neg, mut neg_proof) = (String::from("-. ph"), String::from("wph wn"));
+    let (mut triple, mut triple_proof) =
+        (String::from("-. -. -. ph"), String::from("wph wn wn wn"));
+    for i in (0..20).rev() {
+        neg = format!("( c{i} -> ( -. ph -> {neg} ) )");
+        neg_proof = format!("wc{i} wph wn {neg_proof} wi wi");
+        if i < 3 {
+            triple = format!("( -. -. -. ph -> {triple} )");
+            triple_proof = format!("wph wn wn wn {triple_proof} wi");
+        }
+    }
+    let text = format!(
+        "$c ( ) -> -. wff {constants} $.\n$v ph ps $.\nwph $f wff ph $.\nwps $f wff ps $.\n\
+         wn $a wff -. ph $.\nwi $a wff ( ph -> ps ) $.\n{axioms}\n\
+         neg $p wff {neg} $= {neg_proof} $.\ntriple $p wff {triple} $= {triple_proof} $.\n"
+    );
+    let proof = |db: &Database, label: &str| {
+        let sref = db.statement(label).unwrap();
+        (0..sref.proof_len())
+            .map(|ix| String::from_utf8_lossy(sref.proof_slice_at(ix)).into_owned())
+            .collect::<Vec<_>>()
+            .concat()
+    };
+
+    let mut db = mkdb_edit(text.as_bytes());
+    db.rewrite_proofs(ProofStyle::Compressed, None).unwrap();
+    db.name_pass();
+    let (neg_compressed, triple_compressed) = (proof(&db, "neg"), proof(&db, "triple"));
+    assert!(neg_compressed.contains('Z'));
+
+    let mut db = mkdb_edit(text.as_bytes());
+    let report = db
+        .rewrite_proofs(ProofStyle::OptimizedCompressed, None)
+        .unwrap();
+    assert_eq!(report.rewritten, ["neg", "triple"]);
+    assert!(db.diag_notations(&[DiagnosticClass::Verify]).is_empty());
+    db.name_pass();
+    let (neg_optimized, triple_optimized) = (proof(&db, "neg"), proof(&db, "triple"));
+    assert!(!neg_optimized.contains('Z'));
+    assert_eq!(neg_optimized.len() + 1, neg_compressed.len());
+    assert!(triple_optimized.contains('Z'));
+    assert!(triple_optimized.len() <= triple_compressed.len());
+
+    let report = db
+        .rewrite_proofs(ProofStyle::OptimizedCompressed, None)
+        .unwrap();
+    assert!(report.rewritten.is_empty());
+}
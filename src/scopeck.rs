@@ -37,7 +37,7 @@ use crate::parser::{
     TokenRef, NO_STATEMENT,
 };
 use crate::segment_set::SegmentSet;
-use crate::util::{fast_extend, HashMap, HashSet};
+use crate::util::{fast_extend, HashMap, HashSet, PersistentMap, PersistentVec};
 use crate::Database;
 use crate::Formula;
 use crate::Label;
@@ -45,6 +45,7 @@ use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::ops::Range;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Information on a `$v` active in the local or global scope.
 #[derive(Clone, Copy)]
@@ -943,8 +944,8 @@ fn scope_check_single(
 pub struct ScopeResult {
     incremental: bool,
     generation: usize,
-    segments: Vec<Option<Arc<SegmentScopeResult>>>,
-    frame_index: HashMap<Token, (usize, usize, usize)>,
+    segments: PersistentVec<Option<Arc<SegmentScopeResult>>>,
+    frame_index: PersistentMap<Token, (usize, usize, usize)>,
 }
 
 impl ScopeResult {
@@ -990,23 +991,43 @@ pub(crate) fn scope_check(result: &mut ScopeResult, segments: &SegmentSet, names
         for (sid, ssr) in result.segments.iter().enumerate() {
             prev.insert(SegmentId(sid as u32), ssr.clone());
         }
-        for sref in segments.segments() {
+        let srefs = segments.segments();
+        let count = srefs.len();
+        for (index, sref) in srefs.into_iter().enumerate() {
             let segments2 = segments.clone();
             let names = names.clone();
             let id = sref.id;
             let osr = prev.get(&id).and_then(Option::clone);
-            ssrq.push_back(segments.exec.exec(sref.bytes(), move || {
-                let sref = segments2.segment(id);
-                if let Some(old_res) = osr {
-                    if old_res.name_usage.valid(&names) && Arc::ptr_eq(&old_res.source, &sref) {
-                        return None;
+            ssrq.push_back(segments.exec.exec_segment(
+                "scopeck",
+                sref.id,
+                sref.bytes(),
+                move || {
+                    let start = Instant::now();
+                    let sref = segments2.segment(id);
+                    if let Some(old_res) = osr {
+                        if old_res.name_usage.valid(&names) && Arc::ptr_eq(&old_res.source, &sref) {
+                            segments2.progress.segment(
+                                "scopeck",
+                                start,
+                                index,
+                                count,
+                                &sref.buffer,
+                                true,
+                            );
+                            return None;
+                        }
                     }
-                }
-                if segments2.options.trace_recalc {
-                    println!("scopeck({:?})", parser::guess_buffer_name(&sref.buffer));
-                }
-                Some(Arc::new(scope_check_single(&segments2, &names, sref)))
-            }));
+                    if segments2.options.trace_recalc {
+                        println!("scopeck({:?})", parser::guess_buffer_name(&sref.buffer));
+                    }
+                    let res = scope_check_single(&segments2, &names, sref);
+                    segments2
+                        .progress
+                        .segment("scopeck", start, index, count, &sref.buffer, false);
+                    Some(Arc::new(res))
+                },
+            ));
         }
     }
 
@@ -1048,8 +1069,8 @@ pub(crate) fn scope_check(result: &mut ScopeResult, segments: &SegmentSet, names
 
     for res_new in to_add {
         let seg_index = res_new.id.0 as usize;
-        if seg_index >= result.segments.len() {
-            result.segments.resize(seg_index + 1, None);
+        while seg_index >= result.segments.len() {
+            result.segments.push_back(None);
         }
 
         let sref = segments.segment(res_new.id);
@@ -0,0 +1,512 @@
+//! Structural search of formulas across the database.
+//!
+//! A search pattern is a math string, parsed with the grammar of the database
+//! into a `Formula`, whose variables are wildcards: a formula matches the
+//! pattern if it is an instance of it, i.e. if it unifies with the pattern
+//! taken as a model, a variable occurring several times in the pattern
+//! standing for the same sub-formula each time.  Work variables like `&W1`
+//! are accepted in patterns, as wildcards too.  This is the equivalent of the
+//! `SEARCH` command of metamath.exe, on formulas instead of symbol strings.
+//!
+//! A `SearchIndex` stores the conclusions and the essential hypotheses of all
+//! the assertions of the database in a discrimination tree: a trie keyed by
+//! the labels of the nodes of the formulas in prefix order, together with
+//! their number of children.  A query walks down the trie following the
+//! pattern, skipping whole sub-formulas at the variables of the pattern, so
+//! that only the formulas sharing the structure of the pattern are unified
+//! with it.
+//!
+//! The same index answers the converse question of which assertions apply
+//! to a goal: `SearchIndex::applicable` returns the assertions whose
+//! conclusion unifies with the goal, and the instances of their hypotheses
+//! which are left to prove.  There, the variables of the assertions match any
+//! sub-formula of the goal, while the variables of the goal are fixed, and
+//! only its work variables may be substituted.
+
+use crate::diag::Diagnostic;
+use crate::formula::Label;
+use crate::formula::Substitutions;
+use crate::formula::WorkVariable;
+use crate::formula::WorkVariables;
+use crate::parser::as_str;
+use crate::parser::Comparer;
+use crate::parser::StatementAddress;
+use crate::parser::StatementType;
+use crate::scopeck::Hyp;
+use crate::util::HashMap;
+use crate::Database;
+use crate::Formula;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// An error in a search pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    /// The pattern is empty
+    EmptyPattern,
+    /// A token of the pattern is neither a math symbol of the database nor a
+    /// work variable
+    UnknownSymbol(String),
+    /// The pattern does not parse from the given symbol on
+    UnexpectedSymbol(String),
+    /// The pattern does not parse
+    Parse(Diagnostic),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SearchError::EmptyPattern => write!(f, "Empty search pattern"),
+            SearchError::UnknownSymbol(ref symbol) => write!(f, "Unknown math symbol {symbol}"),
+            SearchError::UnexpectedSymbol(ref symbol) => {
+                write!(f, "Pattern does not parse at {symbol}")
+            }
+            SearchError::Parse(Diagnostic::ParsedStatementTooShort(ref token)) => write!(
+                f,
+                "Pattern is too short, expecting for example {}",
+                as_str(token)
+            ),
+            SearchError::Parse(Diagnostic::ParsedStatementWrongTypeCode(ref token)) => write!(
+                f,
+                "Type code {} is not among the expected type codes",
+                as_str(token)
+            ),
+            SearchError::Parse(ref diag) => write!(f, "Pattern does not parse: {diag:?}"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Which formulas of the assertions a search matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchTarget {
+    /// Only the conclusions
+    Conclusions,
+    /// Only the essential hypotheses
+    Hypotheses,
+    /// The conclusions and the essential hypotheses, like `/JOIN` in metamath.exe
+    All,
+}
+
+/// A formula of the database matching a search pattern
+#[derive(Debug)]
+pub struct SearchMatch {
+    /// The address of the assertion
+    pub address: StatementAddress,
+    /// The address of the essential hypothesis which matched, `None` if the
+    /// conclusion of the assertion matched
+    pub hypothesis: Option<StatementAddress>,
+    /// The substitutions of the variables of the pattern giving the formula
+    pub substitutions: Box<Substitutions>,
+}
+
+/// An assertion which applies to a goal
+#[derive(Debug)]
+pub struct Application {
+    /// The address of the assertion
+    pub address: StatementAddress,
+    /// The substitutions of the variables of the assertion, by the labels of
+    /// their floating hypotheses.  Variables which do not occur in the
+    /// conclusion are substituted with fresh work variables.
+    pub substitutions: Substitutions,
+    /// The substitutions of the work variables of the goal required for the
+    /// assertion to apply
+    pub goal_substitutions: Substitutions,
+    /// The essential hypotheses of the assertion under the substitutions, in
+    /// order: the new subgoals
+    pub hypotheses: Vec<Formula>,
+}
+
+/// An indexed formula
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    address: StatementAddress,
+    hypothesis: Option<StatementAddress>,
+}
+
+/// A node of the discrimination tree
+#[derive(Debug, Default)]
+struct IndexNode {
+    children: HashMap<(Label, usize, bool), usize>,
+    entries: Vec<Entry>,
+}
+
+/// An index of the conclusions and essential hypotheses of the assertions of
+/// a database, for structural search.
+#[derive(Debug)]
+pub struct SearchIndex {
+    nodes: Vec<IndexNode>,
+}
+
+impl SearchIndex {
+    /// Builds the index of the assertions of the database.
+    /// This requires the statements to have been parsed.
+    #[must_use]
+    pub fn new(db: &Database) -> Self {
+        let mut index = SearchIndex {
+            nodes: vec![IndexNode::default()],
+        };
+        let stmt_parse = db.stmt_parse_result();
+        let sset = db.parse_result();
+        for sref in db.statements() {
+            if !matches!(
+                sref.statement_type(),
+                StatementType::Axiom | StatementType::Provable
+            ) {
+                continue;
+            }
+            if let Some(formula) = stmt_parse.get_formula(&sref) {
+                index.insert(
+                    formula,
+                    Entry {
+                        address: sref.address(),
+                        hypothesis: None,
+                    },
+                );
+                if let Some(frame) = db.scope_result().get(sref.label()) {
+                    for hyp in &*frame.hypotheses {
+                        if let Hyp::Essential(address, _) = *hyp {
+                            if let Some(formula) = stmt_parse.get_formula(&sset.statement(address))
+                            {
+                                index.insert(
+                                    formula,
+                                    Entry {
+                                        address: sref.address(),
+                                        hypothesis: Some(address),
+                                    },
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    /// Adds a formula to the discrimination tree
+    fn insert(&mut self, formula: &Formula, entry: Entry) {
+        let mut node = 0;
+        for key in formula.preorder() {
+            node = if let Some(&next) = self.nodes[node].children.get(&key) {
+                next
+            } else {
+                let next = self.nodes.len();
+                self.nodes.push(IndexNode::default());
+                self.nodes[node].children.insert(key, next);
+                next
+            };
+        }
+        self.nodes[node].entries.push(entry);
+    }
+
+    /// Returns the formulas of the given target which are instances of the
+    /// pattern, in database order, conclusions before hypotheses.
+    #[must_use]
+    pub fn search(
+        &self,
+        db: &Database,
+        pattern: &Formula,
+        target: SearchTarget,
+    ) -> Vec<SearchMatch> {
+        let mut reached = Vec::new();
+        self.walk(0, &pattern.preorder(), &mut reached);
+        let stmt_parse = db.stmt_parse_result();
+        let sset = db.parse_result();
+        let mut matches = Vec::new();
+        for node in reached {
+            for entry in &self.nodes[node].entries {
+                let wanted = match target {
+                    SearchTarget::Conclusions => entry.hypothesis.is_none(),
+                    SearchTarget::Hypotheses => entry.hypothesis.is_some(),
+                    SearchTarget::All => true,
+                };
+                if !wanted {
+                    continue;
+                }
+                let address = entry.hypothesis.unwrap_or(entry.address);
+                if let Some(substitutions) = stmt_parse
+                    .get_formula(&sset.statement(address))
+                    .and_then(|formula| formula.unify(pattern))
+                {
+                    matches.push(SearchMatch {
+                        address: entry.address,
+                        hypothesis: entry.hypothesis,
+                        substitutions,
+                    });
+                }
+            }
+        }
+        let order = &sset.order;
+        matches.sort_by(|m1, m2| {
+            order
+                .cmp(&m1.address, &m2.address)
+                .then_with(|| match (m1.hypothesis, m2.hypothesis) {
+                    (Some(hyp1), Some(hyp2)) => order.cmp(&hyp1, &hyp2),
+                    (hyp1, hyp2) => hyp1.is_some().cmp(&hyp2.is_some()),
+                })
+        });
+        matches
+    }
+
+    /// Returns the assertions whose conclusion unifies with the given goal,
+    /// with the resulting subgoals, ranked by number of subgoals, and then in
+    /// database order.
+    ///
+    /// Only the assertions before the given address are considered if there
+    /// is one, typically the theorem being proved.
+    /// The variables of the goal are kept as they are, only its work
+    /// variables may be substituted.
+    #[must_use]
+    pub fn applicable(
+        &self,
+        db: &Database,
+        goal: &Formula,
+        before: Option<StatementAddress>,
+    ) -> Vec<Application> {
+        self.applicable_with(db, goal, before, &WorkVariables::default())
+    }
+
+    /// Returns the assertions whose conclusion unifies with the given goal,
+    /// like [`Self::applicable`], with fresh work variables allocated after
+    /// the given ones.
+    pub(crate) fn applicable_with(
+        &self,
+        db: &Database,
+        goal: &Formula,
+        before: Option<StatementAddress>,
+        work_variables: &WorkVariables,
+    ) -> Vec<Application> {
+        let mut reached = Vec::new();
+        self.walk_unifiable(0, &goal.preorder(), &mut reached);
+        let mut goal_work_variables = work_variables.clone();
+        goal_work_variables.reserve(goal);
+        let goal_variables = goal.variables();
+        let order = &db.parse_result().order;
+        let mut applications = Vec::new();
+        for node in reached {
+            for entry in &self.nodes[node].entries {
+                if entry.hypothesis.is_some() {
+                    continue;
+                }
+                if let Some(before) = before {
+                    if order.cmp(&entry.address, &before) != Ordering::Less {
+                        continue;
+                    }
+                }
+                if let Some(application) = Self::apply(
+                    db,
+                    entry.address,
+                    goal,
+                    &goal_variables,
+                    goal_work_variables.clone(),
+                ) {
+                    applications.push(application);
+                }
+            }
+        }
+        applications.sort_by(|a1, a2| {
+            a1.hypotheses
+                .len()
+                .cmp(&a2.hypotheses.len())
+                .then_with(|| order.cmp(&a1.address, &a2.address))
+        });
+        applications
+    }
+
+    /// Applies an assertion to a goal, if its conclusion unifies with the goal.
+    fn apply(
+        db: &Database,
+        address: StatementAddress,
+        goal: &Formula,
+        goal_variables: &[Label],
+        mut work_variables: WorkVariables,
+    ) -> Option<Application> {
+        let stmt_parse = db.stmt_parse_result();
+        let sset = db.parse_result();
+        let sref = sset.statement(address);
+        let conclusion = stmt_parse.get_formula(&sref)?;
+        let frame = db.scope_result().get(sref.label())?;
+        let mut hypotheses = Vec::new();
+        for hyp in &*frame.hypotheses {
+            if let Hyp::Essential(address, _) = *hyp {
+                hypotheses.push(stmt_parse.get_formula(&sset.statement(address))?);
+            }
+        }
+
+        // rename the variables of the assertion, which are distinct from the
+        // ones of the goal, then unify
+        let mut renaming = Substitutions::default();
+        conclusion.instantiate(db, &mut renaming, &mut work_variables);
+        for hypothesis in &hypotheses {
+            hypothesis.instantiate(db, &mut renaming, &mut work_variables);
+        }
+        let mut unifier = Substitutions::default();
+        if !unifier.unify(&conclusion.substitute(&renaming), goal) {
+            return None;
+        }
+        let mut goal_substitutions = Substitutions::default();
+        for &label in goal_variables {
+            if let Some(formula) = unifier.get(label) {
+                // the variables of the goal cannot be substituted
+                WorkVariable::from_atom(label)?;
+                goal_substitutions.insert(label, formula.clone());
+            }
+        }
+
+        let mut substitutions = Substitutions::default();
+        for (label, formula) in renaming.iter() {
+            substitutions.insert(label, formula.substitute(&unifier));
+        }
+        let hypotheses = hypotheses
+            .iter()
+            .map(|hypothesis| hypothesis.substitute(&substitutions))
+            .collect();
+        Some(Application {
+            address,
+            substitutions,
+            goal_substitutions,
+            hypotheses,
+        })
+    }
+
+    /// Collects the nodes reached from the given node by the formulas which
+    /// may unify with the given goal: variables of the indexed formulas match
+    /// any sub-formula of the goal, and work variables of the goal match any
+    /// indexed sub-formula.
+    fn walk_unifiable(&self, node: usize, goal: &[(Label, usize, bool)], reached: &mut Vec<usize>) {
+        if let Some((&key, rest)) = goal.split_first() {
+            let (label, _, is_variable) = key;
+            if is_variable && WorkVariable::from_atom(label).is_some() {
+                let mut skipped = Vec::new();
+                self.skip(node, 1, &mut skipped);
+                for next in skipped {
+                    self.walk_unifiable(next, rest, reached);
+                }
+                return;
+            }
+            for (&(_, _, indexed_variable), &next) in &self.nodes[node].children {
+                if indexed_variable {
+                    self.walk_unifiable(next, &goal[sub_formula_len(goal)..], reached);
+                }
+            }
+            if let Some(&next) = self.nodes[node].children.get(&key) {
+                if !is_variable {
+                    self.walk_unifiable(next, rest, reached);
+                }
+            }
+        } else {
+            reached.push(node);
+        }
+    }
+
+    /// Collects the nodes reached from the given node by the nodes of a pattern
+    fn walk(&self, node: usize, pattern: &[(Label, usize, bool)], reached: &mut Vec<usize>) {
+        if let Some((&(label, children, is_variable), rest)) = pattern.split_first() {
+            if is_variable {
+                let mut skipped = Vec::new();
+                self.skip(node, 1, &mut skipped);
+                for next in skipped {
+                    self.walk(next, rest, reached);
+                }
+            } else if let Some(&next) = self.nodes[node].children.get(&(label, children, false)) {
+                self.walk(next, rest, reached);
+            }
+        } else {
+            reached.push(node);
+        }
+    }
+
+    /// Collects the nodes reached from the given node by skipping the given
+    /// number of whole sub-formulas
+    fn skip(&self, node: usize, pending: usize, skipped: &mut Vec<usize>) {
+        if pending == 0 {
+            skipped.push(node);
+            return;
+        }
+        for (&(_, children, _), &next) in &self.nodes[node].children {
+            self.skip(next, pending - 1 + children, skipped);
+        }
+    }
+}
+
+impl Database {
+    /// Parses a search pattern.
+    ///
+    /// The pattern is a math string, optionally starting with a typecode;
+    /// without a typecode, or with the provable typecode, it is parsed as a
+    /// formula of the logic typecode, like the assertions of the database.
+    /// Work variables like `&W1` may be used as well as the variables of the
+    /// database.
+    pub fn parse_pattern(&self, pattern: &str) -> Result<Formula, SearchError> {
+        let nset = self.name_result();
+        let grammar = self.grammar_result();
+        let mut symbols = Vec::new();
+        for token in pattern.split_whitespace() {
+            let symbol = nset
+                .lookup_symbol(token.as_bytes())
+                .map(|symbol| symbol.atom)
+                .or_else(|| WorkVariable::from_name(self, token).map(WorkVariable::atom))
+                .ok_or_else(|| SearchError::UnknownSymbol(token.to_owned()))?;
+            symbols.push(symbol);
+        }
+        let first = *symbols.first().ok_or(SearchError::EmptyPattern)?;
+        let typecode = first == grammar.provable_typecode() || grammar.typecodes().contains(&first);
+        let result = if typecode {
+            grammar.parse_math_string(first, &mut symbols[1..].iter().copied(), nset)
+        } else {
+            grammar.parse_formula(
+                &mut symbols.iter().copied(),
+                &[grammar.logic_typecode()],
+                nset,
+            )
+        };
+        result.map_err(|diag| match diag {
+            Diagnostic::UnparseableStatement(index) => pattern
+                .split_whitespace()
+                .nth(index as usize + usize::from(typecode))
+                .map_or(SearchError::Parse(diag), |token| {
+                    SearchError::UnexpectedSymbol(token.to_owned())
+                }),
+            _ => SearchError::Parse(diag),
+        })
+    }
+
+    /// Returns the assertions whose conclusion unifies with the given goal,
+    /// see [`SearchIndex::applicable`].
+    /// This builds a [`SearchIndex`]; keep one for repeated queries.
+    #[must_use]
+    pub fn applicable_assertions(
+        &self,
+        goal: &Formula,
+        before: Option<StatementAddress>,
+    ) -> Vec<Application> {
+        SearchIndex::new(self).applicable(self, goal, before)
+    }
+
+    /// Searches the assertions of the database for formulas matching the
+    /// given pattern, see [`Database::parse_pattern`] and [`SearchIndex::search`].
+    /// This builds a [`SearchIndex`]; keep one for repeated searches.
+    pub fn search(
+        &self,
+        pattern: &str,
+        target: SearchTarget,
+    ) -> Result<Vec<SearchMatch>, SearchError> {
+        let pattern = self.parse_pattern(pattern)?;
+        Ok(SearchIndex::new(self).search(self, &pattern, target))
+    }
+}
+
+/// Returns the number of nodes of the first sub-formula of the given nodes in
+/// prefix order
+fn sub_formula_len(nodes: &[(Label, usize, bool)]) -> usize {
+    let mut pending = 1;
+    let mut len = 0;
+    while pending > 0 {
+        pending += nodes[len].1;
+        pending -= 1;
+        len += 1;
+    }
+    len
+}
@@ -0,0 +1,157 @@
+use crate::formula::WorkVariable;
+use crate::search::SearchError;
+use crate::search::SearchIndex;
+use crate::search::SearchTarget;
+use crate::worksheet_tests::worksheet_db;
+use crate::Database;
+use std::fmt::Write;
+
+/// Returns the labels of the statements matching a pattern
+fn search(db: &Database, pattern: &str, target: SearchTarget) -> Vec<String> {
+    let sset = db.parse_result();
+    db.search(pattern, target)
+        .unwrap()
+        .iter()
+        .map(|found| {
+            let label = |address| String::from_utf8_lossy(sset.statement(address).label());
+            match found.hypothesis {
+                Some(hyp) => format!("{} {}", label(found.address), label(hyp)),
+                None => label(found.address).into_owned(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_search() {
+    let db = worksheet_db();
+    assert_eq!(
+        search(&db, "( ph -> ps )", SearchTarget::Conclusions),
+        ["ax-1", "a1i"]
+    );
+    assert_eq!(
+        search(&db, "|- ( ph -> ( ps -> ph ) )", SearchTarget::Conclusions),
+        ["ax-1"]
+    );
+    assert_eq!(
+        search(&db, "( &W1 -> ( &W2 -> &W1 ) )", SearchTarget::All),
+        ["ax-1"]
+    );
+    assert!(search(&db, "( ph -> ph )", SearchTarget::All).is_empty());
+    assert_eq!(
+        search(&db, "ch", SearchTarget::Conclusions),
+        ["ax-mp", "ax-1", "a1i"]
+    );
+    assert_eq!(
+        search(&db, "( ph -> ps )", SearchTarget::Hypotheses),
+        ["ax-mp maj"]
+    );
+    assert_eq!(
+        search(&db, "( ph -> ps )", SearchTarget::All),
+        ["ax-mp maj", "ax-1", "a1i"]
+    );
+    assert_eq!(
+        search(&db, "ph", SearchTarget::Hypotheses),
+        ["ax-mp min", "ax-mp maj", "a1i a1i.1"]
+    );
+}
+
+#[test]
+fn test_search_substitutions() {
+    let db = worksheet_db();
+    let pattern = db.parse_pattern("wff ( ph -> ps )").unwrap();
+    let index = SearchIndex::new(&db);
+    let found = index.search(&db, &pattern, SearchTarget::Conclusions);
+    assert_eq!(found.len(), 2);
+    let nset = db.name_result();
+    let ph = nset.lookup_label(b"wph").unwrap().atom;
+    let ps = nset.lookup_label(b"wps").unwrap().atom;
+    assert_eq!(
+        found[0].substitutions[ps].as_ref(&db).to_string(),
+        "wff ( ps -> ph )"
+    );
+    assert_eq!(found[1].substitutions[ph].as_ref(&db).to_string(), "wff ps");
+}
+
+#[test]
+fn test_search_errors() {
+    let db = worksheet_db();
+    assert_eq!(
+        db.parse_pattern("( ph -> foo )").unwrap_err(),
+        SearchError::UnknownSymbol("foo".to_owned())
+    );
+    assert_eq!(
+        db.parse_pattern("  ").unwrap_err(),
+        SearchError::EmptyPattern
+    );
+    let error = |pattern| db.parse_pattern(pattern).unwrap_err().to_string();
+    assert_eq!(
+        error("( ph ->"),
+        "Pattern is too short, expecting for example wff"
+    );
+    assert_eq!(
+        db.parse_pattern("ph ps").unwrap_err(),
+        SearchError::UnexpectedSymbol("ps".to_owned())
+    );
+    assert_eq!(error("wff ph ps"), "Pattern does not parse at ps");
+}
+
+/// Returns the labels of the assertions applying to a goal, each with its
+/// subgoals, and the substitutions of the work variables of the goal
+fn applicable(db: &Database, goal: &str, before: Option<&str>) -> Vec<String> {
+    let goal = db.parse_pattern(goal).unwrap();
+    let before = before.map(|label| db.statement(label).unwrap().address());
+    db.applicable_assertions(&goal, before)
+        .iter()
+        .map(|application| {
+            let mut line =
+                String::from_utf8_lossy(db.statement_by_address(application.address).label())
+                    .into_owned();
+            for hypothesis in &application.hypotheses {
+                write!(line, " [{}]", hypothesis.as_ref(db)).unwrap();
+            }
+            for (label, formula) in application.goal_substitutions.iter() {
+                write!(
+                    line,
+                    " {}:={}",
+                    WorkVariable::from_atom(label).unwrap().name(db),
+                    formula.as_ref(db)
+                )
+                .unwrap();
+            }
+            line
+        })
+        .collect()
+}
+
+#[test]
+fn test_applicable() {
+    let db = worksheet_db();
+    assert_eq!(
+        applicable(&db, "|- ( ps -> ph )", None),
+        [
+            "a1i [wff ph]",
+            "ax-mp [wff &W2] [wff ( &W2 -> ( ps -> ph ) )]"
+        ]
+    );
+    assert_eq!(
+        applicable(&db, "|- ( ps -> ph )", Some("a1i")),
+        ["ax-mp [wff &W2] [wff ( &W2 -> ( ps -> ph ) )]"]
+    );
+    assert_eq!(
+        applicable(&db, "|- ( ph -> ( ps -> ph ) )", None),
+        [
+            "ax-1",
+            "a1i [wff ( ps -> ph )]",
+            "ax-mp [wff &W2] [wff ( &W2 -> ( ph -> ( ps -> ph ) ) )]"
+        ]
+    );
+    assert_eq!(
+        applicable(&db, "|- ( ph -> &W1 )", None),
+        [
+            "ax-1 &W1:=wff ( &W3 -> ph )",
+            "a1i [wff &W1]",
+            "ax-mp [wff &W3] [wff ( &W3 -> ( ph -> &W1 ) )]"
+        ]
+    );
+}
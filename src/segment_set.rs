@@ -48,13 +48,15 @@
 //! would make changing the beginning and end at the same time faster, and is
 //! attractive future work.
 
-use crate::database::{DbOptions, Executor, Promise};
+use crate::database::{estimate_bytes, DbOptions, Executor, Promise};
 use crate::diag::Diagnostic;
+use crate::edit::EditError;
 use crate::parser::{
-    self, Command, Comparer, Segment, SegmentId, SegmentOrder, SegmentRef, Span, StatementAddress,
-    StatementRef,
+    self, Command, Comparer, FilePos, Segment, SegmentId, SegmentOrder, SegmentRef, Span,
+    StatementAddress, StatementRef,
 };
-use crate::util::{find_chapter_header, HashMap, HashSet};
+use crate::progress::Progress;
+use crate::util::{find_chapter_header, HashMap, HashSet, PersistentMap};
 use filetime::FileTime;
 use std::collections::VecDeque;
 use std::fs::{self, File};
@@ -63,6 +65,7 @@ use std::io::{self, Read};
 use std::mem;
 use std::str;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Memory buffer wrapper which hashes by length.
 ///
@@ -80,7 +83,7 @@ impl Hash for LongBuf {
 
 impl PartialEq for LongBuf {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        self.0 == other.0
     }
 }
 
@@ -134,16 +137,20 @@ pub(crate) struct SegmentSet {
     pub(crate) options: Arc<DbOptions>,
     /// The work queue for use with this database.
     pub(crate) exec: Executor,
+    /// Progress observer for analysis passes run on this database.
+    pub(crate) progress: Progress,
     /// Order structure which records the relative order of segment IDs created
     /// by the SegmentSet.
     pub(crate) order: Arc<SegmentOrder>,
     /// Track segment and source info in parallel so they can be updated
     /// independently in the slicing case and if a file is renamed.
-    segments: HashMap<SegmentId, (Arc<Segment>, Arc<SourceInfo>)>,
+    segments: PersistentMap<SegmentId, (Arc<Segment>, Arc<SourceInfo>)>,
     /// First cache as described in the module comment.
-    file_cache: HashMap<(String, FileTime), FileSR>,
+    file_cache: PersistentMap<(String, FileTime), FileSR>,
     /// Second cache as described in the module comment.
-    parse_cache: HashMap<LongBuf, Vec<Arc<Segment>>>,
+    parse_cache: PersistentMap<LongBuf, Vec<Arc<Segment>>>,
+    /// Texts given in place of files to the last `read`, by file name.
+    preload: Arc<HashMap<String, Vec<u8>>>,
 }
 
 impl SegmentSet {
@@ -151,21 +158,24 @@ impl SegmentSet {
     /// executor which were previously created by the `Database`.
     pub(crate) fn new(opts: Arc<DbOptions>, exec: &Executor) -> Self {
         SegmentSet {
+            progress: Progress::new(opts.timing),
             options: opts,
             exec: exec.clone(),
             order: Arc::new(SegmentOrder::new()),
-            segments: HashMap::default(),
-            parse_cache: HashMap::default(),
-            file_cache: HashMap::default(),
+            segments: PersistentMap::default(),
+            parse_cache: PersistentMap::default(),
+            file_cache: PersistentMap::default(),
+            preload: Arc::default(),
         }
     }
 
     /// Reset the segment set to the empty state.
     pub(crate) fn clear(&mut self) {
         *Arc::make_mut(&mut self.order) = SegmentOrder::new();
-        self.segments = HashMap::default();
-        self.parse_cache = HashMap::default();
-        self.file_cache = HashMap::default();
+        self.segments = PersistentMap::default();
+        self.parse_cache = PersistentMap::default();
+        self.file_cache = PersistentMap::default();
+        self.preload = Arc::default();
     }
 
     /// Iterates over all loaded segments in logical order.
@@ -211,6 +221,150 @@ impl SegmentSet {
         self.segment(addr.segment_id).statement(addr.index)
     }
 
+    /// Replaces a range of the buffer of a loaded segment with new text and
+    /// reparses the segment, keeping its ID.
+    ///
+    /// `range` is relative to the segment's buffer and must lie within the
+    /// segment.  The rest of the buffer is reparsed along with it, which only
+    /// makes a difference when the buffer contains file inclusions: the
+    /// segments which follow them keep their IDs too, but are new segments as
+    /// far as later passes are concerned.  The source information of every
+    /// segment loaded from the same version of the same file is updated to
+    /// refer to the edited file text, so that diagnostics keep pointing at the
+    /// right place.
+    pub(crate) fn replace_text(
+        &mut self,
+        id: SegmentId,
+        range: Span,
+        text: &[u8],
+    ) -> Result<(), EditError> {
+        let (segment, source) = self.segments[&id].clone();
+        let seg_start = SegmentRef {
+            segment: &segment,
+            id,
+        }
+        .span()
+        .start as usize;
+        assert!(seg_start <= range.start as usize && range.end <= segment.buffer.len() as FilePos);
+
+        // the old segments covering the same part of the buffer, in order
+        let mut old_ids: Vec<(usize, SegmentId)> = self
+            .segments
+            .iter()
+            .filter(|(_, (other, _))| Arc::ptr_eq(&other.buffer, &segment.buffer))
+            .map(|(&other_id, (other, _))| {
+                let sref = SegmentRef {
+                    segment: other,
+                    id: other_id,
+                };
+                (sref.span().start as usize, other_id)
+            })
+            .filter(|&(start, _)| start >= seg_start)
+            .collect();
+        old_ids.sort_unstable_by_key(|&(start, _)| start);
+
+        let mut buf_text = Vec::with_capacity(segment.buffer.len() - seg_start + text.len());
+        buf_text.extend_from_slice(&segment.buffer[seg_start..range.start as usize]);
+        buf_text.extend_from_slice(text);
+        buf_text.extend_from_slice(&segment.buffer[range.end as usize..]);
+        let buf_len = buf_text.len();
+        let parsed = parser::parse_segments(&Arc::new(buf_text));
+
+        // the edit must not add or remove file inclusions, which would change
+        // the segment structure
+        let included = |seg: &Arc<Segment>| seg.next_file.as_ref(&seg.buffer).to_vec();
+        if parsed.len() != old_ids.len()
+            || parsed
+                .iter()
+                .zip(&old_ids)
+                .any(|(new, (_, old_id))| included(new) != included(&self.segments[old_id].0))
+        {
+            return Err(EditError::ChangesInclusions);
+        }
+
+        // splice the edit into the file text as well
+        let edit_start = source.span.start as usize + range.start as usize;
+        let edit_end = source.span.start as usize + range.end as usize;
+        let mut file_text = Vec::with_capacity(source.text.len() + text.len());
+        file_text.extend_from_slice(&source.text[..edit_start]);
+        file_text.extend_from_slice(text);
+        file_text.extend_from_slice(&source.text[edit_end..]);
+        let file_text = Arc::new(file_text);
+        let shift = |pos: FilePos| {
+            if pos as usize >= edit_end {
+                pos as usize + edit_start + text.len() - edit_end
+            } else {
+                pos as usize
+            }
+        };
+
+        // segments from other buffers are before or after the edited part of
+        // the file as a whole, and segments earlier in the same buffer only
+        // see its end move
+        let mut updates = Vec::new();
+        for (&other_id, (other, other_source)) in &self.segments {
+            if old_ids.iter().any(|&(_, old_id)| old_id == other_id)
+                || !Arc::ptr_eq(&other_source.text, &source.text)
+            {
+                continue;
+            }
+            let source_info = SourceInfo {
+                name: other_source.name.clone(),
+                text: file_text.clone(),
+                span: Span::new(shift(other_source.span.start), shift(other_source.span.end)),
+            };
+            updates.push((other_id, (other.clone(), Arc::new(source_info))));
+        }
+        let buf_start = source.span.start as usize + seg_start;
+        let source_info = Arc::new(SourceInfo {
+            name: source.name.clone(),
+            text: file_text,
+            span: Span::new(buf_start, buf_start + buf_len),
+        });
+        for (new, (_, old_id)) in parsed.into_iter().zip(old_ids) {
+            updates.push((old_id, (new, source_info.clone())));
+        }
+        for (update_id, update) in updates {
+            self.segments.insert(update_id, update);
+        }
+        Ok(())
+    }
+
+    /// Regenerates the text of each loaded source file from its segments, in
+    /// order of first appearance.
+    ///
+    /// Segments are concatenated in database order, which puts the segments
+    /// of each file in file order.  Files which could not be read are left
+    /// out.
+    pub(crate) fn source_texts(&self) -> Vec<(String, Vec<u8>)> {
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::default();
+        for sref in self.segments() {
+            if sref
+                .diagnostics
+                .iter()
+                .any(|(_, diag)| matches!(diag, Diagnostic::IoError(_)))
+            {
+                continue;
+            }
+            let name = &self.source_info(sref.id).name;
+            let file = *index.entry(name.clone()).or_insert_with(|| {
+                files.push((name.clone(), Vec::new()));
+                files.len() - 1
+            });
+            files[file]
+                .1
+                .extend_from_slice(sref.span().as_ref(&sref.buffer));
+        }
+        files
+    }
+
+    /// Returns the text given in place of the file with the given name to the
+    /// last `read`, as it was given, or `None` if the file was read from disk.
+    pub(crate) fn preloaded_text(&self, name: &str) -> Option<&[u8]> {
+        self.preload.get(name).map(Vec::as_slice)
+    }
+
     /// Reports any parse errors associated with loaded segments.
     pub(crate) fn parse_diagnostics(&self) -> Vec<(StatementAddress, Diagnostic)> {
         let mut out = Vec::new();
@@ -245,18 +399,19 @@ impl SegmentSet {
         struct RecState {
             options: Arc<DbOptions>,
             /// second cache from the last load
-            old_by_content: HashMap<LongBuf, Vec<Arc<Segment>>>,
+            old_by_content: PersistentMap<LongBuf, Vec<Arc<Segment>>>,
             /// second cache which will be saved after this load is done
-            new_by_content: HashMap<LongBuf, Vec<Arc<Segment>>>,
+            new_by_content: PersistentMap<LongBuf, Vec<Arc<Segment>>>,
             /// first cache from the last load
-            old_by_time: HashMap<(String, FileTime), FileSR>,
+            old_by_time: PersistentMap<(String, FileTime), FileSR>,
             /// first cache which will be saved after this load is done
-            new_by_time: HashMap<(String, FileTime), FileSR>,
+            new_by_time: PersistentMap<(String, FileTime), FileSR>,
             /// segments which have been placed in the order so far
             segments: SegList,
             included: HashSet<String>,
-            preload: HashMap<String, Vec<u8>>,
+            preload: Arc<HashMap<String, Vec<u8>>>,
             exec: Executor,
+            progress: Progress,
         }
 
         /// one or more segments with provenance, the output of the cache
@@ -291,7 +446,9 @@ impl SegmentSet {
             }
 
             let mut promises = Vec::new();
-            for range in parts {
+            let count = parts.len();
+            for (index, range) in parts.into_iter().enumerate() {
+                let start = Instant::now();
                 let partbuf = if range == (0..buf.len()) {
                     buf.clone()
                 } else {
@@ -310,16 +467,23 @@ impl SegmentSet {
                 if let Some(eseg) = state.old_by_content.get(&cachekey) {
                     // hit, don't queue a job.  keep the name so that it
                     // gets reinserted into the new second cache.
+                    state
+                        .progress
+                        .segment("parse", start, index, count, &partbuf, true);
                     let sres = SliceSR(Some(cachekey), eseg.clone(), srcinfo);
                     promises.push(Promise::new(sres));
                 } else {
                     let trace = state.options.trace_recalc;
+                    let progress = state.progress.clone();
                     // parse it on a worker thread
-                    promises.push(state.exec.exec(partbuf.len(), move || {
+                    promises.push(state.exec.exec(estimate_bytes(partbuf.len()), move || {
+                        let start = Instant::now();
                         if trace {
                             println!("parse({:?})", parser::guess_buffer_name(&partbuf));
                         }
-                        SliceSR(Some(cachekey), parser::parse_segments(&partbuf), srcinfo)
+                        let segments = parser::parse_segments(&partbuf);
+                        progress.segment("parse", start, index, count, &partbuf, false);
+                        SliceSR(Some(cachekey), segments, srcinfo)
                     }));
                 }
             }
@@ -341,6 +505,14 @@ impl SegmentSet {
 
             // probe 1st cache
             if let Some(old_fsr) = state.old_by_time.get(&(path.clone(), time)) {
+                let count = old_fsr.1.len();
+                for (index, slice) in old_fsr.1.iter().enumerate() {
+                    let start = Instant::now();
+                    let text = slice.2.span.as_ref(&slice.2.text);
+                    state
+                        .progress
+                        .segment("parse", start, index, count, text, true);
+                }
                 Ok(Promise::new(old_fsr.clone()))
             } else {
                 // miss, but we have the file size, so try to read in one
@@ -441,16 +613,18 @@ impl SegmentSet {
         // Note that we clear out the caches immediately, and only copy forward
         // things that are actually used, to avoid memory bloat
 
+        self.preload = Arc::new(data.into_iter().collect());
         let mut state = RecState {
             options: self.options.clone(),
             old_by_content: mem::take(&mut self.parse_cache),
-            new_by_content: HashMap::default(),
+            new_by_content: PersistentMap::default(),
             old_by_time: mem::take(&mut self.file_cache),
-            new_by_time: HashMap::default(),
+            new_by_time: PersistentMap::default(),
             segments: Vec::new(),
             included: HashSet::default(),
-            preload: data.into_iter().collect(),
+            preload: self.preload.clone(),
             exec: self.exec.clone(),
+            progress: self.progress.clone(),
         };
 
         // parse and recursively incorporate the initial file
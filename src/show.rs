@@ -0,0 +1,147 @@
+//! Human-readable displays of proofs.
+//!
+//! `Database::show_proof` writes the proof of a theorem in one of several
+//! layouts, in the manner of the `show proof` command of metamath.exe:
+//!
+//! * A Lemmon-style table, with a line for each step giving its number, the
+//!   numbers of the steps used for its hypotheses, the label of the statement
+//!   it applies, and the expression it proves.
+//! * A tree, with a line for each step indented by its distance from the
+//!   final step.
+//! * An mmj2 proof worksheet, as written by `Database::export_mmp`.
+//! * The proof text as it appears in a database, in any `ProofStyle`.
+//!
+//! Steps which are used more than once appear once in the table and the tree.
+//! Both can leave out the syntax steps, whose typecode is not the provable
+//! typecode; the remaining steps are numbered consecutively.  The unknown
+//! steps of an incomplete proof are shown with the label `?`, and the steps
+//! whose expression depends on them are shown without an expression.
+
+use crate::export::ExportError;
+use crate::parser::as_str;
+use crate::parser::StatementRef;
+use crate::proof::ProofStyle;
+use crate::proof::ProofTreeArray;
+use crate::proof::ProofTreePrinter;
+use crate::Database;
+use std::io::Write;
+
+/// A layout for displaying a proof.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProofLayout {
+    /// A table with a line for each step: its number, the steps used for its
+    /// hypotheses, the label it applies and its expression.
+    Lemmon,
+    /// A line for each step, indented by its depth in the proof tree.
+    Tree,
+    /// An mmj2 proof worksheet.
+    Mmp,
+    /// The proof text in the given style.
+    Style(ProofStyle),
+}
+
+impl Database {
+    /// Writes the proof of a `$p` statement in the given layout.  If
+    /// `hide_syntax` is set, the `Lemmon` and `Tree` layouts leave out the
+    /// syntax steps; the other layouts always show the complete proof.
+    ///
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`]
+    pub fn show_proof<W: Write>(
+        &self,
+        stmt: StatementRef<'_>,
+        layout: ProofLayout,
+        hide_syntax: bool,
+        out: &mut W,
+    ) -> Result<(), ExportError> {
+        match layout {
+            ProofLayout::Lemmon => {
+                let arr = ProofTreeArray::new(self, stmt)?;
+                self.show_steps(&arr, hide_syntax, false, out)
+            }
+            ProofLayout::Tree => {
+                let arr = ProofTreeArray::new(self, stmt)?;
+                self.show_steps(&arr, hide_syntax, true, out)
+            }
+            ProofLayout::Mmp => self.export_mmp(stmt, out),
+            ProofLayout::Style(style) => {
+                let arr = ProofTreeArray::new(self, stmt)?;
+                let printer = ProofTreePrinter {
+                    sset: self.parse_result(),
+                    nset: self.name_result(),
+                    scope: self.scope_result(),
+                    thm_label: stmt.label(),
+                    style,
+                    arr: &arr,
+                    initial_chr: 0,
+                    indent: 2,
+                    line_width: 79,
+                };
+                writeln!(out, "{printer}")?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the steps of a proof as a table, or indented as a tree.
+    fn show_steps<W: Write>(
+        &self,
+        arr: &ProofTreeArray,
+        hide_syntax: bool,
+        tree: bool,
+        out: &mut W,
+    ) -> Result<(), ExportError> {
+        let sset = self.parse_result();
+        let provable_tc = self.provable_typecode().filter(|_| hide_syntax);
+
+        // the number of each shown step, or zero for hidden steps
+        let mut numbers = vec![0; arr.trees.len()];
+        let unknown = arr.unknown_exprs();
+        let mut rows = Vec::new();
+        for (ix, step) in arr.trees.iter().enumerate() {
+            // the unknown steps of an incomplete proof are always shown, and
+            // written with a `?` label and no expression
+            let (label, expr) = if step.is_unknown() {
+                ("?", String::new())
+            } else {
+                let stmt = sset.statement(step.address);
+                let typecode = stmt.math_at(0);
+                if provable_tc.is_some() && provable_tc != Some(typecode.slice) {
+                    continue;
+                }
+                let expr = if unknown[ix] {
+                    String::new()
+                } else {
+                    String::from_utf8_lossy(&[typecode.slice, &arr.exprs[ix]].concat()).into_owned()
+                };
+                (as_str(stmt.label()), expr)
+            };
+            numbers[ix] = rows.len() + 1;
+            let hyps: Vec<String> = step
+                .children
+                .iter()
+                .filter(|&&hyp| numbers[hyp] != 0)
+                .map(|&hyp| numbers[hyp].to_string())
+                .collect();
+            rows.push((ix, hyps.join(","), label, expr));
+        }
+
+        let number_width = rows.len().to_string().len();
+        let hyps_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        let label_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+        let indent = arr.indent();
+        for (row, (ix, hyps, label, expr)) in rows.iter().enumerate() {
+            let number = row + 1;
+            if tree {
+                let depth = 2 * indent[*ix] as usize;
+                let line = format!("{number:>number_width$} {:depth$}{label} {expr}", "");
+                writeln!(out, "{}", line.trim_end())?;
+            } else {
+                let line = format!(
+                    "{number:>number_width$} {hyps:hyps_width$} {label:label_width$} {expr}"
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use crate::database_tests::mkdb_edit;
+use crate::database_tests::PROGRESS_DB;
+use crate::proof::ProofStyle;
+use crate::show::ProofLayout;
+
+#[test]
+fn test_show_proof() {
+    let mut db = mkdb_edit(PROGRESS_DB);
+    db.scope_pass();
+    let show = |layout: ProofLayout, hide_syntax: bool| {
+        let mut out = Vec::new();
+        db.show_proof(db.statement("mp2").unwrap(), layout, hide_syntax, &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(
+        show(ProofLayout::Lemmon, false),
+        "1         wph   wff ph
+2         wps   wff ps
+3         mp2.1 |- ph
+4         mp2.2 |- ( ph -> ps )
+5 1,2,3,4 ax-mp |- ps
+"
+    );
+    assert_eq!(
+        show(ProofLayout::Lemmon, true),
+        "1     mp2.1 |- ph
+2     mp2.2 |- ( ph -> ps )
+3 1,2 ax-mp |- ps
+"
+    );
+    assert_eq!(
+        show(ProofLayout::Tree, true),
+        "1   mp2.1 |- ph
+2   mp2.2 |- ( ph -> ps )
+3 ax-mp |- ps
+"
+    );
+    assert_eq!(
+        show(ProofLayout::Style(ProofStyle::Compressed), false),
+        "  ( ax-mp ) ABCDE $.\n"
+    );
+}
@@ -0,0 +1,80 @@
+//! Read-only views of a database which can be shared between threads.
+//!
+//! The query methods of `Database` compute analysis passes on demand, which
+//! requires `&mut self`, so a `Database` cannot serve several readers at once.
+//! A `Snapshot` is obtained with `Database::snapshot` after the passes of
+//! interest have been run; it holds references to the results of those passes
+//! and nothing else, so taking one is cheap, and it is `Send + Sync`.
+//!
+//! Because pass results are never modified once they are shared (see the
+//! discussion of cloning in `database`), a snapshot is not affected by later
+//! calls to `Database::parse` or to the pass methods of the database it was
+//! taken from.  It keeps describing the database as it was, which makes it
+//! possible to answer queries about the current version while the next one is
+//! being parsed and checked on another thread.
+//!
+//! A snapshot dereferences to a `Database`, so all the methods which take
+//! `&self` are available, such as `Database::statement`,
+//! `Database::get_frame`, `Database::stmt_parse_result` and
+//! `Database::outline_result`.  As for a `Database`, they panic if the pass
+//! they need had not been run when the snapshot was taken.
+
+use crate::database::Database;
+use crate::diag;
+use crate::diag::DiagnosticClass;
+use crate::diag::Notation;
+use std::ops::Deref;
+
+/// An immutable view of the analysis results of a `Database`.
+#[derive(Debug)]
+pub struct Snapshot {
+    db: Database,
+}
+
+impl Snapshot {
+    /// Wraps a database whose passes will not be run any further.
+    pub(crate) const fn new(db: Database) -> Self {
+        Snapshot { db }
+    }
+
+    /// Collects and returns the errors generated by the given passes.
+    ///
+    /// This is the same as `Database::diag_notations`, except that the passes
+    /// are not run on demand: all the passes named in `types` must have been
+    /// run before the snapshot was taken.  Requires: [`Database::scope_pass`]
+    /// for `Scope`, [`Database::verify_pass`] for `Verify`,
+    /// [`Database::grammar_pass`] for `Grammar`,
+    /// [`Database::stmt_parse_pass`] for `StmtParse` and
+    /// [`Database::hygiene_pass`] for `Hygiene`.
+    #[must_use]
+    pub fn diag_notations(&self, types: &[DiagnosticClass]) -> Vec<Notation> {
+        let mut diags = Vec::new();
+        if types.contains(&DiagnosticClass::Parse) {
+            diags.extend(self.db.parse_result().parse_diagnostics());
+        }
+        if types.contains(&DiagnosticClass::Scope) {
+            diags.extend(self.db.scope_result().diagnostics());
+        }
+        if types.contains(&DiagnosticClass::Verify) {
+            diags.extend(self.db.verify_result().diagnostics());
+        }
+        if types.contains(&DiagnosticClass::Grammar) {
+            diags.extend(self.db.grammar_result().diagnostics());
+        }
+        if types.contains(&DiagnosticClass::StmtParse) {
+            diags.extend(self.db.stmt_parse_result().diagnostics());
+        }
+        if types.contains(&DiagnosticClass::Hygiene) {
+            diags.extend(self.db.hygiene_result().diagnostics());
+        }
+        diag::to_annotations(self.db.parse_result(), diags)
+    }
+}
+
+impl Deref for Snapshot {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.db
+    }
+}
@@ -0,0 +1,559 @@
+//! Step unification for proof worksheets.
+//!
+//! `Database::unify_worksheet` completes the steps of a proof worksheet read
+//! by `Database::import_mmp`, in the manner of the unification of mmj2:
+//!
+//! * A hypothesis step without a label gets the label of the hypothesis of the
+//!   theorem with the same formula, and one without a formula gets the
+//!   formula of the hypothesis named by its label.
+//! * A derivation step with a label but no formula gets the formula of the
+//!   assertion of its label, under the substitution obtained by unifying the
+//!   steps it refers to with the essential hypotheses of the label.
+//! * A missing hypothesis `?` of a step with a label is filled with the only
+//!   earlier step which unifies with the corresponding essential hypothesis.
+//!   A step with a label and no hypotheses at all has all of them missing.
+//! * A derivation step with a formula but no label gets the label of the only
+//!   assertion before the theorem which justifies it.
+//!
+//! These are repeated until no step changes.  If all the steps are then
+//! complete and correct, including their `$d` conditions,
+//! `Database::worksheet_proof` builds the proof of the theorem, generating the
+//! syntax proofs of the substitutions with `FormulaRef::build_syntax_proof`,
+//! and the compressed proof is stored in the worksheet.
+
+use crate::formula::scoped_variables;
+use crate::formula::symbol_name;
+use crate::formula::Substitutions;
+use crate::parser::as_str;
+use crate::parser::Comparer;
+use crate::parser::StatementAddress;
+use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::proof::ProofStyle;
+use crate::proof::ProofTreeArray;
+use crate::proof::ProofTreePrinter;
+use crate::scopeck::Frame;
+use crate::scopeck::Hyp;
+use crate::search::SearchIndex;
+use crate::verify::ProofBuilder;
+use crate::worksheet::Worksheet;
+use crate::worksheet::WorksheetError;
+use crate::Database;
+use crate::Formula;
+use std::cmp::Ordering;
+
+/// The parts of an assertion used to justify a step.
+struct Assertion<'a> {
+    sref: StatementRef<'a>,
+    formula: &'a Formula,
+    essentials: Vec<(StatementRef<'a>, &'a Formula)>,
+}
+
+impl Database {
+    /// Fills in the missing labels, hypotheses and formulas of the steps of a
+    /// worksheet, and checks it again.  If the worksheet is then complete and
+    /// has no problems, its proof is replaced by the compressed proof of the
+    /// theorem.  See the [module documentation](crate::unify) for the rules
+    /// used.
+    ///
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`],
+    /// [`Database::grammar_pass`], [`Database::stmt_parse_pass`]
+    pub fn unify_worksheet(&self, worksheet: &mut Worksheet) {
+        self.check_worksheet(worksheet);
+        let limit = self.worksheet_limit(worksheet);
+        let theorem = self.worksheet_theorem(worksheet);
+        let search = SearchIndex::new(self);
+        loop {
+            let mut changed = false;
+            for index in 0..worksheet.steps.len() {
+                changed |= if worksheet.steps[index].hyp {
+                    match theorem {
+                        Some(theorem) => self.unify_hyp_step(worksheet, index, theorem),
+                        None => false,
+                    }
+                } else {
+                    self.unify_derivation_step(&search, worksheet, index, limit)
+                };
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.check_worksheet(worksheet);
+
+        let theorem = match theorem {
+            Some(theorem) if worksheet.diagnostics.is_empty() => theorem,
+            _ => return,
+        };
+        let arr = match self.worksheet_proof(worksheet) {
+            Ok(arr) => arr,
+            Err(err) => {
+                let qed = worksheet.steps.iter().find(|step| step.name == "qed");
+                let line = qed.map_or(0, |step| step.line);
+                worksheet.diagnostics.push((line, err));
+                return;
+            }
+        };
+        let printer = ProofTreePrinter {
+            sset: self.parse_result(),
+            nset: self.name_result(),
+            scope: self.scope_result(),
+            thm_label: theorem.label(),
+            style: ProofStyle::Compressed,
+            arr: &arr,
+            initial_chr: 2,
+            indent: 6,
+            line_width: 79,
+        };
+        worksheet.proof = printer
+            .to_string()
+            .split_whitespace()
+            .take_while(|&token| token != "$.")
+            .map(str::to_owned)
+            .collect();
+    }
+
+    /// Builds the proof of the theorem of a complete worksheet, from its `qed`
+    /// step.  The steps are not checked again; see
+    /// [`Database::check_worksheet`].  The work variables left in the steps
+    /// are replaced by distinct unused variables in scope at the theorem,
+    /// after which the `$d` conditions of the steps are checked.
+    ///
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`],
+    /// [`Database::grammar_pass`], [`Database::stmt_parse_pass`]
+    pub fn worksheet_proof(&self, worksheet: &Worksheet) -> Result<ProofTreeArray, WorksheetError> {
+        let frame = self
+            .worksheet_theorem(worksheet)
+            .and_then(|theorem| self.scope_result().get(theorem.label()))
+            .ok_or(WorksheetError::NoTheorem)?;
+        let qed = worksheet
+            .steps
+            .iter()
+            .position(|step| step.name == "qed")
+            .ok_or(WorksheetError::MissingQed)?;
+        let formulas = worksheet
+            .steps
+            .iter()
+            .filter_map(|step| step.formula.as_ref())
+            .collect::<Vec<_>>();
+        let variables = scoped_variables(self, frame);
+        let mut elimination = Substitutions::default();
+        if !elimination.eliminate_work_variables(self, &variables, &formulas, &[]) {
+            return Err(WorksheetError::WorkVariables);
+        }
+        self.check_proof_dv(worksheet, frame, &elimination)?;
+        let mut arr = ProofTreeArray::default();
+        let mut built = vec![None; worksheet.steps.len()];
+        let mut stack_buffer = Vec::new();
+        arr.qed = self.build_step_proof(
+            worksheet,
+            &elimination,
+            qed,
+            &mut built,
+            &mut stack_buffer,
+            &mut arr,
+        )?;
+        arr.calc_indent();
+        Ok(arr)
+    }
+
+    /// Adds the proof of a step to a proof tree array, with its work
+    /// variables replaced as given, and returns its index.
+    fn build_step_proof(
+        &self,
+        worksheet: &Worksheet,
+        elimination: &Substitutions,
+        index: usize,
+        built: &mut [Option<usize>],
+        stack_buffer: &mut Vec<u8>,
+        arr: &mut ProofTreeArray,
+    ) -> Result<usize, WorksheetError> {
+        if let Some(ix) = built[index] {
+            return Ok(ix);
+        }
+        let step = &worksheet.steps[index];
+        let (label, formula) = step
+            .label
+            .as_ref()
+            .zip(step.formula.as_ref())
+            .ok_or(WorksheetError::Incomplete)?;
+        let sref = self
+            .statement(label)
+            .ok_or_else(|| WorksheetError::UnknownLabel(label.clone()))?;
+
+        let mut hyps = Vec::new();
+        if !step.hyp {
+            let assertion = self
+                .assertion(sref)
+                .ok_or_else(|| WorksheetError::NotAssertion(label.clone()))?;
+            let hyp_steps = step_hyps(worksheet, index);
+            if hyp_steps.len() != assertion.essentials.len() || hyp_steps.contains(&None) {
+                return Err(WorksheetError::Incomplete);
+            }
+            let hyp_formulas = hyp_formulas(worksheet, &hyp_steps);
+            let substitutions = step_substitutions(Some(formula), &assertion, &hyp_formulas)
+                .ok_or(WorksheetError::ConclusionMismatch)?;
+            let nset = self.name_result();
+            let sset = self.parse_result();
+            let frame = self
+                .scope_result()
+                .get(sref.label())
+                .ok_or_else(|| WorksheetError::NotAssertion(label.clone()))?;
+            let mut hyp_steps = hyp_steps.into_iter().flatten();
+            for hyp in &frame.hypotheses {
+                hyps.push(match *hyp {
+                    Hyp::Floating(address, ..) => {
+                        let variable = nset.lookup_label(sset.statement(address).label());
+                        let substitution = variable
+                            .and_then(|variable| substitutions.get(variable.atom))
+                            .ok_or(WorksheetError::Incomplete)?;
+                        substitution
+                            .substitute(elimination)
+                            .as_ref(self)
+                            .build_syntax_proof(stack_buffer, arr)
+                            .ok_or(WorksheetError::WorkVariables)?
+                    }
+                    Hyp::Essential(..) => {
+                        let hyp_step = hyp_steps.next().ok_or(WorksheetError::Incomplete)?;
+                        self.build_step_proof(
+                            worksheet,
+                            elimination,
+                            hyp_step,
+                            built,
+                            stack_buffer,
+                            arr,
+                        )?
+                    }
+                });
+            }
+        }
+        let range = formula
+            .substitute(elimination)
+            .as_ref(self)
+            .append_to_stack_buffer(stack_buffer);
+        let ix = arr.build(sref.address(), hyps, stack_buffer, range);
+        built[index] = Some(ix);
+        Ok(ix)
+    }
+
+    /// Checks the `$d` conditions of the derivation steps of a worksheet
+    /// against the frame `target` of its theorem, with the work variables of
+    /// the steps replaced as given.
+    fn check_proof_dv(
+        &self,
+        worksheet: &Worksheet,
+        target: &Frame,
+        elimination: &Substitutions,
+    ) -> Result<(), WorksheetError> {
+        for (index, step) in worksheet.steps.iter().enumerate() {
+            let applied = step.label.as_ref().filter(|_| !step.hyp).and_then(|label| {
+                let sref = self.statement(label)?;
+                Some((
+                    self.assertion(sref)?,
+                    self.scope_result().get(sref.label())?,
+                ))
+            });
+            if let Some((assertion, frame)) = applied {
+                let hyp_formulas = hyp_formulas(worksheet, &step_hyps(worksheet, index));
+                if let Some(substitutions) =
+                    step_substitutions(step.formula.as_ref(), &assertion, &hyp_formulas)
+                {
+                    let mut eliminated = Substitutions::default();
+                    for (label, formula) in substitutions.iter() {
+                        eliminated.insert(label, formula.substitute(elimination));
+                    }
+                    self.check_step_dv(frame, &eliminated, Some(target))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the formulas of the assertion applied by a statement, or
+    /// `None` if it is not a `$a` or `$p` statement parsed by the grammar.
+    fn assertion<'a>(&'a self, sref: StatementRef<'a>) -> Option<Assertion<'a>> {
+        if !matches!(
+            sref.statement_type(),
+            StatementType::Axiom | StatementType::Provable
+        ) {
+            return None;
+        }
+        let stmt_parse = self.stmt_parse_result();
+        let sset = self.parse_result();
+        let formula = stmt_parse.get_formula(&sref)?;
+        let frame = self.scope_result().get(sref.label())?;
+        let mut essentials = Vec::new();
+        for hyp in &frame.hypotheses {
+            if let Hyp::Essential(address, _) = *hyp {
+                let essential = sset.statement(address);
+                essentials.push((essential, stmt_parse.get_formula(&essential)?));
+            }
+        }
+        Some(Assertion {
+            sref,
+            formula,
+            essentials,
+        })
+    }
+
+    /// Sets the formula of a step, and the tokens of its math string.
+    fn set_step_formula(
+        &self,
+        worksheet: &mut Worksheet,
+        index: usize,
+        typecode: &[u8],
+        formula: Formula,
+    ) {
+        let mut math = vec![as_str(typecode).to_owned()];
+        math.extend(
+            formula
+                .as_ref(self)
+                .into_iter()
+                .map(|symbol| symbol_name(self, symbol).into_owned()),
+        );
+        let step = &mut worksheet.steps[index];
+        step.math = math;
+        step.formula = Some(formula);
+    }
+
+    /// Completes a hypothesis step from the hypotheses of the theorem.
+    fn unify_hyp_step(
+        &self,
+        worksheet: &mut Worksheet,
+        index: usize,
+        theorem: StatementRef<'_>,
+    ) -> bool {
+        let stmt_parse = self.stmt_parse_result();
+        let sset = self.parse_result();
+        let hypotheses = self
+            .scope_result()
+            .get(theorem.label())
+            .map_or(&[][..], |frame| &frame.hypotheses);
+        let essentials = hypotheses.iter().filter_map(|hyp| match *hyp {
+            Hyp::Essential(address, _) => Some(sset.statement(address)),
+            Hyp::Floating(..) => None,
+        });
+        let step = &worksheet.steps[index];
+        match (&step.label, &step.formula) {
+            (None, Some(formula)) => {
+                let mut matching = essentials
+                    .filter(|sref| stmt_parse.get_formula(sref) == Some(formula))
+                    .map(|sref| as_str(sref.label()).to_owned());
+                match (matching.next(), matching.next()) {
+                    (Some(label), None) => {
+                        worksheet.steps[index].label = Some(label);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            (Some(label), None) => {
+                let sref = essentials
+                    .into_iter()
+                    .find(|sref| sref.label() == label.as_bytes());
+                match sref.and_then(|sref| Some((sref, stmt_parse.get_formula(&sref)?))) {
+                    Some((sref, formula)) => {
+                        let typecode = sref.math_at(0).slice;
+                        self.set_step_formula(worksheet, index, typecode, formula.clone());
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Completes a derivation step, returning `true` if it changed.
+    fn unify_derivation_step(
+        &self,
+        search: &SearchIndex,
+        worksheet: &mut Worksheet,
+        index: usize,
+        limit: Option<(StatementAddress, Ordering)>,
+    ) -> bool {
+        let mut hyps = step_hyps(worksheet, index);
+        // names which do not refer to an earlier step are left alone
+        let step = &worksheet.steps[index];
+        if step
+            .hyps
+            .iter()
+            .zip(&hyps)
+            .any(|(name, hyp)| name.is_some() && hyp.is_none())
+        {
+            return false;
+        }
+        let assertion = match step.label {
+            Some(ref label) => match self.statement(label).and_then(|sref| self.assertion(sref)) {
+                Some(assertion) => assertion,
+                None => return false,
+            },
+            None => match step.formula {
+                Some(ref formula) => {
+                    let hyp_formulas = hyp_formulas(worksheet, &hyps);
+                    match self.find_assertion(search, &step.math[0], formula, &hyp_formulas, limit)
+                    {
+                        Some(assertion) => {
+                            let label = as_str(assertion.sref.label()).to_owned();
+                            worksheet.steps[index].label = Some(label);
+                            return true;
+                        }
+                        None => return false,
+                    }
+                }
+                None => return false,
+            },
+        };
+
+        let mut changed = if hyps.is_empty() && !assertion.essentials.is_empty() {
+            hyps = vec![None; assertion.essentials.len()];
+            true
+        } else {
+            false
+        };
+        if hyps.len() != assertion.essentials.len() {
+            return false;
+        }
+        let formula = worksheet.steps[index].formula.as_ref();
+        let hyp_formulas = hyp_formulas(worksheet, &hyps);
+        if let Some(mut substitutions) = step_substitutions(formula, &assertion, &hyp_formulas) {
+            // fill in the missing hypotheses which only one earlier step matches
+            for (hyp, &(_, essential)) in hyps.iter_mut().zip(&assertion.essentials) {
+                if hyp.is_some() {
+                    continue;
+                }
+                let mut matching =
+                    (0..index).filter(|&candidate| match worksheet.steps[candidate].formula {
+                        Some(ref formula) => {
+                            formula.unify_with(essential, &mut substitutions.clone())
+                        }
+                        None => false,
+                    });
+                if let (Some(candidate), None) = (matching.next(), matching.next()) {
+                    let formula = worksheet.steps[candidate].formula.as_ref().unwrap();
+                    formula.unify_with(essential, &mut substitutions);
+                    *hyp = Some(candidate);
+                    changed = true;
+                }
+            }
+            // derive the formulas of the step and of the steps it refers to, once
+            // all the variables they use are known
+            let known = |formula: &Formula| {
+                formula
+                    .variables()
+                    .into_iter()
+                    .all(|variable| substitutions.get(variable).is_some())
+            };
+            if worksheet.steps[index].formula.is_none() && known(assertion.formula) {
+                let formula = assertion.formula.substitute(&substitutions);
+                let typecode = assertion.sref.math_at(0).slice;
+                self.set_step_formula(worksheet, index, typecode, formula);
+                changed = true;
+            }
+            for (&hyp, &(sref, essential)) in hyps.iter().zip(&assertion.essentials) {
+                if let Some(hyp) = hyp {
+                    if worksheet.steps[hyp].formula.is_none() && known(essential) {
+                        let formula = essential.substitute(&substitutions);
+                        self.set_step_formula(worksheet, hyp, sref.math_at(0).slice, formula);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if changed {
+            let names = hyps
+                .iter()
+                .map(|&hyp| hyp.map(|hyp| worksheet.steps[hyp].name.clone()))
+                .collect();
+            worksheet.steps[index].hyps = names;
+        }
+        changed
+    }
+
+    /// Finds the only assertion before the limit which justifies a step
+    /// with the given typecode, formula and hypotheses.
+    fn find_assertion(
+        &self,
+        search: &SearchIndex,
+        typecode: &str,
+        formula: &Formula,
+        hyps: &[Option<&Formula>],
+        limit: Option<(StatementAddress, Ordering)>,
+    ) -> Option<Assertion<'_>> {
+        let sset = self.parse_result();
+        let mut found = None;
+        for application in search.applicable(self, formula, None) {
+            if let Some((address, ordering)) = limit {
+                if sset.order.cmp(&application.address, &address) > ordering {
+                    continue;
+                }
+            }
+            let sref = sset.statement(application.address);
+            if sref.math_len() == 0 || sref.math_at(0).slice != typecode.as_bytes() {
+                continue;
+            }
+            // the work variables of the step are not substituted here
+            match self.stmt_parse_result().get_formula(&sref) {
+                Some(assertion) if formula.unify(assertion).is_some() => {}
+                _ => continue,
+            }
+            if let Some(assertion) = self.assertion(sref) {
+                if assertion.essentials.len() == hyps.len()
+                    && step_substitutions(Some(formula), &assertion, hyps).is_some()
+                {
+                    if found.is_some() {
+                        return None;
+                    }
+                    found = Some(assertion);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Returns the indices of the steps a step refers to, or `None` for the
+/// missing ones and the ones which do not come before it.
+fn step_hyps(worksheet: &Worksheet, index: usize) -> Vec<Option<usize>> {
+    worksheet.steps[index]
+        .hyps
+        .iter()
+        .map(|name| {
+            let name = name.as_ref()?;
+            worksheet.steps[..index]
+                .iter()
+                .position(|step| step.name == *name)
+        })
+        .collect()
+}
+
+/// Returns the formulas of the steps a step refers to.
+fn hyp_formulas<'a>(worksheet: &'a Worksheet, hyps: &[Option<usize>]) -> Vec<Option<&'a Formula>> {
+    hyps.iter()
+        .map(|&hyp| hyp.and_then(|hyp| worksheet.steps[hyp].formula.as_ref()))
+        .collect()
+}
+
+/// Unifies a step formula, if known, with an assertion, and the known
+/// formulas of its hypotheses with the essential hypotheses of the
+/// assertion, in a single substitution.
+fn step_substitutions(
+    formula: Option<&Formula>,
+    assertion: &Assertion<'_>,
+    hyps: &[Option<&Formula>],
+) -> Option<Substitutions> {
+    let mut substitutions = Substitutions::default();
+    if let Some(formula) = formula {
+        if !formula.unify_with(assertion.formula, &mut substitutions) {
+            return None;
+        }
+    }
+    for (hyp, &(_, essential)) in hyps.iter().zip(&assertion.essentials) {
+        if let Some(hyp) = hyp {
+            if !hyp.unify_with(essential, &mut substitutions) {
+                return None;
+            }
+        }
+    }
+    Some(substitutions)
+}
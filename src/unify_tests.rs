@@ -0,0 +1,234 @@
+use crate::diag::DiagnosticClass;
+use crate::grammar_tests::mkdb;
+use crate::proof::ProofStyle;
+use crate::show::ProofLayout;
+use crate::worksheet_tests::worksheet_db;
+use crate::worksheet_tests::WORKSHEET_DB;
+
+const A1I_WORKSHEET: &str = "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1
+2::ax-1
+qed:1,2: |- ( ps -> ph )
+";
+
+#[test]
+fn test_unify_worksheet() {
+    let db = worksheet_db();
+    let mut worksheet = db.import_mmp(A1I_WORKSHEET);
+    assert_eq!(worksheet.diagnostics.len(), 2);
+    db.unify_worksheet(&mut worksheet);
+    assert!(worksheet.diagnostics.is_empty());
+    assert_eq!(
+        worksheet.to_string(),
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1 |- ph
+2::ax-1 |- ( ph -> ( ps -> ph ) )
+qed:1,2:ax-mp |- ( ps -> ph )
+
+$=    ( wi ax-1 ax-mp ) ABADCABEF $.
+
+$)
+"
+    );
+
+    // the proof is the one of the database
+    let mut out = Vec::new();
+    let layout = ProofLayout::Style(ProofStyle::Compressed);
+    db.show_proof(db.statement("a1i").unwrap(), layout, false, &mut out)
+        .unwrap();
+    let expected = String::from_utf8(out).unwrap();
+    assert_eq!(format!("{} $.", worksheet.proof.join(" ")), expected.trim());
+}
+
+#[test]
+fn test_unify_hypotheses() {
+    let db = worksheet_db();
+    let mut worksheet = db.import_mmp(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1:: |- ph
+2:: |- ( ph -> ( ps -> ph ) )
+qed::ax-mp |- ( ps -> ph )
+",
+    );
+    db.unify_worksheet(&mut worksheet);
+    assert!(worksheet.diagnostics.is_empty());
+    assert_eq!(worksheet.steps[0].label.as_deref(), Some("a1i.1"));
+    assert_eq!(worksheet.steps[1].label.as_deref(), Some("ax-1"));
+    let qed = worksheet.step("qed").unwrap();
+    assert_eq!(qed.hyps, vec![Some("1".to_owned()), Some("2".to_owned())]);
+    assert_eq!(worksheet.proof.join(" "), "( wi ax-1 ax-mp ) ABADCABEF");
+}
+
+#[test]
+fn test_unify_incomplete() {
+    let db = worksheet_db();
+    // the formula of step 2 cannot be derived, as ph and ps are not known
+    let mut worksheet = db.import_mmp(
+        "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1
+2::ax-1
+qed:1,?:ax-mp
+",
+    );
+    db.unify_worksheet(&mut worksheet);
+    assert_eq!(worksheet.steps[0].math, vec!["|-", "ph"]);
+    assert!(worksheet.steps[1].formula.is_none());
+    assert!(worksheet.step("qed").unwrap().formula.is_none());
+    assert!(worksheet.proof.is_empty());
+    assert!(!worksheet.has_errors());
+    assert!(db.worksheet_proof(&worksheet).is_err());
+}
+
+#[test]
+fn test_unify_mismatch() {
+    let db = worksheet_db();
+    let mut worksheet = db.import_mmp(
+        "$( <MM> <PROOF_ASST> THEOREM=new  LOC_AFTER=a1i
+
+qed::a1i |- ch
+",
+    );
+    db.unify_worksheet(&mut worksheet);
+    assert_eq!(worksheet.step("qed").unwrap().hyps, vec![None]);
+    assert!(worksheet.has_errors());
+    assert!(matches!(
+        db.worksheet_proof(&worksheet),
+        Err(crate::worksheet::WorksheetError::NoTheorem)
+    ));
+}
+
+#[test]
+fn test_unify_work_variables() {
+    let text = [
+        WORKSHEET_DB,
+        b"
+    id $a |- ( ph -> ph ) $.
+    ax-w $a |- ( ( ph -> ph ) -> ps ) $.
+    th $p |- ps $= ? $.
+",
+    ]
+    .concat();
+    let mut db = mkdb(&text);
+    db.scope_pass();
+    db.stmt_parse_pass();
+    // the work variables left are replaced by unused variables
+    let mut worksheet = db.import_mmp(
+        "$( <MM> <PROOF_ASST> THEOREM=th  LOC_AFTER=?
+
+1::id |- ( ( &W1 -> &W2 ) -> ( &W1 -> &W2 ) )
+2::ax-w |- ( ( ( &W1 -> &W2 ) -> ( &W1 -> &W2 ) ) -> ps )
+qed:1,2:ax-mp |- ps
+",
+    );
+    db.unify_worksheet(&mut worksheet);
+    assert!(worksheet.diagnostics.is_empty());
+    let proof = worksheet.proof.join(" ");
+    assert!(!proof.is_empty());
+
+    let text = String::from_utf8(text)
+        .unwrap()
+        .replace("$= ? $.", &format!("$= {proof} $."));
+    let mut verified = mkdb(text.as_bytes());
+    verified.verify_pass();
+    assert!(verified
+        .diag_notations(&[DiagnosticClass::Verify])
+        .is_empty());
+
+    // there are only two variables besides ps
+    let mut worksheet = db.import_mmp(
+        "$( <MM> <PROOF_ASST> THEOREM=th  LOC_AFTER=?
+
+1::id |- ( ( &W1 -> ( &W2 -> &W3 ) ) -> ( &W1 -> ( &W2 -> &W3 ) ) )
+2::ax-w |- ( ( ( &W1 -> ( &W2 -> &W3 ) ) -> ( &W1 -> ( &W2 -> &W3 ) ) ) -> ps )
+qed:1,2:ax-mp |- ps
+",
+    );
+    db.unify_worksheet(&mut worksheet);
+    assert!(worksheet.proof.is_empty());
+    let diags = worksheet
+        .diagnostics
+        .iter()
+        .map(|(line, err)| format!("{line}: {err}"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        diags,
+        ["5: Not enough variables in scope to replace the work variables"]
+    );
+}
+
+#[test]
+fn test_unify_dv() {
+    let text = [
+        WORKSHEET_DB,
+        b"
+    ${ $d ph ps $. ax-d $a |- ( ph -> ps ) $. $}
+    ax-w $a |- ( ( ph -> ps ) -> ch ) $.
+    th1 $p |- ( ph -> ph ) $= ? $.
+    th2 $p |- ( ph -> ps ) $= ? $.
+    ${ $d ph ps $. th3 $p |- ( ph -> ps ) $= ? $. $}
+    th4 $p |- ch $= ? $.
+",
+    ]
+    .concat();
+    let mut db = mkdb(&text);
+    db.scope_pass();
+    db.stmt_parse_pass();
+    let unify = |worksheet: &str| {
+        let mut worksheet = db.import_mmp(worksheet);
+        db.unify_worksheet(&mut worksheet);
+        let diags = worksheet
+            .diagnostics
+            .iter()
+            .map(|(line, err)| format!("{line}: {err}"))
+            .collect::<Vec<_>>();
+        (diags, worksheet.proof.join(" "))
+    };
+
+    // the variables of the assertion are substituted with the same variable
+    let (diags, proof) = unify(
+        "$( <MM> <PROOF_ASST> THEOREM=th1  LOC_AFTER=?
+
+qed::ax-d |- ( ph -> ph )
+",
+    );
+    assert_eq!(
+        diags,
+        ["3: Variables ph and ps must be disjoint, but both contain ph"]
+    );
+    assert!(proof.is_empty());
+
+    // the theorem has no $d statement for the variables
+    let (diags, proof) = unify(
+        "$( <MM> <PROOF_ASST> THEOREM=th2  LOC_AFTER=?
+
+qed::ax-d |- ( ph -> ps )
+",
+    );
+    assert_eq!(diags, ["3: Variables ph and ps must be disjoint"]);
+    assert!(proof.is_empty());
+
+    let (diags, proof) = unify(
+        "$( <MM> <PROOF_ASST> THEOREM=th3  LOC_AFTER=?
+
+qed::ax-d |- ( ph -> ps )
+",
+    );
+    assert!(diags.is_empty());
+    assert_eq!(proof, "( ax-d ) ABC");
+
+    // the work variables are replaced by variables which must be disjoint
+    let (diags, proof) = unify(
+        "$( <MM> <PROOF_ASST> THEOREM=th4  LOC_AFTER=?
+
+1::ax-d |- ( &W1 -> &W2 )
+2::ax-w |- ( ( &W1 -> &W2 ) -> ch )
+qed:1,2:ax-mp |- ch
+",
+    );
+    assert_eq!(diags, ["5: Variables ph and ps must be disjoint"]);
+    assert!(proof.is_empty());
+}
@@ -10,6 +10,12 @@ use std::slice;
 pub(crate) type HashMap<K, V> = collections::HashMap<K, V, BuildHasherDefault<FnvHasher>>;
 /// Type alias for hashsets to allow swapping out the implementation.
 pub(crate) type HashSet<K> = collections::HashSet<K, BuildHasherDefault<FnvHasher>>;
+/// Type alias for structurally shared hashmaps, used for indexes which are
+/// kept in analysis results: cloning one is O(1), and updating a clone only
+/// copies the touched paths of the tree.
+pub(crate) type PersistentMap<K, V> = im::HashMap<K, V, BuildHasherDefault<FnvHasher>>;
+/// Type alias for structurally shared vectors; see `PersistentMap`.
+pub(crate) type PersistentVec<T> = im::Vector<T>;
 
 /// Empty a vector of a POD type without checking each element for droppability.
 pub(crate) fn fast_clear<T: Copy>(vec: &mut Vec<T>) {
@@ -33,15 +39,32 @@ pub(crate) fn fast_extend<T: Copy>(vec: &mut Vec<T>, other: &[T]) {
     vec.reserve(other.len());
     unsafe {
         let len = vec.len();
-        short_copy(
-            other.get_unchecked(0),
-            vec.get_unchecked_mut(len),
-            other.len(),
-        );
+        short_copy(other.as_ptr(), vec.as_mut_ptr().add(len), other.len());
         vec.set_len(len + other.len());
     }
 }
 
+/// Quotes a string for inclusion in JSON output.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            ch if (ch as u32) < 0x20 => {
+                out.push_str("\\u00");
+                out.push(char::from_digit(ch as u32 >> 4, 16).unwrap());
+                out.push(char::from_digit(ch as u32 & 15, 16).unwrap());
+            }
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
 // Rust already assumes you're on a twos-complement byte-addressed pure-endian
 // machine. A chapter header is CRLF+ $ ( CRLF+ #*#...#*#, 79 total punctuation.
 // Thus, it has #*#* or *#*# on any 32*19-bit boundary
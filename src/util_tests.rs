@@ -26,6 +26,16 @@ fn test_fast_extend() {
     assert_eq!(vec, vec![1, 2, 3, 4, 5, 6]);
 }
 
+#[test]
+fn test_fast_extend_empty() {
+    let mut vec = Vec::<u32>::new();
+    util::fast_extend(&mut vec, &[]);
+    assert!(vec.is_empty());
+    util::fast_extend(&mut vec, &[1]);
+    util::fast_extend(&mut vec, &[]);
+    assert_eq!(vec, vec![1]);
+}
+
 #[test]
 fn test_copy_portion() {
     let mut s = Vec::from(b"Hello world" as &[u8]);
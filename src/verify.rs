@@ -25,22 +25,26 @@
 //! than it is now.
 
 use crate::bit_set::Bitset;
+use crate::database::estimate_bytes;
+use crate::database::Promise;
 use crate::diag::Diagnostic;
 use crate::nameck::{Atom, Nameset};
 use crate::parser::{
-    self, Comparer, Segment, SegmentId, SegmentOrder, SegmentRef, Span, StatementAddress,
+    self, as_str, Comparer, Segment, SegmentId, SegmentOrder, SegmentRef, Span, StatementAddress,
     StatementRef, StatementType, TokenPtr, NO_STATEMENT,
 };
+use crate::profile::ProofProfile;
 use crate::scopeck::{
     self, ExprFragment, Frame, Hyp::*, ScopeReader, ScopeResult, ScopeUsage, VerifyExpr,
 };
 use crate::segment_set::SegmentSet;
-use crate::util::{fast_clear, fast_extend, HashMap};
+use crate::util::{fast_clear, fast_extend, HashMap, HashSet, PersistentMap};
 use crate::Database;
 use std::cmp::Ordering;
 use std::mem;
 use std::ops::Range;
 use std::sync::Arc;
+use std::time::Instant;
 
 // Proofs are very fragile and there are very few situations where errors are
 // recoverable, so we bail out using Result on any error.
@@ -95,6 +99,13 @@ pub trait ProofBuilder {
         pool: &[u8],
         expr: Range<usize>,
     ) -> Self::Item;
+
+    /// Create a proof data node for an unknown step `?` of an incomplete
+    /// proof, whose expression is not known, or `None` if incomplete proofs
+    /// are to be rejected, which is the default.
+    fn build_unknown(&mut self) -> Option<Self::Item> {
+        None
+    }
 }
 
 /// The "null" proof builder, which creates no extra data. This
@@ -140,6 +151,13 @@ struct VerifyState<'a, P: ProofBuilder> {
     var2bit: HashMap<Atom, usize>,
     /// Disjoint variable conditions in the current extended frame
     dv_map: &'a [Bitset],
+    /// Number of steps executed in the current proof
+    steps: usize,
+    /// Largest stack size reached in the current proof
+    max_depth: usize,
+    /// Variables of the assertion being applied which are substituted by
+    /// unknown steps of an incomplete proof
+    unknown_vars: Vec<usize>,
 }
 
 type Result<T> = std::result::Result<T, Diagnostic>;
@@ -444,21 +462,35 @@ fn process_hyp<P: ProofBuilder>(
     state.builder.push(datavec, data.clone());
     match *hyp {
         Floating(_addr, var_index, typecode) => {
-            try_assert!(slot.code == typecode, Diagnostic::StepFloatWrongType);
-            state.subst_info[var_index] = (slot.expr.clone(), slot.vars.clone());
+            if slot.code == typecode {
+                state.subst_info[var_index] = (slot.expr.clone(), slot.vars.clone());
+            } else {
+                // only an unknown step can stand for any variable
+                try_assert!(slot.code == Atom::default(), Diagnostic::StepFloatWrongType);
+                state.subst_info[var_index] = (0..0, Bitset::new());
+                state.unknown_vars.push(var_index);
+            }
         }
         Essential(_addr, ref expr) => {
-            try_assert!(slot.code == expr.typecode, Diagnostic::StepEssenWrongType);
-            try_assert!(
-                do_substitute_eq(
-                    &state.stack_buffer[slot.expr.clone()],
-                    frame,
-                    expr,
-                    &state.subst_info,
-                    &state.stack_buffer
-                ),
-                Diagnostic::StepEssenWrong
-            );
+            if slot.code == expr.typecode {
+                // the hypothesis cannot be checked if it uses a variable
+                // substituted by an unknown step
+                try_assert!(
+                    do_substitute_eq(
+                        &state.stack_buffer[slot.expr.clone()],
+                        frame,
+                        expr,
+                        &state.subst_info,
+                        &state.stack_buffer
+                    ) || expr
+                        .tail
+                        .iter()
+                        .any(|part| state.unknown_vars.contains(&part.var)),
+                    Diagnostic::StepEssenWrong
+                );
+            } else {
+                try_assert!(slot.code == Atom::default(), Diagnostic::StepEssenWrongType);
+            }
         }
     }
     Ok(())
@@ -488,6 +520,8 @@ fn execute_step<P: ProofBuilder>(
                     expr: expr.clone(),
                 },
             ));
+            state.steps += 1;
+            state.max_depth = state.max_depth.max(state.stack.len());
             return Ok(());
         }
         Assert(fref) => fref,
@@ -579,13 +613,24 @@ fn execute_step<P: ProofBuilder>(
     // pool, because they might have been saved steps or hypotheses, and
     // deciding whether we need to move anything would swamp any savings, anyway
     // - remember that this function is largely a branch predictor benchmark
+    // the result of an incomplete proof is unknown if it depends on an
+    // unknown step
+    let unknown = !state.unknown_vars.is_empty() && {
+        let unknown_vars = mem::take(&mut state.unknown_vars);
+        fref.target
+            .tail
+            .iter()
+            .any(|part| unknown_vars.contains(&part.var))
+    };
     let old_top = state.stack_buffer.len();
-    do_substitute(
-        &mut state.stack_buffer,
-        fref,
-        &fref.target,
-        &state.subst_info,
-    );
+    if !unknown {
+        do_substitute(
+            &mut state.stack_buffer,
+            fref,
+            &fref.target,
+            &state.subst_info,
+        );
+    }
     let new_top = state.stack_buffer.len();
 
     state.stack.truncate(sbase);
@@ -597,11 +642,17 @@ fn execute_step<P: ProofBuilder>(
             old_top..new_top,
         ),
         StackSlot {
-            code: fref.target.typecode,
+            code: if unknown {
+                Atom::default()
+            } else {
+                fref.target.typecode
+            },
             vars: do_substitute_vars(&fref.target.tail, &state.subst_info),
             expr: old_top..new_top,
         },
     ));
+    state.steps += 1;
+    state.max_depth = state.max_depth.max(state.stack.len());
 
     // check $d constraints on the used assertion now that the dust has settled.
     // Remember that we might have variable indexes allocated during the proof
@@ -624,6 +675,9 @@ fn finalize_step<P: ProofBuilder>(state: &mut VerifyState<'_, P>) -> Result<P::I
     // if we get here, it's a valid proof, but was it the _right_ valid proof?
     try_assert!(state.stack.len() <= 1, Diagnostic::ProofExcessEnd);
     let &(ref data, ref tos) = state.stack.last().ok_or(Diagnostic::ProofNoSteps)?;
+    if tos.code == Atom::default() {
+        return Ok(data.clone());
+    }
 
     try_assert!(
         tos.code == state.cur_frame.target.typecode,
@@ -654,6 +708,28 @@ fn save_step<P: ProofBuilder>(state: &mut VerifyState<'_, P>) {
     ));
 }
 
+/// Pushes an unknown step `?` of an incomplete proof, if the proof builder
+/// accepts them.  Its typecode is the default atom, which is never the
+/// typecode of a known result since atoms are numbered from 1.
+fn push_unknown<P: ProofBuilder>(state: &mut VerifyState<'_, P>) -> Result<()> {
+    let data = state
+        .builder
+        .build_unknown()
+        .ok_or(Diagnostic::ProofIncomplete)?;
+    let top = state.stack_buffer.len();
+    state.stack.push((
+        data,
+        StackSlot {
+            vars: Bitset::new(),
+            code: Atom::default(),
+            expr: top..top,
+        },
+    ));
+    state.steps += 1;
+    state.max_depth = state.max_depth.max(state.stack.len());
+    Ok(())
+}
+
 // proofs are not self-synchronizing, so it's not likely to get >1 usable error
 fn verify_proof<'a, P: ProofBuilder>(
     state: &mut VerifyState<'a, P>,
@@ -665,6 +741,8 @@ fn verify_proof<'a, P: ProofBuilder>(
     state.prepared.clear();
     state.var2bit.clear();
     state.dv_map = &state.cur_frame.optional_dv;
+    state.steps = 0;
+    state.max_depth = 0;
     // temp_buffer is cleared before use; subst_info should be overwritten
     // before use if scopeck is working correctly
 
@@ -724,7 +802,8 @@ fn verify_proof<'a, P: ProofBuilder>(
                     can_save = false;
                 } else if ch == b'?' {
                     try_assert!(k == 0, Diagnostic::ProofMalformedVarint);
-                    return Err(Diagnostic::ProofIncomplete);
+                    push_unknown(state)?;
+                    can_save = false;
                 }
             }
             i += 1;
@@ -740,7 +819,13 @@ fn verify_proof<'a, P: ProofBuilder>(
         for i in 0..stmt.proof_len() {
             let span = stmt.proof_span(i);
             let chunk = stmt.proof_slice_at(i);
-            try_assert!(chunk != b"?", Diagnostic::ProofIncomplete);
+            if chunk == b"?" {
+                push_unknown(state)?;
+                if let Some(explicit_stack) = explicit_stack.as_mut() {
+                    explicit_stack.push(None);
+                }
+                continue;
+            }
             let step = prepare_step(state, chunk, Some(span))?;
             if let Some(label) = step.label {
                 try_assert!(step.fwdref.is_none(), Diagnostic::ChainBackref(span));
@@ -785,7 +870,7 @@ struct VerifySegment {
 /// Analysis pass result for the verifier.
 #[derive(Default, Clone, Debug)]
 pub struct VerifyResult {
-    segments: HashMap<SegmentId, Arc<VerifySegment>>,
+    segments: PersistentMap<SegmentId, Arc<VerifySegment>>,
 }
 
 impl VerifyResult {
@@ -826,6 +911,9 @@ fn verify_segment(
         subst_info: Vec::new(),
         var2bit: HashMap::default(),
         dv_map: &dummy_frame.optional_dv,
+        steps: 0,
+        max_depth: 0,
+        unknown_vars: Vec::new(),
     };
     // use the _same_ VerifyState so that memory can be reused
     for stmt in sref {
@@ -848,6 +936,122 @@ fn verify_segment(
     }
 }
 
+/// Counts the distinct statements referenced by label in a proof, as an
+/// assertion or as a hypothesis.
+fn count_referenced(nset: &Nameset, stmt: StatementRef<'_>) -> usize {
+    let mut labels = HashSet::default();
+    if stmt.proof_len() > 0 && stmt.proof_slice_at(0) == b"(" {
+        for i in 1..stmt.proof_len() {
+            let chunk = stmt.proof_slice_at(i);
+            if chunk == b")" {
+                break;
+            }
+            labels.insert(chunk);
+        }
+    } else {
+        for i in 0..stmt.proof_len() {
+            let chunk = stmt.proof_slice_at(i);
+            // strip the local label and explicit hypothesis parts
+            let start = chunk
+                .iter()
+                .rposition(|&x| x == b':' || x == b'=')
+                .map_or(0, |pos| pos + 1);
+            labels.insert(&chunk[start..]);
+        }
+    }
+    labels
+        .into_iter()
+        .filter(|&label| nset.lookup_label(label).is_some())
+        .count()
+}
+
+/// Length of the packed step string of a compressed proof, i.e. the letters
+/// after the closing parenthesis of the label list.
+fn compressed_length(stmt: StatementRef<'_>) -> Option<usize> {
+    if stmt.proof_len() == 0 || stmt.proof_slice_at(0) != b"(" {
+        return None;
+    }
+    let close = (1..stmt.proof_len()).find(|&i| stmt.proof_slice_at(i) == b")")?;
+    Some(
+        (close + 1..stmt.proof_len())
+            .map(|i| stmt.proof_slice_at(i).len())
+            .sum(),
+    )
+}
+
+/// Verifies each proof in a segment again, recording metrics for each.
+fn profile_segment(
+    sset: &SegmentSet,
+    nset: &Nameset,
+    scopes: &ScopeResult,
+    sid: SegmentId,
+) -> Vec<ProofProfile> {
+    let mut out = Vec::new();
+    let dummy_frame = Frame::default();
+    let sref = sset.segment(sid);
+    let mut state = VerifyState {
+        this_seg: sref,
+        scoper: ScopeReader::new(scopes),
+        nameset: nset,
+        builder: &mut (),
+        order: &sset.order,
+        cur_frame: &dummy_frame,
+        stack: Vec::new(),
+        stack_buffer: Vec::new(),
+        prepared: Vec::new(),
+        temp_buffer: Vec::new(),
+        subst_info: Vec::new(),
+        var2bit: HashMap::default(),
+        dv_map: &dummy_frame.optional_dv,
+        steps: 0,
+        max_depth: 0,
+        unknown_vars: Vec::new(),
+    };
+    for stmt in sref {
+        if stmt.statement_type() == StatementType::Provable {
+            if let Some(frame) = state.scoper.get(stmt.label()) {
+                state.cur_frame = frame;
+                let start = Instant::now();
+                let valid = verify_proof(&mut state, stmt).is_ok();
+                let time = start.elapsed();
+                out.push(ProofProfile {
+                    address: stmt.address(),
+                    label: as_str(stmt.label()).to_owned(),
+                    valid,
+                    time,
+                    steps: state.steps,
+                    max_depth: state.max_depth,
+                    referenced: count_referenced(nset, stmt),
+                    compressed_length: compressed_length(stmt),
+                });
+            }
+        }
+    }
+    out
+}
+
+/// Verifies all proofs in the database again, collecting a `ProofProfile`
+/// for each one, in database order.
+///
+/// Unlike the verify pass, nothing is cached, since the timings are the point.
+pub(crate) fn profile(
+    segments: &Arc<SegmentSet>,
+    nset: &Arc<Nameset>,
+    scope: &Arc<ScopeResult>,
+) -> Vec<ProofProfile> {
+    let mut ssrq = Vec::new();
+    for sref in segments.segments() {
+        let segments2 = segments.clone();
+        let nset = nset.clone();
+        let scope = scope.clone();
+        let id = sref.id;
+        ssrq.push(segments.exec.exec(estimate_bytes(sref.bytes()), move || {
+            profile_segment(&segments2, &nset, &scope, id)
+        }));
+    }
+    ssrq.into_iter().flat_map(Promise::wait).collect()
+}
+
 /// Calculates or updates the verification result for a database.
 pub(crate) fn verify(
     result: &mut VerifyResult,
@@ -857,24 +1061,45 @@ pub(crate) fn verify(
 ) {
     let old = mem::take(&mut result.segments);
     let mut ssrq = Vec::new();
-    for sref in segments.segments() {
+    let srefs = segments.segments();
+    let count = srefs.len();
+    for (index, sref) in srefs.into_iter().enumerate() {
         let segments2 = segments.clone();
         let nset = nset.clone();
         let scope = scope.clone();
         let id = sref.id;
         let old_res_o = old.get(&id).cloned();
-        ssrq.push(segments.exec.exec(sref.bytes(), move || {
-            let sref = segments2.segment(id);
-            if let Some(old_res) = old_res_o {
-                if old_res.scope_usage.valid(&nset, &scope) && Arc::ptr_eq(&old_res.source, &sref) {
-                    return (id, old_res);
-                }
-            }
-            if segments2.options.trace_recalc {
-                println!("verify({:?})", parser::guess_buffer_name(&sref.buffer));
-            }
-            (id, Arc::new(verify_segment(&segments2, &nset, &scope, id)))
-        }))
+        ssrq.push(
+            segments
+                .exec
+                .exec_segment("verify", id, sref.bytes(), move || {
+                    let start = Instant::now();
+                    let sref = segments2.segment(id);
+                    if let Some(old_res) = old_res_o {
+                        if old_res.scope_usage.valid(&nset, &scope)
+                            && Arc::ptr_eq(&old_res.source, &sref)
+                        {
+                            segments2.progress.segment(
+                                "verify",
+                                start,
+                                index,
+                                count,
+                                &sref.buffer,
+                                true,
+                            );
+                            return (id, old_res);
+                        }
+                    }
+                    if segments2.options.trace_recalc {
+                        println!("verify({:?})", parser::guess_buffer_name(&sref.buffer));
+                    }
+                    let res = verify_segment(&segments2, &nset, &scope, id);
+                    segments2
+                        .progress
+                        .segment("verify", start, index, count, &sref.buffer, false);
+                    (id, Arc::new(res))
+                }),
+        )
     }
 
     result.segments.clear();
@@ -906,6 +1131,9 @@ pub(crate) fn verify_one<P: ProofBuilder>(
         subst_info: Vec::new(),
         var2bit: HashMap::default(),
         dv_map: &dummy_frame.optional_dv,
+        steps: 0,
+        max_depth: 0,
+        unknown_vars: Vec::new(),
     };
 
     assert!(stmt.statement_type() == StatementType::Provable);
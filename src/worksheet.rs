@@ -0,0 +1,668 @@
+//! Import of mmj2 proof worksheets.
+//!
+//! `Database::import_mmp` reads a proof worksheet in the format written by
+//! `Database::export_mmp`, and checks each of its steps against the database.
+//! A worksheet is a sequence of statements, each starting at the beginning
+//! of a line and continued on the following indented lines:
+//!
+//! * The header, `$( <MM> <PROOF_ASST> THEOREM=label  LOC_AFTER=label`.
+//! * Comments, starting with `*`.
+//! * Hypothesis steps, `h1::label formula`.
+//! * Derivation steps, `3:1,2:label formula`, where `1,2` refers to the steps
+//!   proving the essential hypotheses of `label`, in order.  The last one is
+//!   named `qed`.  A missing hypothesis is written `?`, and the label and the
+//!   formula of an unfinished step can be left out.
+//! * Disjoint variable conditions, `$d x y`.
+//! * The proof, after `$=`, and the end of the worksheet, `$)`.
+//!
+//! A derivation step is checked by unifying its formula with the assertion
+//! of its label, and the formulas of the steps it refers to with the
+//! essential hypotheses of the label, in a single substitution.  Problems are
+//! reported with the line of the statement where they are found; checking
+//! goes on with the next step.
+
+use crate::diag::Diagnostic;
+use crate::formula::Substitutions;
+use crate::formula::WorkVariable;
+use crate::parser::as_str;
+use crate::parser::Comparer;
+use crate::parser::StatementAddress;
+use crate::parser::StatementRef;
+use crate::parser::StatementType;
+use crate::parser::SymbolType;
+use crate::proof::LineWrap;
+use crate::scopeck::Frame;
+use crate::scopeck::Hyp;
+use crate::util::HashMap;
+use crate::Database;
+use crate::Formula;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A problem found in a proof worksheet.
+#[derive(Debug, Clone)]
+pub enum WorksheetError {
+    /// The step does not have the form `name:hyps:label`.
+    MalformedStep(String),
+    /// A step with the same name appears earlier in the worksheet.
+    DuplicateStep(String),
+    /// The step refers to a step which does not appear before it.
+    UnknownStep(String),
+    /// The formula uses a token which is not a math symbol of the database.
+    UnknownSymbol(String),
+    /// The step applies a label which does not exist.
+    UnknownLabel(String),
+    /// The derivation step applies a label which is not a `$a` or `$p`
+    /// statement.
+    NotAssertion(String),
+    /// The hypothesis step names a statement which is not an essential
+    /// hypothesis of the theorem.
+    NotHypothesis(String),
+    /// The step applies a statement which does not come before the theorem.
+    LabelAfterTheorem(String),
+    /// The formula cannot be parsed with the grammar of the database.
+    Parse(Diagnostic),
+    /// The step refers to a different number of steps than the essential
+    /// hypotheses of its label.
+    HypCount {
+        /// The number of essential hypotheses of the label
+        expected: usize,
+        /// The number of steps referred to
+        found: usize,
+    },
+    /// The formula is not an instance of the assertion of the label.
+    ConclusionMismatch,
+    /// The formula of the step used for the given hypothesis, counted from
+    /// 1, does not match it under the substitution of the step.
+    HypothesisMismatch(usize),
+    /// The formula of the hypothesis step differs from the hypothesis of
+    /// the same name in the database.
+    HypothesisFormula,
+    /// The formula of the `qed` step differs from the assertion of the
+    /// theorem in the database.
+    QedMismatch,
+    /// The step has no label or no formula, or refers to a missing
+    /// hypothesis `?`, and could only be partly checked.
+    Incomplete,
+    /// The `$d` statement uses a token which is not a variable.
+    UnknownVariable(String),
+    /// The worksheet has steps but no `qed` step.
+    MissingQed,
+    /// The theorem of the worksheet is not a `$p` statement of the database,
+    /// so its proof cannot be built.
+    NoTheorem,
+    /// The work variables left in the worksheet cannot all be replaced by
+    /// distinct variables in scope at the theorem.
+    WorkVariables,
+    /// The given variables of the assertion must be disjoint, but their
+    /// substitutions share the third variable.
+    DvSameVariable(String, String, String),
+    /// The given variables must be disjoint, but the theorem has no `$d`
+    /// statement for them.
+    DvViolation(String, String),
+}
+
+impl fmt::Display for WorksheetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WorksheetError::MalformedStep(ref field) => write!(f, "Malformed step {field}"),
+            WorksheetError::DuplicateStep(ref name) => write!(f, "Duplicate step {name}"),
+            WorksheetError::UnknownStep(ref name) => write!(f, "Unknown step {name}"),
+            WorksheetError::UnknownSymbol(ref token) => {
+                write!(f, "Unknown math symbol {token}")
+            }
+            WorksheetError::UnknownLabel(ref label) => write!(f, "Unknown label {label}"),
+            WorksheetError::NotAssertion(ref label) => {
+                write!(f, "{label} is not a $a or $p statement")
+            }
+            WorksheetError::NotHypothesis(ref label) => {
+                write!(f, "{label} is not a hypothesis of the theorem")
+            }
+            WorksheetError::LabelAfterTheorem(ref label) => {
+                write!(f, "{label} does not come before the theorem")
+            }
+            WorksheetError::Parse(ref diag) => write!(f, "Formula does not parse: {diag:?}"),
+            WorksheetError::HypCount { expected, found } => {
+                write!(f, "Expected {expected} hypotheses, found {found}")
+            }
+            WorksheetError::ConclusionMismatch => {
+                write!(f, "The formula does not match the assertion of the label")
+            }
+            WorksheetError::HypothesisMismatch(index) => {
+                write!(f, "Hypothesis {index} does not match the step it refers to")
+            }
+            WorksheetError::HypothesisFormula => {
+                write!(f, "The formula differs from the hypothesis in the database")
+            }
+            WorksheetError::QedMismatch => {
+                write!(f, "The formula differs from the assertion of the theorem")
+            }
+            WorksheetError::Incomplete => write!(f, "Incomplete step"),
+            WorksheetError::UnknownVariable(ref token) => write!(f, "Unknown variable {token}"),
+            WorksheetError::MissingQed => write!(f, "No qed step"),
+            WorksheetError::NoTheorem => {
+                write!(f, "The theorem is not a $p statement of the database")
+            }
+            WorksheetError::WorkVariables => {
+                write!(
+                    f,
+                    "Not enough variables in scope to replace the work variables"
+                )
+            }
+            WorksheetError::DvSameVariable(ref var1, ref var2, ref variable) => write!(
+                f,
+                "Variables {var1} and {var2} must be disjoint, but both contain {variable}"
+            ),
+            WorksheetError::DvViolation(ref var1, ref var2) => {
+                write!(f, "Variables {var1} and {var2} must be disjoint")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorksheetError {}
+
+/// A hypothesis or derivation step of a proof worksheet.
+#[derive(Debug, Clone, Default)]
+pub struct WorksheetStep {
+    /// The line of the worksheet where the step starts, counted from 1.
+    pub line: usize,
+    /// Whether this is a hypothesis step, whose name starts with `h`.
+    pub hyp: bool,
+    /// The name of the step, without the `h` of hypothesis steps.
+    pub name: String,
+    /// The names of the steps used for the essential hypotheses of the
+    /// label, or `None` for a missing one, written `?`.
+    pub hyps: Vec<Option<String>>,
+    /// The label of the assertion applied, or of the hypothesis.
+    pub label: Option<String>,
+    /// The tokens of the formula, starting with its typecode, or empty if the
+    /// formula is left out.
+    pub math: Vec<String>,
+    /// The parsed formula, if it could be parsed.
+    pub formula: Option<Formula>,
+}
+
+/// A proof worksheet, as read by [`Database::import_mmp`].
+#[derive(Debug, Clone, Default)]
+pub struct Worksheet {
+    /// The label of the theorem, from the `THEOREM=` field of the header.
+    pub theorem: Option<String>,
+    /// The label of the statement after which the theorem goes, from the
+    /// `LOC_AFTER=` field of the header, unless it is `?`.
+    pub loc_after: Option<String>,
+    /// The text of each comment, without the `*`.
+    pub comments: Vec<String>,
+    /// The hypothesis and derivation steps, in order.
+    pub steps: Vec<WorksheetStep>,
+    /// The variables of each `$d` statement.
+    pub dvs: Vec<Vec<String>>,
+    /// The tokens of the proof after `$=`, up to the closing `$.`.
+    pub proof: Vec<String>,
+    /// The problems found, with the line of the statement where they are
+    /// found, counted from 1.
+    pub diagnostics: Vec<(usize, WorksheetError)>,
+}
+
+/// Writes the worksheet in the format read by [`Database::import_mmp`], with
+/// the comments after the header.
+impl fmt::Display for Worksheet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "$( <MM> <PROOF_ASST> THEOREM={}  LOC_AFTER={}",
+            self.theorem.as_deref().unwrap_or("?"),
+            self.loc_after.as_deref().unwrap_or("?")
+        )?;
+        for comment in &self.comments {
+            writeln!(f, "\n* {comment}")?;
+        }
+        writeln!(f)?;
+        for step in &self.steps {
+            let hyps: Vec<&str> = step
+                .hyps
+                .iter()
+                .map(|hyp| hyp.as_deref().unwrap_or("?"))
+                .collect();
+            let line = format!(
+                "{}{}:{}:{} {}",
+                if step.hyp { "h" } else { "" },
+                step.name,
+                hyps.join(","),
+                step.label.as_deref().unwrap_or_default(),
+                step.math.join(" ")
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        if !self.dvs.is_empty() {
+            writeln!(f)?;
+            for dv in &self.dvs {
+                writeln!(f, "$d {}", dv.join(" "))?;
+            }
+        }
+        if !self.proof.is_empty() {
+            write!(f, "\n$=")?;
+            let mut out = LineWrap::new(f, 2, 6, 79);
+            for (index, token) in self.proof.iter().enumerate() {
+                out.write_word_gap(token, if index == 0 { 4 } else { 1 })?;
+            }
+            out.write_word("$.")?;
+            writeln!(f)?;
+        }
+        writeln!(f, "\n$)")
+    }
+}
+
+impl Worksheet {
+    /// Returns `true` if a problem other than an incomplete step was found.
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|(_, err)| !matches!(err, WorksheetError::Incomplete))
+    }
+
+    /// Returns the step with the given name.
+    #[must_use]
+    pub fn step(&self, name: &str) -> Option<&WorksheetStep> {
+        self.steps.iter().find(|step| step.name == name)
+    }
+
+    /// Splits the worksheet text into statements, each with the line where it
+    /// starts.
+    fn statements(text: &str) -> Vec<(usize, String)> {
+        let mut out: Vec<(usize, String)> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            match out.last_mut() {
+                Some((_, statement))
+                    if line.is_empty() || line.starts_with(char::is_whitespace) =>
+                {
+                    statement.push('\n');
+                    statement.push_str(line);
+                }
+                _ if line.is_empty() => {}
+                _ => out.push((index + 1, line.to_owned())),
+            }
+        }
+        out
+    }
+
+    /// Parses the text of a worksheet, without checking it.
+    fn parse(text: &str) -> Self {
+        let mut worksheet = Worksheet::default();
+        for (line, statement) in Self::statements(text) {
+            let mut tokens = statement.split_whitespace();
+            let first = tokens.next().unwrap_or_default();
+            if first == "$(" {
+                for token in tokens {
+                    if let Some(label) = token.strip_prefix("THEOREM=") {
+                        worksheet.theorem = Some(label.to_owned());
+                    } else if let Some(label) = token.strip_prefix("LOC_AFTER=") {
+                        worksheet.loc_after = Some(label.to_owned()).filter(|label| label != "?");
+                    }
+                }
+            } else if let Some(comment) = statement.strip_prefix('*') {
+                worksheet.comments.push(comment.trim().to_owned());
+            } else if first == "$d" {
+                worksheet.dvs.push(tokens.map(str::to_owned).collect());
+            } else if first == "$=" {
+                worksheet.proof = tokens
+                    .take_while(|&token| token != "$.")
+                    .map(str::to_owned)
+                    .collect();
+            } else if first == "$)" {
+                break;
+            } else {
+                let fields: Vec<&str> = first.split(':').collect();
+                match *fields {
+                    [name, hyps, label] => {
+                        let (hyp, name) = match name.strip_prefix('h') {
+                            Some(name) => (true, name),
+                            None => (false, name),
+                        };
+                        if hyp && !hyps.is_empty() {
+                            worksheet
+                                .diagnostics
+                                .push((line, WorksheetError::MalformedStep(first.to_owned())));
+                            continue;
+                        }
+                        worksheet.steps.push(WorksheetStep {
+                            line,
+                            hyp,
+                            name: name.to_owned(),
+                            hyps: hyps
+                                .split(',')
+                                .filter(|hyp| !hyp.is_empty())
+                                .map(|hyp| Some(hyp.to_owned()).filter(|hyp| hyp != "?"))
+                                .collect(),
+                            label: Some(label.to_owned()).filter(|label| !label.is_empty()),
+                            math: tokens.map(str::to_owned).collect(),
+                            formula: None,
+                        });
+                    }
+                    _ => worksheet
+                        .diagnostics
+                        .push((line, WorksheetError::MalformedStep(first.to_owned()))),
+                }
+            }
+        }
+        worksheet
+    }
+}
+
+impl Database {
+    /// Reads an mmj2 proof worksheet, and checks each of its steps against
+    /// the database.  See the [module documentation](crate::worksheet) for
+    /// the format and the checks made.
+    ///
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`],
+    /// [`Database::grammar_pass`], [`Database::stmt_parse_pass`]
+    #[must_use]
+    pub fn import_mmp(&self, text: &str) -> Worksheet {
+        let mut worksheet = Worksheet::parse(text);
+        let nset = self.name_result();
+        for (line, statement) in Worksheet::statements(text) {
+            if statement.starts_with("$d") {
+                for token in statement.split_whitespace().skip(1) {
+                    let stype = nset
+                        .lookup_symbol(token.as_bytes())
+                        .map(|symbol| symbol.stype);
+                    if stype != Some(SymbolType::Variable) {
+                        let err = WorksheetError::UnknownVariable(token.to_owned());
+                        worksheet.diagnostics.push((line, err));
+                    }
+                }
+            }
+        }
+        self.check_worksheet(&mut worksheet);
+        worksheet
+    }
+
+    /// Parses the formulas of the steps of a worksheet, and checks the steps
+    /// again, replacing the problems previously found in them.
+    ///
+    /// Requires: [`Database::name_pass`], [`Database::scope_pass`],
+    /// [`Database::grammar_pass`], [`Database::stmt_parse_pass`]
+    pub fn check_worksheet(&self, worksheet: &mut Worksheet) {
+        let step_lines: Vec<usize> = worksheet.steps.iter().map(|step| step.line).collect();
+        worksheet
+            .diagnostics
+            .retain(|(line, _)| !step_lines.contains(line));
+        let mut diagnostics = Vec::new();
+        let theorem = self.worksheet_theorem(worksheet);
+        let limit = self.worksheet_limit(worksheet);
+
+        let mut names: HashMap<String, usize> = HashMap::default();
+        for index in 0..worksheet.steps.len() {
+            let step = &worksheet.steps[index];
+            let line = step.line;
+            // references go to the first step of a given name
+            if names.contains_key(&step.name) {
+                diagnostics.push((line, WorksheetError::DuplicateStep(step.name.clone())));
+            } else {
+                names.insert(step.name.clone(), index);
+            }
+            let formula = match self.parse_step_formula(&step.math) {
+                Ok(formula) => formula,
+                Err(err) => {
+                    diagnostics.push((line, err));
+                    worksheet.steps[index].formula = None;
+                    continue;
+                }
+            };
+            let result = if step.hyp {
+                self.check_hyp_step(step, formula.as_ref(), theorem)
+            } else {
+                let target = theorem.and_then(|theorem| self.scope_result().get(theorem.label()));
+                self.check_derivation_step(
+                    &worksheet.steps,
+                    &names,
+                    step,
+                    formula.as_ref(),
+                    limit,
+                    target,
+                )
+            };
+            if let Err(err) = result {
+                diagnostics.push((line, err));
+            }
+            if step.name == "qed" {
+                let expected = theorem.and_then(|sref| self.stmt_parse_result().get_formula(&sref));
+                if let (Some(formula), Some(expected)) = (&formula, expected) {
+                    if formula != expected {
+                        diagnostics.push((line, WorksheetError::QedMismatch));
+                    }
+                }
+            }
+            worksheet.steps[index].formula = formula;
+        }
+        if !worksheet.steps.is_empty() && !names.contains_key("qed") {
+            let line = worksheet.steps.last().unwrap().line;
+            diagnostics.push((line, WorksheetError::MissingQed));
+        }
+
+        worksheet.diagnostics.append(&mut diagnostics);
+        worksheet.diagnostics.sort_by_key(|&(line, _)| line);
+    }
+
+    /// Returns the theorem of a worksheet, if it is a `$p` statement of the
+    /// database.
+    pub(crate) fn worksheet_theorem(&self, worksheet: &Worksheet) -> Option<StatementRef<'_>> {
+        worksheet
+            .theorem
+            .as_ref()
+            .and_then(|label| self.statement(label))
+            .filter(|sref| sref.statement_type() == StatementType::Provable)
+    }
+
+    /// Returns the last statement which the steps of a worksheet can apply,
+    /// and the ordering it must have with them: the statements before the
+    /// theorem, or up to and including the `LOC_AFTER` statement of a new
+    /// theorem, can be used.
+    pub(crate) fn worksheet_limit(
+        &self,
+        worksheet: &Worksheet,
+    ) -> Option<(StatementAddress, Ordering)> {
+        match self.worksheet_theorem(worksheet) {
+            Some(sref) => Some((sref.address(), Ordering::Less)),
+            None => worksheet
+                .loc_after
+                .as_ref()
+                .and_then(|label| self.statement(label))
+                .map(|sref| (sref.address(), Ordering::Equal)),
+        }
+    }
+
+    /// Parses the formula of a step, if it has one.
+    fn parse_step_formula(&self, math: &[String]) -> Result<Option<Formula>, WorksheetError> {
+        let nset = self.name_result();
+        let mut symbols = Vec::with_capacity(math.len());
+        for token in math {
+            let symbol = nset
+                .lookup_symbol(token.as_bytes())
+                .map(|symbol| symbol.atom)
+                .or_else(|| WorkVariable::from_name(self, token).map(WorkVariable::atom))
+                .ok_or_else(|| WorksheetError::UnknownSymbol(token.clone()))?;
+            symbols.push(symbol);
+        }
+        if let Some((&typecode, symbols)) = symbols.split_first() {
+            self.grammar_result()
+                .parse_math_string(typecode, &mut symbols.iter().copied(), nset)
+                .map(Some)
+                .map_err(WorksheetError::Parse)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Checks a hypothesis step against the hypothesis of the same label in
+    /// the database, which must be an essential hypothesis of the theorem, if
+    /// the theorem is in the database.
+    fn check_hyp_step(
+        &self,
+        step: &WorksheetStep,
+        formula: Option<&Formula>,
+        theorem: Option<StatementRef<'_>>,
+    ) -> Result<(), WorksheetError> {
+        let label = match step.label {
+            Some(ref label) if theorem.is_some() => label,
+            _ => return Ok(()),
+        };
+        let frame = theorem.and_then(|theorem| self.scope_result().get(theorem.label()));
+        let sref = self
+            .statement(label)
+            .filter(|sref| {
+                frame.is_some_and(|frame| {
+                    frame.hypotheses.iter().any(|hyp| {
+                        matches!(*hyp, Hyp::Essential(address, _) if address == sref.address())
+                    })
+                })
+            })
+            .ok_or_else(|| WorksheetError::NotHypothesis(label.clone()))?;
+        match (formula, self.stmt_parse_result().get_formula(&sref)) {
+            (Some(formula), Some(expected)) if formula != expected => {
+                Err(WorksheetError::HypothesisFormula)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a derivation step, by unifying its formula and the formulas of
+    /// the steps it refers to with the assertion and the essential hypotheses
+    /// of its label.
+    fn check_derivation_step(
+        &self,
+        steps: &[WorksheetStep],
+        names: &HashMap<String, usize>,
+        step: &WorksheetStep,
+        formula: Option<&Formula>,
+        limit: Option<(StatementAddress, Ordering)>,
+        target: Option<&Frame>,
+    ) -> Result<(), WorksheetError> {
+        // the steps used for the hypotheses, or `None` for missing ones
+        let mut hyps = Vec::with_capacity(step.hyps.len());
+        for name in &step.hyps {
+            hyps.push(match name {
+                Some(name) => Some(
+                    names
+                        .get(name)
+                        .map(|&index| &steps[index])
+                        .filter(|hyp| hyp.line < step.line)
+                        .ok_or_else(|| WorksheetError::UnknownStep(name.clone()))?,
+                ),
+                None => None,
+            });
+        }
+        if let (Some(label), Some(formula)) = (&step.label, formula) {
+            self.check_assertion(label, formula, &step.math[0], &hyps, limit, target)
+        } else {
+            Err(WorksheetError::Incomplete)
+        }
+    }
+
+    /// Checks a complete derivation step, applying `label` to the steps
+    /// `hyps` to prove `formula`, of typecode `typecode`, in the theorem with
+    /// the frame `target`.
+    fn check_assertion(
+        &self,
+        label: &str,
+        formula: &Formula,
+        typecode: &str,
+        hyps: &[Option<&WorksheetStep>],
+        limit: Option<(StatementAddress, Ordering)>,
+        target: Option<&Frame>,
+    ) -> Result<(), WorksheetError> {
+        let sref = self
+            .statement(label)
+            .ok_or_else(|| WorksheetError::UnknownLabel(label.to_owned()))?;
+        if !matches!(
+            sref.statement_type(),
+            StatementType::Axiom | StatementType::Provable
+        ) {
+            return Err(WorksheetError::NotAssertion(label.to_owned()));
+        }
+        if let Some((address, ordering)) = limit {
+            if self.parse_result().order.cmp(&sref.address(), &address) > ordering {
+                return Err(WorksheetError::LabelAfterTheorem(label.to_owned()));
+            }
+        }
+        let frame = self
+            .scope_result()
+            .get(sref.label())
+            .ok_or_else(|| WorksheetError::UnknownLabel(label.to_owned()))?;
+        let essentials: Vec<StatementAddress> = frame
+            .hypotheses
+            .iter()
+            .filter_map(|hyp| match *hyp {
+                Hyp::Essential(address, _) => Some(address),
+                Hyp::Floating(..) => None,
+            })
+            .collect();
+        if essentials.len() != hyps.len() {
+            return Err(WorksheetError::HypCount {
+                expected: essentials.len(),
+                found: hyps.len(),
+            });
+        }
+
+        let stmt_parse = self.stmt_parse_result();
+        let sset = self.parse_result();
+        let mut substitutions = Substitutions::default();
+        // syntax axioms are not parsed, and cannot be checked
+        if let Some(assertion) = stmt_parse.get_formula(&sref) {
+            if typecode.as_bytes() != sref.math_at(0).slice
+                || !formula.unify_with(assertion, &mut substitutions)
+            {
+                return Err(WorksheetError::ConclusionMismatch);
+            }
+        } else {
+            return Ok(());
+        }
+        let mut complete = true;
+        for (index, (&address, hyp)) in essentials.iter().zip(hyps).enumerate() {
+            let essential = stmt_parse.get_formula(&sset.statement(address));
+            match (hyp.and_then(|hyp| hyp.formula.as_ref()), essential) {
+                (None, _) => complete = false,
+                (Some(hyp_formula), Some(essential)) => {
+                    if !hyp_formula.unify_with(essential, &mut substitutions) {
+                        return Err(WorksheetError::HypothesisMismatch(index + 1));
+                    }
+                }
+                (Some(_), None) => {}
+            }
+        }
+        if complete {
+            self.check_step_dv(frame, &substitutions, target)
+        } else {
+            Err(WorksheetError::Incomplete)
+        }
+    }
+
+    /// Checks the `$d` conditions of a step applying the assertion with frame
+    /// `frame` under `substitutions`.  Conditions missing from the theorem
+    /// are only reported if its frame `target` is known.
+    pub(crate) fn check_step_dv(
+        &self,
+        frame: &Frame,
+        substitutions: &Substitutions,
+        target: Option<&Frame>,
+    ) -> Result<(), WorksheetError> {
+        let report = self.check_dv(frame, substitutions, target.unwrap_or(frame));
+        let nset = self.name_result();
+        let name = |symbol| as_str(nset.atom_name(symbol)).to_owned();
+        if let Some(violation) = report.violations.first() {
+            let (var1, var2) = violation.mandatory;
+            return Err(WorksheetError::DvSameVariable(
+                name(var1),
+                name(var2),
+                name(violation.variable),
+            ));
+        }
+        match report.missing.first() {
+            Some(&(var1, var2)) if target.is_some() => {
+                Err(WorksheetError::DvViolation(name(var1), name(var2)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
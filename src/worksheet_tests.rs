@@ -0,0 +1,162 @@
+use crate::grammar_tests::mkdb;
+use crate::Database;
+
+pub(super) const WORKSHEET_DB: &[u8] = b"
+    $c |- wff ( ) -> $.
+    $( $j syntax 'wff'; syntax '|-' as 'wff'; $)
+    $v ph ps ch $.
+    wph $f wff ph $.
+    wps $f wff ps $.
+    wch $f wff ch $.
+    wi $a wff ( ph -> ps ) $.
+    ${
+        min $e |- ph $.
+        maj $e |- ( ph -> ps ) $.
+        ax-mp $a |- ps $.
+    $}
+    ax-1 $a |- ( ph -> ( ps -> ph ) ) $.
+    ${
+        a1i.1 $e |- ph $.
+        a1i $p |- ( ps -> ph ) $= wph wps wph wi a1i.1 wph wps ax-1 ax-mp $.
+    $}
+";
+
+pub(super) fn worksheet_db() -> Database {
+    let mut db = mkdb(WORKSHEET_DB);
+    db.scope_pass();
+    db.stmt_parse_pass();
+    db
+}
+
+fn diagnostics(db: &Database, text: &str) -> Vec<String> {
+    db.import_mmp(text)
+        .diagnostics
+        .iter()
+        .map(|(line, err)| format!("{line}: {err}"))
+        .collect()
+}
+
+#[test]
+fn test_import_exported_mmp() {
+    let db = worksheet_db();
+    let mut out = Vec::new();
+    db.export_mmp(db.statement("a1i").unwrap(), &mut out)
+        .unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let worksheet = db.import_mmp(&text);
+    assert!(worksheet.diagnostics.is_empty());
+    assert!(!worksheet.has_errors());
+    assert_eq!(worksheet.theorem.as_deref(), Some("a1i"));
+    assert_eq!(worksheet.loc_after.as_deref(), Some("ax-1"));
+    assert_eq!(worksheet.steps.len(), 3);
+    assert!(worksheet.steps[0].hyp);
+    let qed = worksheet.step("qed").unwrap();
+    assert_eq!(qed.label.as_deref(), Some("ax-mp"));
+    assert_eq!(qed.hyps, vec![Some("1".to_owned()), Some("2".to_owned())]);
+    assert!(qed.formula.is_some());
+    assert!(!worksheet.proof.is_empty());
+}
+
+#[test]
+fn test_import_mmp_diagnostics() {
+    let db = worksheet_db();
+    let text = "$( <MM> <PROOF_ASST> THEOREM=new  LOC_AFTER=ax-1
+
+* A comment
+  on two lines.
+
+h1::hyp |- ch
+2::ax-1 |- ( ch -> ( ph -> ch ) )
+3:1,2:ax-mp |- ( ph -> ps )
+4:1,2:ax-mp
+    |- ( ph -> ch )
+5:1:ax-mp |- ch
+6::a1i |- ch
+7::nope |- ch
+8::ax-1 |- ( ph -> foo )
+9:1,?:ax-mp |- ch
+10::ax-1 |- ( ph ->
+2::ax-1 |- ( ph -> ( ps -> ph ) )
+bad |- ch
+11:12:ax-mp |- ch
+qed:1,2:ax-mp |- ( ph -> ch )
+
+$d ph zz
+
+$)
+";
+    let worksheet = db.import_mmp(text);
+    assert_eq!(worksheet.comments, vec!["A comment\n  on two lines."]);
+    assert_eq!(worksheet.dvs, vec![vec!["ph", "zz"]]);
+    assert_eq!(
+        worksheet.step("4").unwrap().math,
+        vec!["|-", "(", "ph", "->", "ch", ")"]
+    );
+    assert!(worksheet.has_errors());
+    let diags = diagnostics(&db, text);
+    assert_eq!(diags.len(), 11);
+    assert_eq!(
+        diags[..6],
+        [
+            "8: Hypothesis 2 does not match the step it refers to",
+            "11: Expected 2 hypotheses, found 1",
+            "12: a1i does not come before the theorem",
+            "13: Unknown label nope",
+            "14: Unknown math symbol foo",
+            "15: Incomplete step",
+        ]
+    );
+    assert_eq!(
+        diags[7..],
+        [
+            "17: Duplicate step 2",
+            "18: Malformed step bad",
+            "19: Unknown step 12",
+            "22: Unknown variable zz",
+        ]
+    );
+    assert!(diags[6].starts_with("16: Formula does not parse"));
+
+    // trailing symbols are reported, and do not panic
+    let diags = diagnostics(&db, "qed::ax-mp |- ph ps\n");
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].starts_with("1: Formula does not parse"));
+}
+
+#[test]
+fn test_import_mmp_theorem() {
+    let db = worksheet_db();
+    let text = "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::a1i.1 |- ps
+h2::ax-1 |- ph
+3::a1i |- ( ph -> ( ps -> ph ) )
+4::ax-1 |- ( ph -> ( ps -> ph ) )
+qed:1,4:ax-mp |- ( ph -> ps )
+";
+    assert_eq!(
+        diagnostics(&db, text),
+        [
+            "3: The formula differs from the hypothesis in the database",
+            "4: ax-1 is not a hypothesis of the theorem",
+            "5: a1i does not come before the theorem",
+            "7: Hypothesis 2 does not match the step it refers to",
+            "7: The formula differs from the assertion of the theorem",
+        ]
+    );
+    // `min` is a hypothesis of `ax-mp`, not of `a1i`
+    let text = "$( <MM> <PROOF_ASST> THEOREM=a1i  LOC_AFTER=?
+
+h1::min |- ph
+2::ax-1 |- ( ph -> ( ps -> ph ) )
+qed:1,2:ax-mp |- ( ps -> ph )
+";
+    assert_eq!(
+        diagnostics(&db, text),
+        ["3: min is not a hypothesis of the theorem"]
+    );
+    assert_eq!(
+        diagnostics(&db, "1::ax-1\n"),
+        ["1: Incomplete step", "1: No qed step"]
+    );
+}
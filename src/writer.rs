@@ -0,0 +1,302 @@
+//! Writing edited databases back to their source files.
+//!
+//! The text of each source file is regenerated by concatenating the buffers of
+//! its segments in database order.  The parser keeps every byte of its input,
+//! including white space and comments, and the editing methods in the `edit`
+//! module splice their changes into those buffers, so the regenerated text is
+//! identical to the loaded text outside of the edited regions.  Files included
+//! with `$[ $]` keep their own content; the inclusion statements themselves
+//! are part of the including file.
+//!
+//! `Database::write_back` compares the regenerated text with the files on disk
+//! and rewrites the ones which differ.  In dry-run mode nothing is written, and
+//! the returned changes can be printed with `FileChange::unified_diff`.
+//! Sources given as text to `Database::parse` have no file: their changes are
+//! reported against the text given, and never written.
+
+use crate::database::Database;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Number of unchanged lines shown around each change in a diff.
+const CONTEXT: usize = 3;
+
+/// Edit distance beyond which the diff gives up looking for common lines in
+/// the changed part of a file.
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+/// A source file whose regenerated text differs from the file on disk.
+#[derive(Clone, Debug)]
+pub struct FileChange {
+    /// Name of the file, as it was loaded.
+    pub name: String,
+    /// Content of the file on disk, or `None` if it does not exist.
+    /// For a source given as text, the text as it was given.
+    pub old: Option<Vec<u8>>,
+    /// Regenerated content of the file.
+    pub new: Vec<u8>,
+    /// Whether the source was given as text to [`Database::parse`] instead of
+    /// being read from disk, in which case it has no file to write.
+    pub in_memory: bool,
+}
+
+impl FileChange {
+    /// Formats the change as a unified diff, with three lines of context
+    /// around each change.  The file name is used as is in the headers, so
+    /// the diff applies with `patch -p0`.
+    #[must_use]
+    pub fn unified_diff(&self) -> String {
+        let old_lines: Vec<&[u8]> = self
+            .old
+            .as_deref()
+            .unwrap_or_default()
+            .split_inclusive(|&ch| ch == b'\n')
+            .collect();
+        let new_lines: Vec<&[u8]> = self.new.split_inclusive(|&ch| ch == b'\n').collect();
+        let ops = diff_lines(&old_lines, &new_lines);
+
+        let mut out = String::new();
+        let old_name = if self.old.is_some() {
+            &self.name
+        } else {
+            "/dev/null"
+        };
+        let _ = writeln!(out, "--- {old_name}");
+        let _ = writeln!(out, "+++ {}", self.name);
+        for (start, end) in hunks(&ops) {
+            let (old_before, new_before) = ops[..start].iter().fold((0, 0), count_lines);
+            let (old_count, new_count) = ops[start..end].iter().fold((0, 0), count_lines);
+            let _ = writeln!(
+                out,
+                "@@ -{},{} +{},{} @@",
+                old_before + usize::from(old_count > 0),
+                old_count,
+                new_before + usize::from(new_count > 0),
+                new_count
+            );
+            for op in &ops[start..end] {
+                let (prefix, line) = match *op {
+                    DiffOp::Equal(index, _) => (' ', old_lines[index]),
+                    DiffOp::Delete(index) => ('-', old_lines[index]),
+                    DiffOp::Insert(index) => ('+', new_lines[index]),
+                };
+                out.push(prefix);
+                out.push_str(&String::from_utf8_lossy(line));
+                if !line.ends_with(b"\n") {
+                    out.push_str("\n\\ No newline at end of file\n");
+                }
+            }
+        }
+        out
+    }
+
+    /// Writes the new content to the file, through a temporary file which is
+    /// then renamed over the original.
+    pub fn write(&self) -> io::Result<()> {
+        let temp = [self.name.as_str(), ".tmp"].concat();
+        fs::write(&temp, &self.new)?;
+        fs::rename(&temp, &self.name)
+    }
+}
+
+/// One line of an edit script, with line indices into the old and new texts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Accumulates the number of old and new lines covered by diff operations.
+const fn count_lines((old, new): (usize, usize), op: &DiffOp) -> (usize, usize) {
+    match *op {
+        DiffOp::Equal(..) => (old + 1, new + 1),
+        DiffOp::Delete(_) => (old + 1, new),
+        DiffOp::Insert(_) => (old, new + 1),
+    }
+}
+
+/// Computes a line diff, using Myers' algorithm on the part between the
+/// common prefix and suffix.
+fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<DiffOp> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut ops: Vec<DiffOp> = (0..prefix)
+        .map(|index| DiffOp::Equal(index, index))
+        .collect();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    for op in myers(old_mid, new_mid) {
+        ops.push(match op {
+            DiffOp::Equal(i, j) => DiffOp::Equal(prefix + i, prefix + j),
+            DiffOp::Delete(i) => DiffOp::Delete(prefix + i),
+            DiffOp::Insert(j) => DiffOp::Insert(prefix + j),
+        });
+    }
+    let old_end = old.len() - suffix;
+    let new_end = new.len() - suffix;
+    ops.extend((0..suffix).map(|index| DiffOp::Equal(old_end + index, new_end + index)));
+    ops
+}
+
+/// Finds a shortest edit script, or replaces everything if it would be longer
+/// than `MAX_EDIT_DISTANCE`.
+fn myers(old: &[&[u8]], new: &[&[u8]]) -> Vec<DiffOp> {
+    let old_len = old.len() as isize;
+    let new_len = new.len() as isize;
+    let limit = (old.len() + new.len()).min(MAX_EDIT_DISTANCE) as isize;
+    // furthest x reached on each diagonal k = x - y, offset by `limit + 1`
+    let offset = limit + 1;
+    let mut frontier = vec![0_isize; 2 * limit as usize + 3];
+    // for each d, the part of `frontier` for diagonals -d..=d after step d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let at = |frontier: &[isize], k: isize| frontier[(k + offset) as usize];
+    let same = |x: isize, y: isize| old[x as usize] == new[y as usize];
+
+    for d in 0..=limit {
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && at(&frontier, k - 1) < at(&frontier, k + 1)) {
+                at(&frontier, k + 1)
+            } else {
+                at(&frontier, k - 1) + 1
+            };
+            let mut y = x - k;
+            while x < old_len && y < new_len && same(x, y) {
+                x += 1;
+                y += 1;
+            }
+            frontier[(k + offset) as usize] = x;
+            if x >= old_len && y >= new_len {
+                trace.push(frontier[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                return backtrack(&trace, old, new);
+            }
+            k += 2;
+        }
+        trace.push(frontier[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    (0..old.len())
+        .map(DiffOp::Delete)
+        .chain((0..new.len()).map(DiffOp::Insert))
+        .collect()
+}
+
+/// Recovers the edit script from the saved Myers frontiers.
+fn backtrack(trace: &[Vec<isize>], old: &[&[u8]], new: &[&[u8]]) -> Vec<DiffOp> {
+    let at = |d: isize, k: isize| trace[d as usize][(k + d) as usize];
+    let mut ops = Vec::new();
+    let mut x = old.len() as isize;
+    let mut y = new.len() as isize;
+    for d in (1..trace.len() as isize).rev() {
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(d - 1, k - 1) < at(d - 1, k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(d - 1, prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Equal(x as usize, y as usize));
+        }
+        if prev_k == k + 1 {
+            ops.push(DiffOp::Insert(prev_y as usize));
+        } else {
+            ops.push(DiffOp::Delete(prev_x as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        ops.push(DiffOp::Equal(x as usize, y as usize));
+    }
+    ops.reverse();
+    ops
+}
+
+/// Groups the changes of an edit script into hunks with context, as ranges of
+/// operations.
+fn hunks(ops: &[DiffOp]) -> Vec<(usize, usize)> {
+    let is_change = |op: &DiffOp| !matches!(op, DiffOp::Equal(..));
+    let mut out: Vec<(usize, usize)> = Vec::new();
+    let mut index = 0;
+    while let Some(offset) = ops[index..].iter().position(is_change) {
+        let change = index + offset;
+        let mut end = change;
+        while end < ops.len() && is_change(&ops[end]) {
+            end += 1;
+        }
+        let start = change.saturating_sub(CONTEXT);
+        let hunk_end = (end + CONTEXT).min(ops.len());
+        match out.last_mut() {
+            // the context of the previous hunk reaches this one
+            Some(last) if start <= last.1 => last.1 = hunk_end,
+            _ => out.push((start, hunk_end)),
+        }
+        index = end;
+    }
+    out
+}
+
+/// Reads a file, treating a missing file as `None`.
+fn read_existing(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+impl Database {
+    /// Regenerates the text of each loaded source file from its segments,
+    /// including any edits, in order of first inclusion.  Files which could
+    /// not be read are left out.
+    #[must_use]
+    pub fn source_files(&self) -> Vec<(String, Vec<u8>)> {
+        self.parse_result().source_texts()
+    }
+
+    /// Writes every source file whose regenerated text differs from the file
+    /// on disk, and returns the changes.
+    ///
+    /// Files are written under the name they were loaded with, using
+    /// [`FileChange::write`].  If `dry_run` is set, nothing is written.
+    /// Sources given as text to [`Database::parse`] are compared with the
+    /// text given, and their changes are returned but never written.
+    pub fn write_back(&self, dry_run: bool) -> io::Result<Vec<FileChange>> {
+        let mut changes = Vec::new();
+        for (name, new) in self.source_files() {
+            let preloaded = self.parse_result().preloaded_text(&name);
+            let old = match preloaded {
+                Some(text) => Some(text.to_vec()),
+                None => read_existing(Path::new(&name))?,
+            };
+            if old.as_ref() == Some(&new) {
+                continue;
+            }
+            let change = FileChange {
+                name,
+                old,
+                new,
+                in_memory: preloaded.is_some(),
+            };
+            if !dry_run && !change.in_memory {
+                change.write()?;
+            }
+            changes.push(change);
+        }
+        Ok(changes)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::database::Database;
+use crate::database::DbOptions;
+use crate::writer::FileChange;
+use std::env;
+use std::fs;
+use std::process;
+
+#[test]
+fn test_write_back() {
+    let dir = env::temp_dir().join(format!("knife-write-back-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let main_path = dir.join("main.mm");
+    let inc_path = dir.join("inc.mm");
+    let inc_name = inc_path.to_str().unwrap().to_owned();
+    let main_text = format!(
+        "$( header $)\n$c |- wff $.\n$v ph $.\nwph $f wff ph $.\n$[ {inc_name} $]\n\n  ax-1 $a |- ph $.\n  $( trailing $)\n"
+    );
+    fs::write(&main_path, &main_text).unwrap();
+    fs::write(&inc_path, "ax-2 $a wff ph $.\n").unwrap();
+
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    db.parse(main_path.to_str().unwrap().to_owned(), Vec::new());
+    assert!(db.write_back(true).unwrap().is_empty());
+
+    db.insert_statement("ax-1", "th1 $p |- ph $= ax-1 $.")
+        .unwrap();
+    let changes = db.write_back(true).unwrap();
+    assert_eq!(changes.len(), 1);
+    let expected = main_text.replace(
+        "ax-1 $a |- ph $.\n",
+        "ax-1 $a |- ph $.\n  th1 $p |- ph $= ax-1 $.\n",
+    );
+    assert_eq!(changes[0].new, expected.as_bytes());
+    let hunk = format!(
+        "@@ -5,4 +5,5 @@\n $[ {inc_name} $]\n \n   ax-1 $a |- ph $.\n+  th1 $p |- ph $= ax-1 $.\n   $( trailing $)\n"
+    );
+    assert!(changes[0].unified_diff().ends_with(&hunk));
+    assert_eq!(fs::read_to_string(&main_path).unwrap(), main_text);
+
+    assert_eq!(db.write_back(false).unwrap().len(), 1);
+    assert_eq!(fs::read_to_string(&main_path).unwrap(), expected);
+    assert_eq!(
+        fs::read_to_string(&inc_path).unwrap(),
+        "ax-2 $a wff ph $.\n"
+    );
+    assert!(db.write_back(false).unwrap().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_back_in_memory() {
+    let dir = env::temp_dir().join(format!("knife-write-back-mem-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mem.mm");
+    let name = path.to_str().unwrap().to_owned();
+    let text = "$c |- wff $.\n$v ph $.\nwph $f wff ph $.\nax-1 $a |- ph $.\n";
+    let mut db = Database::new(DbOptions {
+        incremental: true,
+        ..DbOptions::default()
+    });
+    db.parse(name.clone(), vec![(name, text.as_bytes().to_vec())]);
+    assert!(db.write_back(false).unwrap().is_empty());
+
+    db.insert_statement("ax-1", "th1 $p |- ph $= ax-1 $.")
+        .unwrap();
+    let changes = db.write_back(false).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(changes[0].in_memory);
+    assert_eq!(changes[0].old.as_deref(), Some(text.as_bytes()));
+    assert!(changes[0]
+        .unified_diff()
+        .ends_with("+th1 $p |- ph $= ax-1 $.\n"));
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unified_diff() {
+    let old: String = (1..=20)
+        .map(|line| ["line ", &line.to_string(), "\n"].concat())
+        .collect();
+    let new = old
+        .replace("line 2\n", "line two\n")
+        .replace("line 4\n", "")
+        .replace("line 18\n", "line 18\nextra\n");
+    let change = FileChange {
+        name: "x.mm".to_owned(),
+        old: Some(old.into_bytes()),
+        new: new.into_bytes(),
+        in_memory: false,
+    };
+    assert_eq!(
+        change.unified_diff(),
+        "--- x.mm\n+++ x.mm\n\
+         @@ -1,7 +1,6 @@\n line 1\n-line 2\n+line two\n line 3\n-line 4\n line 5\n line 6\n line 7\n\
+         @@ -16,5 +15,6 @@\n line 16\n line 17\n line 18\n+extra\n line 19\n line 20\n"
+    );
+}